@@ -0,0 +1 @@
+/* local placeholder, not htmx */
@@ -2,25 +2,26 @@ use std::process::Output;
 
 fn main() {
     println!("cargo:rerun-if-changed=src/ui/");
+    println!("cargo:rerun-if-changed=assets/htmx.min.js");
+
+    vendor_htmx();
 
     let dir: String = std::env::var("CARGO_MANIFEST_DIR").unwrap();
     let input = format!("{dir}/src/ui/style.css");
     let output = format!("{dir}/assets/style.css");
 
-    let result: Output;
-
-    if cfg!(windows) {
+    let result: Output = if cfg!(windows) {
         let cmd = format!("tailwindcss --yes -i {} -o {}", input, output);
-        result = std::process::Command::new("cmd")
+        std::process::Command::new("cmd")
             .args(["/C", &cmd])
             .output()
-            .expect("Unable to generate css");
+            .expect("Unable to generate css")
     } else {
-        result = std::process::Command::new("npx")
+        std::process::Command::new("npx")
             .args(["--yes", "tailwindcss", "-i", &input, "-o", &output])
             .output()
-            .expect("Unable to generate css");
-    }
+            .expect("Unable to generate css")
+    };
 
     if !result.status.success() {
         let error = String::from_utf8_lossy(&result.stderr);
@@ -28,3 +29,18 @@ fn main() {
         println!("cargo:warning=Output: {error}");
     }
 }
+
+/// Copies the vendored htmx somewhere the server can embed it from. There's
+/// no fallback: a build without it would serve pages that can't do anything.
+fn vendor_htmx() {
+    let dir: String = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir: String = std::env::var("OUT_DIR").unwrap();
+
+    let htmx = std::fs::read(format!("{dir}/assets/htmx.min.js")).unwrap_or_else(|e| {
+        panic!(
+            "Unable to read assets/htmx.min.js ({e}), download it with \
+             `curl -L -o assets/htmx.min.js https://unpkg.com/htmx.org@1.9.11/dist/htmx.min.js`"
+        )
+    });
+    std::fs::write(format!("{out_dir}/htmx.min.js"), htmx).expect("Unable to write htmx");
+}
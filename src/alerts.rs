@@ -0,0 +1,459 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use lettre::{Address, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{watch, Mutex},
+    task::JoinHandle,
+};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    api::OctopusClient,
+    compare::{compare_tariffs, CompareRequest, Comparison},
+    reports::ReportStore,
+    tariff::TariffDefinition,
+};
+
+/// How long between re-running a subscription's comparison.
+const RERUN_AFTER: chrono::Duration = chrono::Duration::days(30);
+
+/// Longest a scheduled comparison (and sending its alert) gets, so one that
+/// hangs doesn't hold up everyone else's.
+const RERUN_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// How long a webhook gets to answer.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An account that's opted in to having its comparison re-run every month,
+/// and hearing about it when another tariff would have been cheaper. Unlike
+/// reports these have to keep the API key, so they live in their own file
+/// that only the server's user can read.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Subscription {
+    pub id: String,
+    pub api_key: String,
+    pub account_number: String,
+    pub property_id: f64,
+    pub fill_gaps: bool,
+    /// Pounds the best alternative has to beat the current tariff by over the
+    /// period compared
+    pub threshold: f64,
+    pub webhook: Option<String>,
+    pub email: Option<String>,
+    pub last_run: DateTime<Utc>,
+}
+
+impl Subscription {
+    pub fn next_run(&self) -> DateTime<Utc> {
+        self.last_run + RERUN_AFTER
+    }
+
+    fn is_due(&self, now: DateTime<Utc>) -> bool {
+        now >= self.next_run()
+    }
+}
+
+/// Subscriptions, kept in a single JSON file.
+#[derive(Clone)]
+pub struct SubscriptionStore {
+    path: PathBuf,
+    lock: Arc<Mutex<()>>,
+}
+
+impl SubscriptionStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    async fn read(&self) -> Result<Vec<Subscription>> {
+        match tokio::fs::read(&self.path).await {
+            Ok(json) => Ok(serde_json::from_slice(&json)
+                .with_context(|| format!("Unable to parse {:?}", self.path))?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(e).with_context(|| format!("Unable to read {:?}", self.path)),
+        }
+    }
+
+    /// Writes the file readable by the server's user only, as it's full of
+    /// API keys.
+    async fn write(&self, subscriptions: &[Subscription]) -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        if let Some(dir) = self.path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let write = async {
+            let mut file = options.open(&self.path).await?;
+            // `mode` only applies to new files, so tighten up older ones too
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                file.set_permissions(std::fs::Permissions::from_mode(0o600))
+                    .await?;
+            }
+            file.write_all(&serde_json::to_vec_pretty(subscriptions)?)
+                .await?;
+            file.flush().await?;
+            anyhow::Ok(())
+        };
+        write
+            .await
+            .with_context(|| format!("Unable to write {:?}", self.path))
+    }
+
+    pub async fn all(&self) -> Result<Vec<Subscription>> {
+        let _guard = self.lock.lock().await;
+        self.read().await
+    }
+
+    pub async fn add(&self, subscription: Subscription) -> Result<()> {
+        let _guard = self.lock.lock().await;
+        let mut subscriptions = self.read().await?;
+        info!(
+            "Subscribing {} property {} to monthly comparisons",
+            subscription.account_number, subscription.property_id
+        );
+        subscriptions.push(subscription);
+        self.write(&subscriptions).await
+    }
+
+    /// Removes a subscription, returning whether there was one to remove.
+    pub async fn remove(&self, id: &str) -> Result<bool> {
+        let _guard = self.lock.lock().await;
+        let mut subscriptions = self.read().await?;
+        let before = subscriptions.len();
+        subscriptions.retain(|s| s.id != id);
+        self.write(&subscriptions).await?;
+        Ok(subscriptions.len() < before)
+    }
+
+    async fn mark_run(&self, id: &str, at: DateTime<Utc>) -> Result<()> {
+        let _guard = self.lock.lock().await;
+        let mut subscriptions = self.read().await?;
+        if let Some(s) = subscriptions.iter_mut().find(|s| s.id == id) {
+            s.last_run = at;
+        }
+        self.write(&subscriptions).await
+    }
+}
+
+pub fn new_subscription_id() -> String {
+    Uuid::new_v4().to_string()
+}
+
+/// A local SMTP relay to send alert emails through. No TLS or auth, the relay
+/// is expected to deal with getting mail out.
+#[derive(Debug, Clone)]
+pub struct SmtpRelay {
+    pub host: String,
+    pub port: u16,
+    pub from: String,
+}
+
+/// What gets POSTed to a webhook when there's a cheaper tariff.
+#[derive(Debug, Serialize)]
+pub struct Alert {
+    pub address: String,
+    pub current_tariff: String,
+    /// Pounds over the period compared
+    pub current_cost: f64,
+    pub best_tariff: String,
+    pub best_cost: f64,
+    pub saving: f64,
+    pub report: Option<String>,
+}
+
+impl Alert {
+    /// An alert if the cheapest tariff beats the current one by more than
+    /// `threshold` pounds.
+    pub fn check(comparison: &Comparison, threshold: f64) -> Option<Self> {
+        let current = comparison.current()?;
+        let best = comparison.cheapest()?;
+        let saving = (current.total() - best.total()) / 100.0;
+        if saving <= threshold {
+            return None;
+        }
+
+        Some(Self {
+            address: comparison.address.clone(),
+            current_tariff: current.display_name.clone(),
+            current_cost: current.total() / 100.0,
+            best_tariff: best.display_name.clone(),
+            best_cost: best.total() / 100.0,
+            saving,
+            report: None,
+        })
+    }
+
+    fn message(&self) -> String {
+        let mut message = format!(
+            "{} would have saved £{:.2} on {} compared to {} (£{:.2} vs £{:.2}).",
+            self.best_tariff,
+            self.saving,
+            self.address,
+            self.current_tariff,
+            self.best_cost,
+            self.current_cost
+        );
+        if let Some(report) = &self.report {
+            message += &format!("\n\nThe full comparison is at {}", report);
+        }
+        message
+    }
+}
+
+/// Whether an address is out on the internet, rather than this machine, the
+/// network it's on or anything else a webhook has no business reaching.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || a == 0
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && (64..128).contains(&b))
+                // Reserved, 240.0.0.0/4
+                || a >= 240)
+        }
+        // IPv4-mapped (::ffff:a.b.c.d) and the old IPv4-compatible
+        // (::a.b.c.d) forms are only as public as the address they carry
+        IpAddr::V6(ip) => match ip.to_ipv4() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let [first, second, ..] = ip.segments();
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // Unique local, fc00::/7
+                    || (first & 0xfe00) == 0xfc00
+                    // Link local, fe80::/10
+                    || (first & 0xffc0) == 0xfe80
+                    // NAT64, 64:ff9b::/96 and the local use 64:ff9b:1::/48,
+                    // which reach whatever IPv4 address they carry
+                    || (first == 0x64 && second == 0xff9b)
+                    // 6to4, 2002::/16, the same
+                    || first == 0x2002
+                    // Documentation, 2001:db8::/32
+                    || (first == 0x2001 && second == 0x0db8))
+            }
+        },
+    }
+}
+
+/// Checks an email address is one alerts can be sent to, so a typo turns up
+/// when subscribing rather than when the first alert goes out.
+pub fn check_email(email: &str) -> Result<()> {
+    email
+        .parse::<Address>()
+        .with_context(|| format!("{} isn't an email address", email))?;
+    Ok(())
+}
+
+/// Checks a webhook is somewhere it's safe for the server to POST to: HTTPS,
+/// and only resolving to public addresses. Gives back the host and an address
+/// to pin it to, so it can't resolve somewhere else by the time it's called.
+pub async fn check_webhook(url: &str) -> Result<(reqwest::Url, String, SocketAddr)> {
+    let url = reqwest::Url::parse(url).with_context(|| format!("{} isn't a URL", url))?;
+    if url.scheme() != "https" {
+        bail!("Webhooks have to be https:// URLs");
+    }
+    let Some(host) = url.host_str().map(|h| h.to_owned()) else {
+        bail!("{} doesn't have a host", url);
+    };
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host.trim_matches(['[', ']']), port))
+        .await
+        .with_context(|| format!("Unable to look up {}", host))?
+        .collect();
+    match addresses.first() {
+        None => bail!("{} doesn't resolve to anything", host),
+        Some(_) if addresses.iter().any(|a| !is_public(a.ip())) => {
+            bail!("{} isn't a public address", host)
+        }
+        Some(address) => Ok((url, host, *address)),
+    }
+}
+
+async fn send_webhook(url: &str, alert: &Alert) -> Result<()> {
+    let (url, host, address) = check_webhook(url).await?;
+    reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .resolve(&host, address)
+        // A redirect could go anywhere, including somewhere private
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?
+        .post(url)
+        .json(alert)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+async fn send_email(relay: &SmtpRelay, to: &str, alert: &Alert) -> Result<()> {
+    let email = Message::builder()
+        .from(relay.from.parse()?)
+        .to(to.parse()?)
+        .subject(format!(
+            "OctoCompare: {} could save you £{:.2}",
+            alert.best_tariff, alert.saving
+        ))
+        .body(alert.message())?;
+
+    AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&relay.host)
+        .port(relay.port)
+        .build()
+        .send(email)
+        .await?;
+    Ok(())
+}
+
+/// Everything the scheduler needs to re-run comparisons and send alerts.
+#[derive(Clone)]
+pub struct Scheduler {
+    pub subscriptions: SubscriptionStore,
+    pub reports: ReportStore,
+    pub definitions: Arc<Vec<TariffDefinition>>,
+    pub smtp: Option<SmtpRelay>,
+    pub client: OctopusClient,
+    pub products: Vec<String>,
+    /// Prefixed to report paths in alerts, e.g. `https://octocompare.example`
+    pub base_url: String,
+}
+
+/// A running [`Scheduler`].
+pub struct SchedulerHandle {
+    stop: watch::Sender<bool>,
+    task: JoinHandle<()>,
+}
+
+impl SchedulerHandle {
+    /// Tells the scheduler to stop and waits up to `grace` for the comparison
+    /// it's in the middle of. Returns false if it was still going.
+    pub async fn stop(self, grace: Duration) -> bool {
+        let _ = self.stop.send(true);
+        let mut task = self.task;
+        match tokio::time::timeout(grace, &mut task).await {
+            Ok(_) => true,
+            Err(_) => {
+                task.abort();
+                false
+            }
+        }
+    }
+}
+
+impl Scheduler {
+    /// Starts checking for subscriptions that are due every `check_every`,
+    /// until the returned handle is stopped.
+    pub fn spawn(self, check_every: Duration) -> SchedulerHandle {
+        let (stop, stopped) = watch::channel(false);
+        let task = tokio::spawn(self.run(check_every, stopped));
+        SchedulerHandle { stop, task }
+    }
+
+    async fn run(self, check_every: Duration, mut stopped: watch::Receiver<bool>) {
+        let mut interval = tokio::time::interval(check_every);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = stopped.changed() => return,
+            }
+            let subscriptions = match self.subscriptions.all().await {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("Unable to load subscriptions: {:#}", e);
+                    continue;
+                }
+            };
+
+            let now = chrono::offset::Utc::now();
+            for subscription in subscriptions.iter().filter(|s| s.is_due(now)) {
+                // Finish the run in hand but don't start another, the rest
+                // will still be due after the restart
+                if *stopped.borrow() {
+                    return;
+                }
+                match tokio::time::timeout(RERUN_TIMEOUT, self.rerun(subscription)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => error!(
+                        "Scheduled comparison for {} failed: {:#}",
+                        subscription.account_number, e
+                    ),
+                    Err(_) => warn!(
+                        "Scheduled comparison for {} gave up after {:?}",
+                        subscription.account_number, RERUN_TIMEOUT
+                    ),
+                }
+                // A failed run still waits a month, rather than hammering the
+                // API every hour with a key that's been revoked
+                if let Err(e) = self.subscriptions.mark_run(&subscription.id, now).await {
+                    error!("Unable to update subscription {}: {:#}", subscription.id, e);
+                }
+            }
+        }
+    }
+
+    async fn rerun(&self, subscription: &Subscription) -> Result<()> {
+        info!(
+            "Re-running comparison for {} property {}",
+            subscription.account_number, subscription.property_id
+        );
+        let request = CompareRequest {
+            api_key: subscription.api_key.clone(),
+            account_number: subscription.account_number.clone(),
+            property_id: subscription.property_id,
+            ev: None,
+            definitions: self.definitions.to_vec(),
+            fill_gaps: subscription.fill_gaps,
+            products: self.products.clone(),
+        };
+        let comparison = compare_tariffs(&self.client.session(), &request, &()).await?;
+
+        let Some(mut alert) = Alert::check(&comparison, subscription.threshold) else {
+            info!("No tariff beats the current one by enough to alert");
+            return Ok(());
+        };
+        let report = self.reports.save(comparison).await?;
+        alert.report = Some(format!("{}/reports/{}", self.base_url, report.id));
+        self.send(subscription, &alert).await
+    }
+
+    async fn send(&self, subscription: &Subscription, alert: &Alert) -> Result<()> {
+        if let Some(url) = &subscription.webhook {
+            info!("Sending alert to webhook {}", url);
+            send_webhook(url, alert).await?;
+        }
+        if let Some(to) = &subscription.email {
+            let Some(relay) = &self.smtp else {
+                bail!("Can't email {} as no SMTP relay is configured", to);
+            };
+            info!("Emailing alert to {}", to);
+            send_email(relay, to, alert).await?;
+        }
+        Ok(())
+    }
+}
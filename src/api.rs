@@ -1,22 +1,64 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
 use anyhow::{bail, Result};
 use base64::prelude::*;
-use chrono::{DateTime, Days, Utc};
+use chrono::{DateTime, Days, SecondsFormat, Utc};
 use regex::Regex;
-use serde::{Deserialize, Serialize};
-use tracing::{error, info};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::{
+    fs,
+    sync::{OnceCell, Semaphore},
+};
+use tracing::{error, info, trace, warn};
+
+use crate::{error::UserError, metrics, pricing::Granularity};
 
 // {"consumption":0.0,"interval_start":"2024-01-16T23:00:00Z","interval_end":"2024-01-16T23:30:00Z"}
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ConsumptionDatum {
     pub consumption: f64,
     pub interval_start: DateTime<Utc>,
     pub interval_end: DateTime<Utc>,
 }
 
+/// A window to fetch data for. Either end can be left open.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Period {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl Period {
+    /// When the period ends, or now if it hasn't yet.
+    pub fn end(&self) -> DateTime<Utc> {
+        self.to.unwrap_or_else(chrono::offset::Utc::now)
+    }
+
+    fn query(&self) -> String {
+        let from = self.from.map(|x| {
+            format!(
+                "period_from={}",
+                x.to_rfc3339_opts(SecondsFormat::Secs, true)
+            )
+        });
+        let to = self
+            .to
+            .map(|x| format!("period_to={}", x.to_rfc3339_opts(SecondsFormat::Secs, true)));
+        from.into_iter().chain(to).collect::<Vec<_>>().join("&")
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ConsumptionResponse {
     pub results: Vec<ConsumptionDatum>,
     pub count: i64,
+    pub next: Option<String>,
 }
 
 // Urls:
@@ -45,6 +87,17 @@ pub struct AccountProperty {
     pub gas_meter_points: Vec<GasMeterPoint>,
 }
 
+impl AccountProperty {
+    /// When the account lived at the property. Readings from before they
+    /// moved in (or after they moved out) belong to someone else.
+    pub fn tenancy(&self) -> Period {
+        Period {
+            from: Some(self.moved_in_at),
+            to: self.moved_out_at,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ElectricityMeterPoint {
     pub mpan: String,
@@ -75,29 +128,13 @@ pub struct Agreement {
     pub valid_to: DateTime<Utc>,
 }
 
-pub async fn get_account_details(api_key: &str, account_number: &str) -> Result<AccountResponse> {
-    let uri = format!("https://api.octopus.energy/v1/accounts/{}", account_number);
-
-    info!("Calling account API for account number {}", account_number);
-    let b64 = BASE64_STANDARD.encode(api_key.as_bytes());
-    let client = reqwest::Client::new();
-    let body = client
-        .get(&uri)
-        .header("Authorization", "Basic ".to_owned() + &b64 + ":")
-        .send()
-        .await?;
-
-    if body.status().as_u16() != 200 {
-        let resp = body.text().await?;
-        error!("Account response failed: {}", resp);
-        bail!("Unexpected error from API. Check account details and try again.");
-    } else {
-        info!(
-            "Received account API response for account {}",
-            account_number
-        );
-        Ok(body.json::<AccountResponse>().await?)
-    }
+/// The agreement in effect at `at`, for electricity or gas. Past tenancies end
+/// on the last day of their final agreement, so look a second before the end.
+pub fn agreement_at(agreements: &[Agreement], at: DateTime<Utc>) -> Option<&Agreement> {
+    let at = at - chrono::Duration::seconds(1);
+    agreements
+        .iter()
+        .find(|a| a.valid_from <= at && a.valid_to >= at)
 }
 
 pub enum MeterInfo {
@@ -105,41 +142,6 @@ pub enum MeterInfo {
     Gas(String, String),
 }
 
-pub async fn get_consumption_data(
-    api_key: &str,
-    meter_info: MeterInfo,
-) -> Result<ConsumptionResponse> {
-    let page_size = 25000; // 25000
-    let uri = match meter_info {
-        MeterInfo::Electricity(serial_number, mpan) => format!(
-            "https://api.octopus.energy/v1/electricity-meter-points/{}/meters/{}/consumption?page_size={}",
-            mpan, serial_number, page_size
-        ),
-        MeterInfo::Gas(serial_number, mprn) => format!(
-            "https://api.octopus.energy/v1/gas-meter-points/{}/meters/{}/consumption?page_size={}",
-            mprn, serial_number, page_size
-        ),
-    };
-
-    info!("Calling consumption API {}", uri);
-    let b64 = BASE64_STANDARD.encode(api_key.as_bytes());
-    let client = reqwest::Client::new();
-    let body = client
-        .get(&uri)
-        .header("Authorization", "Basic ".to_owned() + &b64 + ":")
-        .send()
-        .await?;
-
-    if body.status().as_u16() != 200 {
-        let resp = body.text().await?;
-        error!("Consumption endpoint {} failed: {}", uri, resp);
-        bail!("Unexpected error from API. Check account details and try again.");
-    } else {
-        info!("Received consumption API response for {}", uri);
-        Ok(body.json::<ConsumptionResponse>().await?)
-    }
-}
-
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ProductsReponse {
     pub results: Vec<ProductSummary>,
@@ -175,28 +177,57 @@ pub struct Link {
     method: String,
 }
 
-// This doesn't seem to be overly useful as some beta products, like Octopus Tracker aren't included.
-pub async fn get_products() -> Result<ProductsReponse> {
-    let uri = format!("https://api.octopus.energy/v1/products");
+// {"code":"SILVER-24-10-01","display_name":"Octopus Tracker","is_tracker":true,...,"single_register_electricity_tariffs":{"_A":{"direct_debit_monthly":{"code":"E-1R-SILVER-24-10-01-A",...}}}}
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProductDetail {
+    pub code: String,
+    pub full_name: String,
+    pub display_name: String,
+    pub is_tracker: bool,
+    pub is_variable: bool,
+    #[serde(default)]
+    pub single_register_electricity_tariffs: HashMap<String, HashMap<String, TariffSummary>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TariffSummary {
+    pub code: String,
+    /// Pence, for leaving before the end of a fixed term
+    #[serde(default)]
+    pub exit_fees_inc_vat: f64,
+}
 
-    info!("Calling Products API");
-    let client = reqwest::Client::new();
-    let body = client.get(&uri).send().await?;
+impl ProductDetail {
+    /// The single-rate electricity tariff code for a region, preferring the
+    /// monthly direct debit version when there's a choice.
+    pub fn electricity_tariff_code(&self, region: char) -> Option<&str> {
+        let tariffs = self
+            .single_register_electricity_tariffs
+            .get(&format!("_{}", region))?;
+        tariffs
+            .get("direct_debit_monthly")
+            .or_else(|| tariffs.values().next())
+            .map(|t| t.code.as_str())
+    }
 
-    if body.status().as_u16() != 200 {
-        let resp = body.text().await?;
-        error!("Products response failed: {}", resp);
-        bail!("Unexpected error from API.");
-    } else {
-        info!("Received products API response");
-        Ok(body.json::<ProductsReponse>().await?)
+    /// What leaving one of this product's electricity tariffs early costs,
+    /// in pence, if anything.
+    pub fn exit_fee(&self, tariff_code: &str) -> Option<f64> {
+        self.single_register_electricity_tariffs
+            .values()
+            .flat_map(|tariffs| tariffs.values())
+            .find(|t| t.code == tariff_code)
+            .map(|t| t.exit_fees_inc_vat)
+            .filter(|fee| *fee > 0.0)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct TariffPricing {
     pub tariff_code: String,
     pub product_code: String,
+    pub display_name: String,
+    pub granularity: Granularity,
     pub standing_charges: Vec<PricingDatum>,
     pub unit_charges: Vec<PricingDatum>,
 }
@@ -217,70 +248,501 @@ pub struct PricingDatum {
     pub valid_to: Option<DateTime<Utc>>,
 }
 
-pub async fn get_pricing(tariff_code: &str) -> Result<TariffPricing> {
-    let product_code_pattern = Regex::new("-([A-Za-z]+-\\d{2}-\\d{2}-\\d{2})-").unwrap();
-    let product_code = product_code_pattern
-        .captures(tariff_code)
-        .expect("Tariff code should conform to Octopus convention")
-        .get(1)
-        .unwrap()
-        .as_str();
-
-    let start_from = chrono::offset::Utc::now()
-        .checked_sub_days(Days::new(365))
-        .unwrap()
-        .to_rfc3339();
-    let uri = format!(
-        "https://api.octopus.energy/v1/products/{}/electricity-tariffs/{}/standing-charges?start_from={}",
-        product_code, tariff_code, start_from
-    );
+const MAX_CONCURRENT_REQUESTS: usize = 4;
 
-    let sc = get_pricing_response(&uri).await?;
+/// How long fetched products and prices are reused for. Agile prices for the
+/// next day come out in the afternoon, so an hour old is close enough.
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
 
-    // GET /v1/products/{product_code}/electricity-tariffs/{tariff_code}/standard-unit-rates/
-    // GET /v1/products/{product_code}/electricity-tariffs/{tariff_code}/day-unit-rates/
-    // GET /v1/products/{product_code}/electricity-tariffs/{tariff_code}/night-unit-rates/
-    let sur = format!(
-        "https://api.octopus.energy/v1/products/{}/electricity-tariffs/{}/standard-unit-rates?start_from={}&page_size=1500",
-        product_code, tariff_code, start_from
-    );
-    let _dur = format!(
-        "https://api.octopus.energy/v1/products/{}/electricity-tariffs/{}/day-unit-rates",
-        product_code, tariff_code
-    );
-    let _nur = format!(
-        "https://api.octopus.energy/v1/products/{}/electricity-tariffs/{}/night-unit-rates",
-        product_code, tariff_code
-    );
+pub const DEFAULT_BASE_URL: &str = "https://api.octopus.energy/v1";
 
-    let su = get_pricing_response(&sur);
-    //let du = get_pricing_response(&dur);
-    //let nu = get_pricing_response(&nur);
+/// Where the Octopus API lives and how long to give it. The server builds one
+/// client from these and keeps it, so its caches are shared by everyone.
+#[derive(Debug, Clone)]
+pub struct ClientSettings {
+    pub base_url: String,
+    pub timeout: std::time::Duration,
+    /// Where products and prices are kept so a restart doesn't fetch them
+    /// all again. Only kept in memory if not set.
+    pub cache_dir: Option<PathBuf>,
+}
 
-    let r = su.await?;
+impl Default for ClientSettings {
+    fn default() -> Self {
+        Self {
+            base_url: DEFAULT_BASE_URL.to_owned(),
+            timeout: std::time::Duration::from_secs(30),
+            cache_dir: None,
+        }
+    }
+}
 
-    // r.append(&mut du.await?.results);
-    // r.append(&mut nu.await?.results);
+impl ClientSettings {
+    pub fn client(&self) -> OctopusClient {
+        OctopusClient {
+            client: reqwest::Client::builder()
+                .timeout(self.timeout)
+                .build()
+                .expect("Unable to build HTTP client"),
+            base_url: self.base_url.trim_end_matches('/').to_owned(),
+            cache_dir: self.cache_dir.clone(),
+            permits: Arc::new(Semaphore::new(MAX_CONCURRENT_REQUESTS)),
+            pricing_cache: Default::default(),
+            product_cache: Default::default(),
+        }
+    }
+}
 
-    Ok(TariffPricing {
-        tariff_code: tariff_code.to_owned(),
-        product_code: product_code.to_owned(),
-        standing_charges: sc.results,
-        unit_charges: r.results,
-    })
+type Cache<T> = Arc<Mutex<HashMap<String, (Instant, Arc<OnceCell<Arc<T>>>)>>>;
+
+/// Talks to the Octopus REST API. Cloning is cheap and clones share the same
+/// connection pool, concurrency limit and product/pricing caches. Use
+/// [`OctopusClient::session`] for each comparison so they don't queue behind
+/// each other's requests.
+#[derive(Clone)]
+pub struct OctopusClient {
+    client: reqwest::Client,
+    base_url: String,
+    cache_dir: Option<PathBuf>,
+    permits: Arc<Semaphore>,
+    pricing_cache: Cache<TariffPricing>,
+    product_cache: Cache<ProductDetail>,
 }
 
-async fn get_pricing_response(uri: &str) -> Result<PricingResponse> {
-    info!("Calling {}", uri);
-    let client = reqwest::Client::new();
-    let body = client.get(uri).send().await?;
+impl Default for OctopusClient {
+    fn default() -> Self {
+        ClientSettings::default().client()
+    }
+}
+
+impl OctopusClient {
+    /// A client sharing this one's connection pool and caches, but with its
+    /// own limit on concurrent requests.
+    pub fn session(&self) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(MAX_CONCURRENT_REQUESTS)),
+            ..self.clone()
+        }
+    }
+
+    /// `endpoint` names the kind of call for metrics, since URIs are full
+    /// of account numbers and tariff codes.
+    async fn send(
+        &self,
+        endpoint: &'static str,
+        uri: &str,
+        api_key: Option<&str>,
+    ) -> Result<reqwest::Response> {
+        let _permit = self.permits.acquire().await?;
+        let mut request = self.client.get(uri);
+        if let Some(api_key) = api_key {
+            let b64 = BASE64_STANDARD.encode(api_key.as_bytes());
+            request = request.header("Authorization", "Basic ".to_owned() + &b64 + ":");
+        }
+
+        let started = Instant::now();
+        let response = request.send().await;
+        let status = match &response {
+            Ok(response) => response.status().as_str().to_owned(),
+            Err(_) => "error".to_owned(),
+        };
+        trace!(
+            target: metrics::TARGET,
+            metric = "octopus_request",
+            endpoint,
+            status,
+            seconds = started.elapsed().as_secs_f64()
+        );
+        match response {
+            Ok(response) => Ok(response),
+            Err(e) if e.is_timeout() => {
+                error!("Call to {} timed out: {}", endpoint, e);
+                bail!(UserError::TimedOut(
+                    "Octopus took too long to answer, try again in a bit.".to_owned()
+                ))
+            }
+            Err(e) => {
+                error!("Call to {} failed: {}", endpoint, e);
+                bail!(UserError::Upstream(
+                    "We couldn't reach Octopus, try again in a bit.".to_owned()
+                ))
+            }
+        }
+    }
+
+    pub async fn get_account_details(
+        &self,
+        api_key: &str,
+        account_number: &str,
+    ) -> Result<AccountResponse> {
+        let uri = format!("{}/accounts/{}", self.base_url, account_number);
+
+        info!("Calling account API for account number {}", account_number);
+        let body = self.send("accounts", &uri, Some(api_key)).await?;
+
+        let status = body.status().as_u16();
+        if status != 200 {
+            let resp = body.text().await?;
+            error!("Account response failed: {}", resp);
+            // Octopus says these when the account number or key is wrong
+            if matches!(status, 400 | 401 | 403 | 404) {
+                bail!(UserError::BadRequest(
+                    "Octopus didn't recognise that account number and API key.".to_owned()
+                ));
+            }
+            bail!(UserError::Upstream(
+                "Unexpected error from API. Check account details and try again.".to_owned()
+            ));
+        } else {
+            info!(
+                "Received account API response for account {}",
+                account_number
+            );
+            Ok(body.json::<AccountResponse>().await?)
+        }
+    }
+
+    pub async fn get_consumption_data(
+        &self,
+        api_key: &str,
+        meter_info: MeterInfo,
+        period: &Period,
+        on_page: impl Fn(usize),
+    ) -> Result<ConsumptionResponse> {
+        let page_size = 25000; // 25000
+        let mut uri = match meter_info {
+            MeterInfo::Electricity(serial_number, mpan) => format!(
+                "{}/electricity-meter-points/{}/meters/{}/consumption?page_size={}&{}",
+                self.base_url,
+                mpan,
+                serial_number,
+                page_size,
+                period.query()
+            ),
+            MeterInfo::Gas(serial_number, mprn) => format!(
+                "{}/gas-meter-points/{}/meters/{}/consumption?page_size={}&{}",
+                self.base_url,
+                mprn,
+                serial_number,
+                page_size,
+                period.query()
+            ),
+        };
+
+        let mut page = 1;
+        let mut consumption = ConsumptionResponse {
+            results: vec![],
+            count: 0,
+            next: None,
+        };
+
+        loop {
+            on_page(page);
+            info!("Calling consumption API {}", uri);
+            let body = self.send("consumption", &uri, Some(api_key)).await?;
+
+            if body.status().as_u16() != 200 {
+                let resp = body.text().await?;
+                error!("Consumption endpoint {} failed: {}", uri, resp);
+                bail!(UserError::Upstream(
+                    "Unexpected error from API. Check account details and try again.".to_owned()
+                ));
+            }
+
+            info!("Received consumption API response for {}", uri);
+            let mut response = body.json::<ConsumptionResponse>().await?;
+            consumption.count = response.count;
+            consumption.results.append(&mut response.results);
+
+            match response.next {
+                Some(next) => {
+                    uri = next;
+                    page += 1;
+                }
+                None => return Ok(consumption),
+            }
+        }
+    }
+
+    // This doesn't seem to be overly useful as some beta products, like Octopus Tracker aren't included.
+    pub async fn get_products(&self) -> Result<ProductsReponse> {
+        let uri = format!("{}/products", self.base_url);
+
+        info!("Calling Products API");
+        let body = self.send("products", &uri, None).await?;
+
+        if body.status().as_u16() != 200 {
+            let resp = body.text().await?;
+            error!("Products response failed: {}", resp);
+            bail!(UserError::Upstream("Unexpected error from API.".to_owned()));
+        } else {
+            info!("Received products API response");
+            Ok(body.json::<ProductsReponse>().await?)
+        }
+    }
+
+    /// Fetches standing charges and unit rates for a tariff. Tariffs that have
+    /// already been fetched (or are being fetched) by this client are shared
+    /// rather than requested again.
+    pub async fn get_pricing(&self, tariff_code: &str) -> Result<Arc<TariffPricing>> {
+        let start_from = chrono::offset::Utc::now()
+            .checked_sub_days(Days::new(365))
+            .unwrap()
+            .to_rfc3339();
+        let query = format!("start_from={}", start_from);
+        cached(
+            "pricing",
+            &self.pricing_cache,
+            self.cache_dir.as_deref(),
+            tariff_code,
+            || self.fetch_pricing(tariff_code, &query),
+        )
+        .await
+    }
+
+    /// Like `get_pricing`, but for a fixed window such as a past tenancy.
+    pub async fn get_pricing_during(
+        &self,
+        tariff_code: &str,
+        period: &Period,
+    ) -> Result<Arc<TariffPricing>> {
+        let query = period.query();
+        cached(
+            "pricing",
+            &self.pricing_cache,
+            self.cache_dir.as_deref(),
+            &format!("{}?{}", tariff_code, query),
+            || self.fetch_pricing(tariff_code, &query),
+        )
+        .await
+    }
+
+    /// Fetches only the prices in effect between two instants, e.g. the Agile
+    /// rates published for tomorrow.
+    pub async fn get_pricing_between(
+        &self,
+        tariff_code: &str,
+        period_from: DateTime<Utc>,
+        period_to: DateTime<Utc>,
+    ) -> Result<TariffPricing> {
+        let period = Period {
+            from: Some(period_from),
+            to: Some(period_to),
+        };
+        self.fetch_pricing(tariff_code, &period.query()).await
+    }
 
-    if body.status().as_u16() != 200 {
-        let resp = body.text().await?;
-        error!("Call to {} failed: {}", uri, resp);
-        bail!("Unexpected error from API.");
-    } else {
-        info!("Received response from {}", uri);
-        Ok(body.json::<PricingResponse>().await?)
+    /// Fetches a product, including the tariff codes it has in each region.
+    pub async fn get_product(&self, product_code: &str) -> Result<Arc<ProductDetail>> {
+        let cache_dir = self.cache_dir.as_deref();
+        cached(
+            "product",
+            &self.product_cache,
+            cache_dir,
+            product_code,
+            || async {
+                let uri = format!("{}/products/{}/", self.base_url, product_code);
+
+                info!("Calling product API for {}", product_code);
+                let body = self.send("product", &uri, None).await?;
+
+                if body.status().as_u16() != 200 {
+                    let resp = body.text().await?;
+                    error!("Product response for {} failed: {}", product_code, resp);
+                    bail!(UserError::Upstream("Unexpected error from API.".to_owned()));
+                } else {
+                    info!("Received product API response for {}", product_code);
+                    Ok(body.json::<ProductDetail>().await?)
+                }
+            },
+        )
+        .await
     }
+
+    async fn fetch_pricing(&self, tariff_code: &str, query: &str) -> Result<TariffPricing> {
+        let Some(product_code) = product_code(tariff_code) else {
+            bail!(
+                "Tariff code {} doesn't look like an Octopus tariff.",
+                tariff_code
+            );
+        };
+
+        // Gas tariffs are priced the same way, just under a different path
+        let tariffs = match tariff_code.starts_with("G-") {
+            true => "gas-tariffs",
+            false => "electricity-tariffs",
+        };
+        let scr = format!(
+            "{}/products/{}/{}/{}/standing-charges?{}",
+            self.base_url, product_code, tariffs, tariff_code, query
+        );
+
+        // GET /v1/products/{product_code}/electricity-tariffs/{tariff_code}/standard-unit-rates/
+        // GET /v1/products/{product_code}/electricity-tariffs/{tariff_code}/day-unit-rates/
+        // GET /v1/products/{product_code}/electricity-tariffs/{tariff_code}/night-unit-rates/
+        let sur = format!(
+            "{}/products/{}/{}/{}/standard-unit-rates?{}&page_size=1500",
+            self.base_url, product_code, tariffs, tariff_code, query
+        );
+
+        let (product, sc, su) = tokio::try_join!(
+            self.get_product(product_code),
+            self.get_pricing_response("standing-charges", &scr),
+            self.get_pricing_response("standard-unit-rates", &sur)
+        )?;
+
+        Ok(TariffPricing {
+            tariff_code: tariff_code.to_owned(),
+            product_code: product_code.to_owned(),
+            display_name: product.display_name.clone(),
+            // Tracker prices change once a day rather than every half hour
+            granularity: if product.is_tracker {
+                Granularity::Daily
+            } else {
+                Granularity::HalfHourly
+            },
+            standing_charges: sc,
+            unit_charges: su,
+        })
+    }
+
+    async fn get_pricing_response(
+        &self,
+        endpoint: &'static str,
+        uri: &str,
+    ) -> Result<Vec<PricingDatum>> {
+        let mut uri = uri.to_owned();
+        let mut results = vec![];
+
+        loop {
+            info!("Calling {}", uri);
+            let body = self.send(endpoint, &uri, None).await?;
+
+            if body.status().as_u16() != 200 {
+                let resp = body.text().await?;
+                error!("Call to {} failed: {}", uri, resp);
+                bail!(UserError::Upstream("Unexpected error from API.".to_owned()));
+            }
+
+            info!("Received response from {}", uri);
+            let mut response = body.json::<PricingResponse>().await?;
+            results.append(&mut response.results);
+
+            match response.next {
+                Some(next) => uri = next,
+                None => return Ok(results),
+            }
+        }
+    }
+}
+
+/// Looks `key` up in memory, then in `dir` if there is one, before fetching
+/// it with `init`. Anything fetched is written back to `dir` for next time.
+async fn cached<T, F, Fut>(
+    name: &'static str,
+    cache: &Cache<T>,
+    dir: Option<&Path>,
+    key: &str,
+    init: F,
+) -> Result<Arc<T>>
+where
+    T: Serialize + DeserializeOwned,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let cell = {
+        let mut cache = cache.lock().unwrap();
+        cache.retain(|_, (fetched, _)| fetched.elapsed() < CACHE_TTL);
+        cache
+            .entry(key.to_owned())
+            .or_insert_with(|| (Instant::now(), Default::default()))
+            .1
+            .clone()
+    };
+    trace!(
+        target: metrics::TARGET,
+        metric = "cache_lookup",
+        cache = name,
+        hit = cell.initialized()
+    );
+
+    let value = cell
+        .get_or_try_init(|| async {
+            let path = dir.map(|dir| cache_file(dir, name, key));
+            let value = match read_cache_file(path.as_deref()).await {
+                Some(value) => value,
+                None => {
+                    let value = init().await?;
+                    if let Some(path) = &path {
+                        write_cache_file(path, &value).await;
+                    }
+                    value
+                }
+            };
+            anyhow::Ok(Arc::new(value))
+        })
+        .await?;
+    Ok(value.clone())
+}
+
+/// Keys are tariff codes, sometimes with a query on the end, so anything
+/// that isn't a letter, digit or dash becomes an underscore.
+fn cache_file(dir: &Path, name: &str, key: &str) -> PathBuf {
+    let key: String = key
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() || c == '-' {
+            true => c,
+            false => '_',
+        })
+        .collect();
+    dir.join(name).join(format!("{}.json", key))
+}
+
+/// A cached response, as long as it's no older than the in-memory ones are
+/// allowed to get. Files that can't be read are fetched again.
+async fn read_cache_file<T: DeserializeOwned>(path: Option<&Path>) -> Option<T> {
+    let path = path?;
+    let modified = fs::metadata(path).await.ok()?.modified().ok()?;
+    if modified.elapsed().ok()? >= CACHE_TTL {
+        return None;
+    }
+    let json = fs::read(path).await.ok()?;
+    match serde_json::from_slice(&json) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            warn!("Ignoring cached {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Failing to cache something isn't worth failing the request over.
+async fn write_cache_file<T: Serialize>(path: &Path, value: &T) {
+    let written = async {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        fs::write(path, serde_json::to_vec(value)?).await?;
+        anyhow::Ok(())
+    };
+    if let Err(e) = written.await {
+        warn!("Unable to cache {:?}: {:#}", path, e);
+    }
+}
+
+/// Pulls the product code out of a tariff code, e.g. `AGILE-24-10-01` from
+/// `E-1R-AGILE-24-10-01-C` or `AGILE-FLEX-22-11-25` from `E-1R-AGILE-FLEX-22-11-25-C`.
+pub fn product_code(tariff_code: &str) -> Option<&str> {
+    static PRODUCT_CODE: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new("-((?:[A-Za-z]+-)+\\d{2}-\\d{2}-\\d{2})-").unwrap());
+    Some(PRODUCT_CODE.captures(tariff_code)?.get(1)?.as_str())
+}
+
+/// The region letter on the end of a tariff code, e.g. `C` for London.
+pub fn region(tariff_code: &str) -> Option<char> {
+    tariff_code
+        .rsplit_once('-')
+        .filter(|(_, r)| r.len() == 1)
+        .and_then(|(_, r)| r.chars().next())
+        .filter(|r| r.is_ascii_uppercase())
 }
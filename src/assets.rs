@@ -0,0 +1,86 @@
+use std::sync::LazyLock;
+
+use axum::{
+    extract::Path,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+/// A file baked into the binary, so the server runs from anywhere.
+pub struct Asset {
+    pub name: &'static str,
+    pub content_type: &'static str,
+    pub body: &'static [u8],
+}
+
+impl Asset {
+    /// Hash of the contents, which changes whenever the file does.
+    pub fn etag(&self) -> &'static str {
+        let index = ASSETS.iter().position(|a| a.name == self.name).unwrap();
+        &ETAGS[index]
+    }
+}
+
+/// ETags for [`ASSETS`], in the same order. Worked out once, with a hash that
+/// stays the same across builds so browser caches survive an upgrade.
+static ETAGS: LazyLock<Vec<String>> = LazyLock::new(|| {
+    ASSETS
+        .iter()
+        .map(|asset| format!("\"{:016x}\"", fnv1a(asset.body)))
+        .collect()
+});
+
+/// 64-bit FNV-1a.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub static ASSETS: [Asset; 2] = [
+    Asset {
+        name: "style.css",
+        content_type: "text/css; charset=utf-8",
+        body: include_bytes!("../assets/style.css"),
+    },
+    Asset {
+        name: "htmx.min.js",
+        content_type: "text/javascript; charset=utf-8",
+        body: include_bytes!(concat!(env!("OUT_DIR"), "/htmx.min.js")),
+    },
+];
+
+/// Where to link to an asset. The ETag goes in the query string so browsers
+/// can cache it forever and still pick up a new build.
+pub fn asset_url(name: &str) -> String {
+    match ASSETS.iter().find(|a| a.name == name) {
+        Some(asset) => format!("/assets/{}?v={}", name, asset.etag().trim_matches('"')),
+        None => format!("/assets/{}", name),
+    }
+}
+
+pub async fn get_asset(Path(name): Path<String>, headers: HeaderMap) -> Response {
+    let Some(asset) = ASSETS.iter().find(|a| a.name == name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let etag = asset.etag();
+    let cache_headers = [
+        (header::ETAG, etag),
+        (header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+    ];
+    let matches = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|tag| tag.trim() == etag));
+    if matches {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+
+    (
+        [(header::CONTENT_TYPE, asset.content_type)],
+        cache_headers,
+        asset.body,
+    )
+        .into_response()
+}
@@ -0,0 +1,461 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, Utc};
+use futures::future::try_join_all;
+use serde::{Deserialize, Deserializer, Serialize};
+use tracing::{error, info};
+
+use crate::{
+    api::{ConsumptionDatum, OctopusClient, Period},
+    compare::{
+        find_property, import_meter_points, meter_point_consumption, property_region, Progress,
+        ReportProgress, AGILE_PRODUCT,
+    },
+    csv::read_columns,
+    error::UserError,
+    pricing::{local_date, Rates},
+};
+
+pub const DEFAULT_CARBON_URL: &str = "https://api.carbonintensity.org.uk";
+
+/// The regional API won't hand back more than this at a time.
+const DAYS_PER_REQUEST: i64 = 14;
+
+/// How far back to work out emissions for.
+const DAYS_TO_CHECK: i64 = 365;
+
+/// Half hours a day's flexible use gets moved into.
+const SHIFT_SLOTS: usize = 6;
+
+/// Grid carbon intensity for the half hour starting at `from`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct IntensityDatum {
+    #[serde(deserialize_with = "instant")]
+    pub from: DateTime<Utc>,
+    /// gCO2/kWh
+    pub intensity: f64,
+}
+
+/// The API leaves the seconds off its times, e.g. `2024-01-31T23:30Z`.
+fn parse_instant(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%MZ")
+                .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M"))
+                .map(|t| t.and_utc())
+                .ok()
+        })
+}
+
+fn instant<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    parse_instant(&value)
+        .ok_or_else(|| serde::de::Error::custom(format!("{:?} isn't a time", value)))
+}
+
+// {"data":{"regionid":13,"shortname":"London","postcode":"SW1A","data":[{"from":"2024-01-01T00:00Z","to":"2024-01-01T00:30Z","intensity":{"forecast":112,"index":"low"},"generationmix":[...]}]}}
+#[derive(Debug, Deserialize)]
+struct RegionalResponse {
+    data: RegionalData,
+}
+
+/// The docs show the region on its own and the API sometimes wraps it in a
+/// list, so take either.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RegionalData {
+    One(Region),
+    Many(Vec<Region>),
+}
+
+#[derive(Debug, Deserialize)]
+struct Region {
+    shortname: String,
+    data: Vec<RegionalPeriod>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RegionalPeriod {
+    #[serde(deserialize_with = "instant")]
+    from: DateTime<Utc>,
+    intensity: Intensity,
+}
+
+#[derive(Debug, Deserialize)]
+struct Intensity {
+    /// Regional figures are only ever forecasts
+    forecast: Option<f64>,
+}
+
+/// Half-hourly carbon intensity by region, from National Grid ESO's carbon
+/// intensity API or anything that looks like it.
+pub struct CarbonClient {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl CarbonClient {
+    pub fn new(base_url: &str, timeout: std::time::Duration) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(timeout)
+                .build()
+                .expect("Unable to build HTTP client"),
+            base_url: base_url.trim_end_matches('/').to_owned(),
+        }
+    }
+
+    /// Intensity for the region a postcode is in between two instants, with
+    /// the region's name. Fetched a fortnight at a time, oldest first.
+    pub async fn regional_intensity(
+        &self,
+        postcode: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        on_window: impl Fn(NaiveDate),
+    ) -> Result<(String, Vec<IntensityDatum>)> {
+        let outward = outward_code(postcode);
+        let mut region = String::new();
+        let mut results = vec![];
+
+        let mut start = from;
+        while start < to {
+            let end = (start + Duration::days(DAYS_PER_REQUEST)).min(to);
+            on_window(local_date(start));
+            let uri = format!(
+                "{}/regional/intensity/{}/{}/postcode/{}",
+                self.base_url,
+                start.format("%Y-%m-%dT%H:%MZ"),
+                end.format("%Y-%m-%dT%H:%MZ"),
+                outward
+            );
+
+            info!("Calling carbon intensity API {}", uri);
+            let body = match self.client.get(&uri).send().await {
+                Ok(body) => body,
+                Err(e) => {
+                    error!("Call to {} failed: {}", uri, e);
+                    bail!(UserError::Upstream(
+                        "We couldn't reach the carbon intensity API, try again in a bit."
+                            .to_owned()
+                    ));
+                }
+            };
+            if body.status().as_u16() != 200 {
+                let resp = body.text().await?;
+                error!("Carbon intensity response for {} failed: {}", uri, resp);
+                bail!(UserError::Upstream(
+                    "Unexpected error from the carbon intensity API.".to_owned()
+                ));
+            }
+
+            let regions = match body.json::<RegionalResponse>().await?.data {
+                RegionalData::One(r) => vec![r],
+                RegionalData::Many(r) => r,
+            };
+            for r in regions {
+                region = r.shortname;
+                results.extend(r.data.into_iter().filter_map(|p| {
+                    Some(IntensityDatum {
+                        from: p.from,
+                        intensity: p.intensity.forecast?,
+                    })
+                }));
+            }
+            start = end;
+        }
+
+        Ok((region, results))
+    }
+}
+
+/// The first half of a postcode, which is all the API wants, e.g. `SW1A`
+/// from `SW1A 1AA` or `SW1A1AA`.
+pub fn outward_code(postcode: &str) -> String {
+    let postcode = postcode.trim().to_uppercase();
+    match postcode.split_once(' ') {
+        Some((outward, _)) => outward.to_owned(),
+        None if postcode.len() > 4 => postcode[..postcode.len() - 3].to_owned(),
+        None => postcode,
+    }
+}
+
+/// Reads half-hourly intensity from a CSV with `from` and `intensity`
+/// (gCO2/kWh) columns, such as one saved from the API. Times are UTC, either
+/// `2024-01-31T23:30Z`, RFC 3339 or `2024-01-31 23:30`. Without a header row
+/// the columns are taken to be in that order.
+pub fn parse_intensity(csv: &str) -> Result<Vec<IntensityDatum>> {
+    let rows = read_columns(
+        csv,
+        "carbon intensity file",
+        &["from", "intensity"],
+        |first| parse_instant(first).is_some(),
+    )?;
+
+    let mut results = vec![];
+    for (i, row) in rows.iter().enumerate() {
+        let Some(from) = parse_instant(&row[0]) else {
+            bail!(UserError::BadRequest(format!(
+                "Line {} of the carbon intensity file doesn't have a time.",
+                i + 1
+            )));
+        };
+        let Ok(intensity) = row[1].parse::<f64>() else {
+            bail!(UserError::BadRequest(format!(
+                "Line {} of the carbon intensity file doesn't have an intensity.",
+                i + 1
+            )));
+        };
+        results.push(IntensityDatum { from, intensity });
+    }
+
+    if results.is_empty() {
+        bail!(UserError::BadRequest(
+            "There weren't any half hours in that carbon intensity file.".to_owned()
+        ));
+    }
+    Ok(results)
+}
+
+/// Moves `share` of each day's use into that day's cheapest Agile half hours,
+/// the way someone on Agile might run the dishwasher or immersion heater
+/// overnight. Days without a full set of prices are left as they are.
+pub fn shift_to_agile(
+    consumption: &[ConsumptionDatum],
+    rates: &dyn Rates,
+    share: f64,
+) -> Vec<ConsumptionDatum> {
+    let mut days: BTreeMap<NaiveDate, Vec<ConsumptionDatum>> = BTreeMap::new();
+    for d in consumption {
+        days.entry(local_date(d.interval_start))
+            .or_default()
+            .push(d.clone());
+    }
+
+    let mut shifted = vec![];
+    for mut readings in days.into_values() {
+        let rates: Option<Vec<f64>> = readings
+            .iter()
+            .map(|d| rates.unit_rate(d.interval_start))
+            .collect();
+        if let Some(rates) = rates {
+            let moved: f64 = readings.iter().map(|d| d.consumption * share).sum();
+            let mut cheapest: Vec<usize> = (0..readings.len()).collect();
+            cheapest.sort_by(|a, b| rates[*a].total_cmp(&rates[*b]));
+            cheapest.truncate(SHIFT_SLOTS);
+
+            for d in readings.iter_mut() {
+                d.consumption *= 1.0 - share;
+            }
+            for i in &cheapest {
+                readings[*i].consumption += moved / cheapest.len() as f64;
+            }
+        }
+        shifted.extend(readings);
+    }
+    shifted
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MonthlyCarbon {
+    pub year: i32,
+    pub month: u32,
+    /// kWh
+    pub consumption: f64,
+    /// kgCO2
+    pub emissions: f64,
+    /// kgCO2 with the flexible use moved to cheap Agile half hours
+    pub shifted_emissions: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CarbonReport {
+    pub address: String,
+    /// Region the intensity came from, when it came from the API
+    pub region: Option<String>,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    /// Percent of each day's use moved to the cheapest Agile half hours
+    pub flexible_percent: f64,
+    pub months: Vec<MonthlyCarbon>,
+    /// Half hours of readings there was no intensity for, left out of the totals
+    pub slots_missing: usize,
+}
+
+impl CarbonReport {
+    /// kWh
+    pub fn consumption(&self) -> f64 {
+        self.months.iter().map(|m| m.consumption).sum()
+    }
+
+    /// kgCO2
+    pub fn emissions(&self) -> f64 {
+        self.months.iter().map(|m| m.emissions).sum()
+    }
+
+    /// kgCO2, when there were Agile prices to shift the use around with
+    pub fn shifted_emissions(&self) -> Option<f64> {
+        self.months.iter().map(|m| m.shifted_emissions).sum()
+    }
+
+    /// gCO2/kWh
+    pub fn average_intensity(&self) -> f64 {
+        let kwh = self.consumption();
+        if kwh == 0.0 {
+            return 0.0;
+        }
+        self.emissions() * 1000.0 / kwh
+    }
+}
+
+/// kWh and kgCO2, keyed by year and month.
+type MonthTotals = BTreeMap<(i32, u32), (f64, f64)>;
+
+/// kgCO2 for each month's use, skipping half hours without an intensity.
+/// Returns how many were skipped as well.
+fn monthly_emissions(
+    consumption: &[ConsumptionDatum],
+    intensity: &HashMap<DateTime<Utc>, f64>,
+) -> (MonthTotals, usize) {
+    let mut months = MonthTotals::new();
+    let mut missing = 0;
+    for d in consumption {
+        let Some(g) = intensity.get(&d.interval_start) else {
+            missing += 1;
+            continue;
+        };
+        let date = local_date(d.interval_start);
+        let month = months.entry((date.year(), date.month())).or_default();
+        month.0 += d.consumption;
+        month.1 += d.consumption * g / 1000.0;
+    }
+    (months, missing)
+}
+
+/// Works out emissions from a year's consumption and `intensity`, and what
+/// they'd have been with `share` of it moved to cheap Agile half hours.
+pub fn carbon_emissions(
+    consumption: &[ConsumptionDatum],
+    agile: Option<&dyn Rates>,
+    share: f64,
+    intensity: &[IntensityDatum],
+) -> (Vec<MonthlyCarbon>, usize) {
+    let intensity: HashMap<DateTime<Utc>, f64> =
+        intensity.iter().map(|i| (i.from, i.intensity)).collect();
+    let (months, missing) = monthly_emissions(consumption, &intensity);
+    let shifted = agile
+        .map(|rates| monthly_emissions(&shift_to_agile(consumption, rates, share), &intensity).0);
+
+    let months = months
+        .into_iter()
+        .map(|((year, month), (consumption, emissions))| MonthlyCarbon {
+            year,
+            month,
+            consumption,
+            emissions,
+            shifted_emissions: shifted
+                .as_ref()
+                .map(|s| s.get(&(year, month)).map(|m| m.1).unwrap_or_default()),
+        })
+        .collect();
+    (months, missing)
+}
+
+#[derive(Debug)]
+pub struct CarbonRequest {
+    pub api_key: String,
+    pub account_number: String,
+    pub property_id: f64,
+    /// Fraction of each day's use that could be moved, 0 to 1
+    pub flexible_share: f64,
+}
+
+/// Works out the property's emissions over the last year, using `uploaded`
+/// intensity if there is any or the carbon intensity API for its region if
+/// not.
+pub async fn carbon_report(
+    client: &OctopusClient,
+    carbon: &CarbonClient,
+    request: &CarbonRequest,
+    uploaded: Option<Vec<IntensityDatum>>,
+    progress: &dyn ReportProgress,
+) -> Result<CarbonReport> {
+    progress.report(Progress::FetchingAccount);
+    let response = client
+        .get_account_details(&request.api_key, &request.account_number)
+        .await?;
+    let property = find_property(&response, request.property_id)?;
+    let tenancy = property.tenancy();
+    let import_points = import_meter_points(property);
+    let region = property_region(&import_points, tenancy.end());
+
+    let start = tenancy.end() - Duration::days(DAYS_TO_CHECK);
+    let period = Period {
+        from: Some(tenancy.from.map_or(start, |from| from.max(start))),
+        to: tenancy.to,
+    };
+
+    let agile = async {
+        let Some(region) = region else {
+            return Ok(None);
+        };
+        let product = client.get_product(AGILE_PRODUCT).await?;
+        match product.electricity_tariff_code(region) {
+            Some(code) => client.get_pricing_during(code, &period).await.map(Some),
+            None => Ok(None),
+        }
+    };
+    let readings = try_join_all(
+        import_points
+            .iter()
+            .map(|emp| meter_point_consumption(client, &request.api_key, emp, &period, progress)),
+    );
+    let (agile, readings) = tokio::try_join!(agile, readings)?;
+
+    // Meter points are separate supplies, so their emissions add up
+    let consumption: Vec<ConsumptionDatum> = readings
+        .into_iter()
+        .flat_map(|r| r.consumption.results)
+        .collect();
+    let (Some(first), Some(last)) = (
+        consumption.iter().map(|d| d.interval_start).min(),
+        consumption.iter().map(|d| d.interval_end).max(),
+    ) else {
+        bail!(UserError::NotFound(
+            "There aren't any half-hourly readings to work out emissions from.".to_owned()
+        ));
+    };
+
+    let (region, intensity) = match uploaded {
+        Some(intensity) => (None, intensity),
+        None => {
+            let (region, intensity) = carbon
+                .regional_intensity(&property.postcode, first, last, |from| {
+                    progress.report(Progress::FetchingCarbonIntensity { from })
+                })
+                .await?;
+            (Some(region).filter(|r| !r.is_empty()), intensity)
+        }
+    };
+
+    progress.report(Progress::Computing);
+    let (months, slots_missing) = carbon_emissions(
+        &consumption,
+        agile.as_deref().map(|a| a as &dyn Rates),
+        request.flexible_share,
+        &intensity,
+    );
+
+    Ok(CarbonReport {
+        address: format!("{}, {}", property.address_line_1, property.postcode),
+        region,
+        from: local_date(first),
+        to: local_date(last - Duration::minutes(30)),
+        flexible_percent: request.flexible_share * 100.0,
+        months,
+        slots_missing,
+    })
+}
@@ -0,0 +1,712 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Display,
+    sync::Arc,
+};
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use futures::future::{join_all, try_join_all};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::api::{
+    agreement_at, product_code, region, AccountProperty, AccountResponse, Agreement,
+    ConsumptionDatum, ConsumptionResponse, ElectricityMeterPoint, MeterInfo, OctopusClient, Period,
+    TariffPricing,
+};
+use crate::error::UserError;
+use crate::ev::{add_charging, has_smart_dispatches, DispatchedRates, EvProfile, EV_PRODUCTS};
+use crate::pricing::{local_date, monthly, price, DailyCost, MonthlyCost, PricedConsumption};
+use crate::profile::synthesise;
+use crate::quality::{check, fill_gaps, DataQuality};
+use crate::tariff::TariffDefinition;
+
+/// Volume correction times a typical calorific value (39.5 MJ/m³), over 3.6
+/// MJ/kWh. Octopus bills with the actual calorific value, which moves about a bit.
+pub const KWH_PER_CUBIC_METRE: f64 = 1.02264 * 39.5 / 3.6;
+
+#[derive(Debug, Clone)]
+pub enum Progress {
+    FetchingAccount,
+    FetchingConsumption {
+        mpan: String,
+        serial_number: String,
+        page: usize,
+    },
+    FetchingRates {
+        tariff_code: String,
+    },
+    EstimatingConsumption {
+        mpan: String,
+    },
+    Computing,
+    FetchingBills,
+    FetchingCarbonIntensity {
+        from: NaiveDate,
+    },
+    ComparingProperty {
+        account_number: String,
+        index: usize,
+        total: usize,
+    },
+}
+
+impl Display for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Progress::FetchingAccount => write!(f, "Fetching account details"),
+            Progress::FetchingConsumption {
+                mpan,
+                serial_number,
+                page,
+            } => write!(
+                f,
+                "Fetching consumption for {} meter {} (page {})",
+                mpan, serial_number, page
+            ),
+            Progress::FetchingRates { tariff_code } => {
+                write!(f, "Fetching rates for tariff {}", tariff_code)
+            }
+            Progress::EstimatingConsumption { mpan } => {
+                write!(f, "No readings for {}, estimating consumption", mpan)
+            }
+            Progress::Computing => write!(f, "Computing costs"),
+            Progress::FetchingBills => write!(f, "Fetching bills"),
+            Progress::FetchingCarbonIntensity { from } => {
+                write!(
+                    f,
+                    "Fetching carbon intensity from {}",
+                    from.format("%-d %b %Y")
+                )
+            }
+            Progress::ComparingProperty {
+                account_number,
+                index,
+                total,
+            } => write!(
+                f,
+                "Comparing property {} of {} (account {})",
+                index, total, account_number
+            ),
+        }
+    }
+}
+
+/// Anything that wants to hear about how a comparison is getting on.
+pub trait ReportProgress: Send + Sync {
+    fn report(&self, progress: Progress);
+}
+
+impl ReportProgress for () {
+    fn report(&self, _progress: Progress) {}
+}
+
+#[derive(Debug)]
+pub struct CompareRequest {
+    pub api_key: String,
+    pub account_number: String,
+    pub property_id: f64,
+    /// Adds a modelled EV's charging to the property's consumption
+    pub ev: Option<EvProfile>,
+    /// Tariffs defined in TOML/JSON to compare alongside the Octopus ones
+    pub definitions: Vec<TariffDefinition>,
+    /// Interpolate across short gaps in the readings before pricing
+    pub fill_gaps: bool,
+    /// Octopus products to price against, usually [`CANDIDATE_PRODUCTS`]
+    pub products: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Comparison {
+    pub address: String,
+    /// Costs for the whole property, summed across its import meter points
+    pub tariffs: Vec<TariffCost>,
+    pub meter_points: Vec<MeterPointComparison>,
+    /// Set when any of the consumption was made up from a standard profile
+    pub estimated: bool,
+    /// Set for a property the account has moved out of, whose costs only
+    /// cover the tenancy
+    pub moved_out_at: Option<DateTime<Utc>>,
+    /// Candidate products Octopus wouldn't give us, e.g. ones since withdrawn
+    #[serde(default)]
+    pub unavailable: Vec<String>,
+}
+
+impl Comparison {
+    pub fn current(&self) -> Option<&TariffCost> {
+        self.tariffs.iter().find(|t| t.is_current)
+    }
+
+    pub fn cheapest(&self) -> Option<&TariffCost> {
+        self.tariffs
+            .iter()
+            .min_by(|a, b| a.total().total_cmp(&b.total()))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MeterPointComparison {
+    pub mpan: String,
+    /// Serial numbers with the number of readings each contributed
+    pub meters: Vec<(String, usize)>,
+    pub tariffs: Vec<TariffCost>,
+    /// Annual consumption the readings were made up from, when the meter
+    /// didn't give us any
+    pub estimated_from: Option<f64>,
+    /// What we made of the readings, when there were any
+    pub quality: Option<DataQuality>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TariffCost {
+    pub tariff_code: String,
+    pub display_name: String,
+    pub supplier: Option<String>,
+    /// Pence, for leaving this tariff before its contract ends. For the
+    /// current tariff, only set while its fixed term is still running.
+    pub exit_fee: Option<f64>,
+    /// Pence, the current tariff's exit fee, which moving to this one would
+    /// mean paying. Included in the total.
+    #[serde(default)]
+    pub switching_fee: f64,
+    pub is_current: bool,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// Consumption cost in pence (inc. VAT)
+    pub unit_cost: f64,
+    /// Standing charges in pence (inc. VAT)
+    pub standing_cost: f64,
+    /// Set when the pricing data doesn't cover the whole consumption period
+    pub data_missing: bool,
+    pub days: Vec<DailyCost>,
+    /// kWh of modelled EV charging included in the costs
+    pub ev_energy: f64,
+    /// Smart-charge dispatches billed at the off-peak rate
+    pub dispatches: usize,
+}
+
+impl TariffCost {
+    pub fn total(&self) -> f64 {
+        self.unit_cost + self.standing_cost + self.switching_fee
+    }
+
+    fn new(pricing: &TariffPricing, priced: PricedConsumption) -> Self {
+        Self {
+            tariff_code: pricing.tariff_code.clone(),
+            display_name: pricing.display_name.clone(),
+            supplier: None,
+            exit_fee: None,
+            switching_fee: 0.0,
+            is_current: false,
+            from: priced.from,
+            to: priced.to,
+            unit_cost: priced.unit_cost(),
+            standing_cost: priced.standing_cost(),
+            data_missing: priced.data_missing,
+            days: priced.days,
+            ev_energy: 0.0,
+            dispatches: 0,
+        }
+    }
+
+    pub fn months(&self) -> Vec<MonthlyCost> {
+        monthly(&self.days)
+    }
+
+    fn add(&mut self, other: &TariffCost) {
+        self.unit_cost += other.unit_cost;
+        self.standing_cost += other.standing_cost;
+        self.data_missing |= other.data_missing;
+        self.ev_energy += other.ev_energy;
+        self.dispatches += other.dispatches;
+        // Leaving only costs the fee once, however many meter points there are
+        self.exit_fee = self
+            .exit_fee
+            .into_iter()
+            .chain(other.exit_fee)
+            .reduce(f64::max);
+        self.from = self.from.into_iter().chain(other.from).min();
+        self.to = self.to.into_iter().chain(other.to).max();
+
+        let mut days: BTreeMap<NaiveDate, DailyCost> =
+            self.days.drain(..).map(|d| (d.date, d)).collect();
+        for day in &other.days {
+            let d = days
+                .entry(day.date)
+                .or_insert_with(|| DailyCost::new(day.date));
+            d.slots += day.slots;
+            d.consumption += day.consumption;
+            d.unit_cost += day.unit_cost;
+            d.standing_cost += day.standing_cost;
+        }
+        self.days = days.into_values().collect();
+    }
+}
+
+const OCTOPUS: &str = "Octopus Energy";
+
+pub const AGILE_PRODUCT: &str = "AGILE-24-10-01";
+
+pub const FLEXIBLE_PRODUCT: &str = "VAR-22-11-01";
+
+/// Products every property's consumption gets priced against, alongside
+/// whatever tariff it's on today, unless configured otherwise.
+pub const CANDIDATE_PRODUCTS: [&str; 3] = [AGILE_PRODUCT, "SILVER-24-10-01", FLEXIBLE_PRODUCT];
+
+/// Prices a property's consumption against the candidate tariffs. Pricing
+/// and products are cached on the client, so share one across a batch.
+pub async fn compare_tariffs(
+    client: &OctopusClient,
+    request: &CompareRequest,
+    progress: &dyn ReportProgress,
+) -> Result<Comparison> {
+    progress.report(Progress::FetchingAccount);
+    let response: AccountResponse = client
+        .get_account_details(&request.api_key, &request.account_number)
+        .await?;
+
+    compare_property(client, request, &response, progress).await
+}
+
+/// Like [`compare_tariffs`], for an account that's already been fetched.
+pub async fn compare_property(
+    client: &OctopusClient,
+    request: &CompareRequest,
+    response: &AccountResponse,
+    progress: &dyn ReportProgress,
+) -> Result<Comparison> {
+    let property = find_property(response, request.property_id)?;
+    let tenancy = property.tenancy();
+    let import_points = import_meter_points(property);
+    let region = property_region(&import_points, tenancy.end());
+
+    let mut products: Vec<&str> = request.products.iter().map(|p| p.as_str()).collect();
+    if request.ev.is_some() {
+        products.extend(EV_PRODUCTS);
+    }
+    let (candidates, unavailable) = match region {
+        Some(region) => candidate_tariff_codes(client, &products, region).await,
+        None => (vec![], vec![]),
+    };
+
+    // The car only gets charged from one meter point, so stick it on the first
+    let meter_points = try_join_all(import_points.iter().enumerate().map(|(i, emp)| {
+        let ev = request.ev.as_ref().filter(|_| i == 0);
+        compare_meter_point(client, request, emp, &tenancy, &candidates, ev, progress)
+    }))
+    .await?;
+
+    let mut totals_for = candidates;
+    totals_for.extend(request.definitions.iter().map(|d| d.code.clone()));
+    let mut tariffs = property_totals(&meter_points, &totals_for);
+
+    // Moving to anything else means paying to leave the current tariff
+    let leaving = tariffs
+        .iter()
+        .find(|t| t.is_current)
+        .and_then(|t| t.exit_fee);
+    for tariff in tariffs.iter_mut().filter(|t| !t.is_current) {
+        tariff.switching_fee = leaving.unwrap_or_default();
+    }
+
+    Ok(Comparison {
+        address: format!("{}, {}", property.address_line_1, property.postcode),
+        tariffs,
+        estimated: meter_points.iter().any(|m| m.estimated_from.is_some()),
+        meter_points,
+        moved_out_at: property.moved_out_at,
+        unavailable,
+    })
+}
+
+/// Looks up the tariff code each candidate product uses in a region, along
+/// with the products that couldn't be fetched. One product being withdrawn
+/// shouldn't stop the rest being compared.
+async fn candidate_tariff_codes(
+    client: &OctopusClient,
+    products: &[&str],
+    region: char,
+) -> (Vec<String>, Vec<String>) {
+    let fetched = join_all(products.iter().map(|p| client.get_product(p))).await;
+
+    let mut codes = vec![];
+    let mut unavailable = vec![];
+    for (product, result) in products.iter().zip(fetched) {
+        match result {
+            Ok(detail) => codes.extend(
+                detail
+                    .electricity_tariff_code(region)
+                    .map(|code| code.to_owned()),
+            ),
+            Err(e) => {
+                warn!("Leaving out product {}: {:#}", product, e);
+                unavailable.push(product.to_string());
+            }
+        }
+    }
+    (codes, unavailable)
+}
+
+pub fn find_property(response: &AccountResponse, property_id: f64) -> Result<&AccountProperty> {
+    match response.properties.iter().find(|p| p.id == property_id) {
+        Some(p) => Ok(p),
+        None => bail!(UserError::NotFound(
+            "Hmmmm... This is embarrassing, we couldn't find that property.".to_owned()
+        )),
+    }
+}
+
+/// Meter points the property imports through that have a meter to read.
+pub fn import_meter_points(property: &AccountProperty) -> Vec<&ElectricityMeterPoint> {
+    property
+        .electricity_meter_points
+        .iter()
+        .filter(|emp| !emp.is_export && !emp.meters.is_empty())
+        .collect()
+}
+
+/// Tariffs are region specific, so borrow the region from whichever meter
+/// point had an agreement at `at`.
+pub fn property_region(meter_points: &[&ElectricityMeterPoint], at: DateTime<Utc>) -> Option<char> {
+    meter_points
+        .iter()
+        .filter_map(|emp| agreement_at(&emp.agreements, at))
+        .find_map(|a| region(&a.tariff_code))
+}
+
+pub fn current_agreement(emp: &ElectricityMeterPoint) -> Option<&Agreement> {
+    agreement_at(&emp.agreements, chrono::offset::Utc::now())
+}
+
+async fn compare_meter_point(
+    client: &OctopusClient,
+    request: &CompareRequest,
+    emp: &ElectricityMeterPoint,
+    tenancy: &Period,
+    candidates: &[String],
+    ev: Option<&EvProfile>,
+    progress: &dyn ReportProgress,
+) -> Result<MeterPointComparison> {
+    info!("Processing MPAN: {}", emp.mpan);
+    let definitions = &request.definitions;
+    // For a past property, "current" is whatever they were on when they left
+    let agreement = agreement_at(&emp.agreements, tenancy.end());
+
+    let mut tariff_codes: Vec<String> = agreement.iter().map(|a| a.tariff_code.clone()).collect();
+    for code in candidates {
+        if !tariff_codes.contains(code) {
+            tariff_codes.push(code.clone());
+        }
+    }
+
+    // Only worth knowing if they're still there and could still switch
+    let exit_fee = match agreement {
+        Some(a) if tenancy.to.is_none() && a.valid_to > chrono::offset::Utc::now() => {
+            current_exit_fee(client, &a.tariff_code).await
+        }
+        _ => None,
+    };
+
+    let consumption = meter_point_consumption(client, &request.api_key, emp, tenancy, progress);
+    let pricing = try_join_all(tariff_codes.iter().map(|tariff_code| async move {
+        progress.report(Progress::FetchingRates {
+            tariff_code: tariff_code.clone(),
+        });
+        match tenancy.to {
+            Some(_) => client.get_pricing_during(tariff_code, tenancy).await,
+            None => client.get_pricing(tariff_code).await,
+        }
+    }));
+    let (readings, mut pricing) = tokio::try_join!(consumption, pricing)?;
+    let MeterPointReadings {
+        meters,
+        consumption: mut consumption_data,
+        duplicates,
+    } = readings;
+
+    let mut quality = None;
+    if !consumption_data.results.is_empty() {
+        let mut q = check(&consumption_data.results, duplicates);
+        if request.fill_gaps {
+            q.filled = fill_gaps(&mut consumption_data.results, &q.gaps);
+        }
+        quality = Some(q);
+    }
+
+    // Without smart readings (or consent to read them) the best we can do is
+    // the industry's estimate of a year's usage, spread over a standard profile
+    let mut estimated_from = None;
+    if consumption_data.results.is_empty() && emp.consumption_standard > 0.0 {
+        progress.report(Progress::EstimatingConsumption {
+            mpan: emp.mpan.clone(),
+        });
+        let to = local_date(tenancy.end()).pred_opt().unwrap();
+        let from = (to - Duration::days(364)).max(tenancy.from.map(local_date).unwrap_or(to));
+        consumption_data.results =
+            synthesise(emp.profile_class as u32, emp.consumption_standard, from, to)?;
+        estimated_from = Some(emp.consumption_standard);
+    }
+
+    // Defined tariffs only need pricing for the days there's consumption
+    let first = consumption_data
+        .results
+        .last()
+        .map(|d| local_date(d.interval_start));
+    let last = consumption_data
+        .results
+        .first()
+        .map(|d| local_date(d.interval_start));
+    if let (Some(first), Some(last)) = (first, last) {
+        for definition in definitions {
+            pricing.push(Arc::new(definition.compile(first, last)?));
+        }
+    }
+
+    progress.report(Progress::Computing);
+    let tariffs = pricing
+        .iter()
+        .map(|price_info| {
+            let mut cost = match ev {
+                Some(ev) => price_with_ev(&consumption_data, price_info, ev),
+                None => TariffCost::new(
+                    price_info,
+                    price(&consumption_data.results, price_info.as_ref()),
+                ),
+            };
+            cost.is_current = agreement.is_some_and(|a| a.tariff_code == price_info.tariff_code);
+            if cost.is_current {
+                cost.exit_fee = exit_fee;
+            }
+            match definitions
+                .iter()
+                .find(|d| d.code == price_info.tariff_code)
+            {
+                Some(definition) => {
+                    cost.supplier = definition.supplier.clone();
+                    cost.exit_fee = definition.exit_fee;
+                }
+                None => cost.supplier = Some(OCTOPUS.to_owned()),
+            }
+            cost
+        })
+        .collect();
+
+    Ok(MeterPointComparison {
+        mpan: emp.mpan.clone(),
+        meters,
+        tariffs,
+        estimated_from,
+        quality,
+    })
+}
+
+/// The exit fee on an Octopus tariff, from its product. Not knowing it isn't
+/// worth failing the comparison over.
+async fn current_exit_fee(client: &OctopusClient, tariff_code: &str) -> Option<f64> {
+    let product = client.get_product(product_code(tariff_code)?).await;
+    match product {
+        Ok(product) => product.exit_fee(tariff_code),
+        Err(e) => {
+            warn!(
+                "Unable to look up the exit fee for {}: {:#}",
+                tariff_code, e
+            );
+            None
+        }
+    }
+}
+
+fn price_with_ev(
+    consumption_data: &ConsumptionResponse,
+    price_info: &TariffPricing,
+    ev: &EvProfile,
+) -> TariffCost {
+    let charging = add_charging(
+        &consumption_data.results,
+        price_info,
+        ev,
+        has_smart_dispatches(&price_info.product_code),
+    );
+    let rates = DispatchedRates {
+        rates: price_info,
+        dispatches: &charging.dispatches,
+    };
+
+    let mut cost = TariffCost::new(price_info, price(&charging.consumption, &rates));
+    cost.ev_energy = charging.energy;
+    cost.dispatches = charging.dispatches.len();
+    cost
+}
+
+pub struct MeterPointReadings {
+    /// Serial numbers with the number of readings each contributed
+    pub meters: Vec<(String, usize)>,
+    pub consumption: ConsumptionResponse,
+    /// Intervals a meter reported more than once
+    pub duplicates: usize,
+}
+
+/// Fetches readings from every meter that has been on an MPAN.
+pub async fn meter_point_consumption(
+    client: &OctopusClient,
+    api_key: &str,
+    emp: &ElectricityMeterPoint,
+    period: &Period,
+    progress: &dyn ReportProgress,
+) -> Result<MeterPointReadings> {
+    let consumption = try_join_all(emp.meters.iter().map(|meter| {
+        client.get_consumption_data(
+            api_key,
+            MeterInfo::Electricity(meter.serial_number.clone(), emp.mpan.clone()),
+            period,
+            |page| {
+                progress.report(Progress::FetchingConsumption {
+                    mpan: emp.mpan.clone(),
+                    serial_number: meter.serial_number.clone(),
+                    page,
+                })
+            },
+        )
+    }))
+    .await?;
+
+    let meters = emp
+        .meters
+        .iter()
+        .zip(&consumption)
+        .map(|(meter, c)| (meter.serial_number.clone(), c.results.len()))
+        .collect();
+    // Overlap between meters is expected around a swap, the same meter
+    // reporting an interval twice isn't
+    let duplicates = consumption
+        .iter()
+        .map(|c| {
+            let intervals: HashSet<_> = c.results.iter().map(|d| d.interval_start).collect();
+            c.results.len() - intervals.len()
+        })
+        .sum();
+
+    Ok(MeterPointReadings {
+        meters,
+        consumption: merge_consumption(consumption),
+        duplicates,
+    })
+}
+
+/// Every meter on every gas meter point, in kWh. Separate meter points are
+/// separate supplies, so their readings add up. SMETS2 meters mostly read in
+/// cubic metres, which get converted.
+pub async fn gas_consumption(
+    client: &OctopusClient,
+    api_key: &str,
+    property: &AccountProperty,
+    period: &Period,
+    in_cubic_metres: bool,
+    progress: &dyn ReportProgress,
+) -> Result<Vec<ConsumptionDatum>> {
+    let meter_points = try_join_all(property.gas_meter_points.iter().map(|gmp| async move {
+        let readings = try_join_all(gmp.meters.iter().map(|meter| {
+            client.get_consumption_data(
+                api_key,
+                MeterInfo::Gas(meter.serial_number.clone(), gmp.mprn.clone()),
+                period,
+                |page| {
+                    progress.report(Progress::FetchingConsumption {
+                        mpan: gmp.mprn.clone(),
+                        serial_number: meter.serial_number.clone(),
+                        page,
+                    })
+                },
+            )
+        }))
+        .await?;
+        anyhow::Ok(merge_consumption(readings))
+    }))
+    .await?;
+
+    let unit = match in_cubic_metres {
+        true => KWH_PER_CUBIC_METRE,
+        false => 1.0,
+    };
+    Ok(meter_points
+        .into_iter()
+        .flat_map(|m: ConsumptionResponse| m.results)
+        .map(|mut d| {
+            d.consumption *= unit;
+            d
+        })
+        .collect())
+}
+
+/// Combines readings from every meter that has been on an MPAN. When meters are
+/// swapped both can report the same half hour (usually the old one reporting
+/// zero), so only the largest reading for each interval is kept.
+pub fn merge_consumption(responses: Vec<ConsumptionResponse>) -> ConsumptionResponse {
+    let mut by_interval: BTreeMap<DateTime<Utc>, ConsumptionDatum> = BTreeMap::new();
+    for datum in responses.into_iter().flat_map(|r| r.results) {
+        match by_interval.get(&datum.interval_start) {
+            Some(existing) if existing.consumption >= datum.consumption => {}
+            _ => {
+                by_interval.insert(datum.interval_start, datum);
+            }
+        }
+    }
+
+    // The API hands back the most recent readings first, keep it that way
+    let results: Vec<ConsumptionDatum> = by_interval.into_values().rev().collect();
+    ConsumptionResponse {
+        count: results.len() as i64,
+        results,
+        next: None,
+    }
+}
+
+/// Rolls the meter point costs up into a row per candidate product for the
+/// property, plus a row for the current set of tariffs if that isn't already
+/// one of the candidates.
+pub fn property_totals(
+    meter_points: &[MeterPointComparison],
+    candidates: &[String],
+) -> Vec<TariffCost> {
+    let mut totals: Vec<TariffCost> = vec![];
+    let mut current: Option<TariffCost> = None;
+
+    for cost in meter_points.iter().flat_map(|m| &m.tariffs) {
+        if cost.is_current {
+            match &mut current {
+                Some(c) => {
+                    c.add(cost);
+                    if !c.tariff_code.contains(&cost.tariff_code) {
+                        c.tariff_code = format!("{} + {}", c.tariff_code, cost.tariff_code);
+                        c.display_name = format!("{} + {}", c.display_name, cost.display_name);
+                    }
+                }
+                None => current = Some(cost.clone()),
+            }
+        }
+
+        if !candidates.contains(&cost.tariff_code) {
+            continue;
+        }
+
+        match totals
+            .iter_mut()
+            .find(|t| t.tariff_code == cost.tariff_code)
+        {
+            Some(total) => {
+                total.add(cost);
+                total.is_current &= cost.is_current;
+            }
+            None => totals.push(cost.clone()),
+        }
+    }
+
+    if let Some(current) = current {
+        if !totals.iter().any(|t| t.is_current) {
+            totals.push(current);
+        }
+    }
+
+    totals
+}
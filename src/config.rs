@@ -0,0 +1,384 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use serde::Deserialize;
+
+use crate::{
+    alerts::SmtpRelay,
+    api::{ClientSettings, DEFAULT_BASE_URL},
+    carbon::DEFAULT_CARBON_URL,
+    compare::CANDIDATE_PRODUCTS,
+};
+
+/// Read from the working directory if it's there and `--config` isn't given.
+const DEFAULT_CONFIG_FILE: &str = "octocompare.toml";
+
+/// axum logs rejections from built-in extractors with the `axum::rejection`
+/// target, at `TRACE` level. `axum::rejection=trace` enables showing those events
+const DEFAULT_LOG_FILTER: &str = "octocompare=debug,axum::rejection=trace";
+
+/// Command line flags. Each one can also be set with an `OCTOCOMPARE_*`
+/// environment variable, and either beats the config file.
+#[derive(Debug, Default, Parser)]
+#[command(version, about = "Which Octopus tariff would have been cheapest?")]
+pub struct Cli {
+    /// TOML config file, defaults to `octocompare.toml` if there is one
+    #[arg(long, env = "OCTOCOMPARE_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Address to listen on
+    #[arg(long, env = "OCTOCOMPARE_HOST")]
+    pub host: Option<String>,
+    #[arg(long, env = "OCTOCOMPARE_PORT")]
+    pub port: Option<u16>,
+    /// Where the server can be reached, used for links in alerts
+    #[arg(long, env = "OCTOCOMPARE_BASE_URL")]
+    pub base_url: Option<String>,
+    /// Octopus API to call, handy for pointing at a stub
+    #[arg(long, env = "OCTOCOMPARE_OCTOPUS_URL")]
+    pub octopus_url: Option<String>,
+    /// Carbon intensity API to call, handy for pointing at a stub or mirror
+    #[arg(long, env = "OCTOCOMPARE_CARBON_URL")]
+    pub carbon_url: Option<String>,
+    /// Seconds to wait for a response from the Octopus API
+    #[arg(long, env = "OCTOCOMPARE_REQUEST_TIMEOUT")]
+    pub request_timeout: Option<u64>,
+    /// Seconds a request to the server gets before it's given up on
+    #[arg(long, env = "OCTOCOMPARE_HANDLER_TIMEOUT")]
+    pub handler_timeout: Option<u64>,
+    /// Timeouts for particular routes, e.g. `/forecast=90`, comma separated
+    #[arg(long, env = "OCTOCOMPARE_ROUTE_TIMEOUTS", value_delimiter = ',', value_parser = parse_route_timeout)]
+    pub route_timeouts: Vec<(String, u64)>,
+    /// Seconds to wait for comparisons to finish when shutting down
+    #[arg(long, env = "OCTOCOMPARE_SHUTDOWN_GRACE")]
+    pub shutdown_grace: Option<u64>,
+    /// Where saved reports and alert sign-ups are kept
+    #[arg(long, env = "OCTOCOMPARE_DATA_DIR")]
+    pub data_dir: Option<PathBuf>,
+    /// Where Octopus products and prices are cached across restarts, only
+    /// in memory if not set
+    #[arg(long, env = "OCTOCOMPARE_CACHE_DIR")]
+    pub cache_dir: Option<PathBuf>,
+    /// What to log, e.g. `octocompare=info`. `RUST_LOG` works too
+    #[arg(long, env = "OCTOCOMPARE_LOG")]
+    pub log_filter: Option<String>,
+    /// Directory of tariff definitions to load at startup
+    #[arg(long, env = "OCTOCOMPARE_TARIFFS_DIR")]
+    pub tariffs_dir: Option<PathBuf>,
+    /// Octopus products every property gets compared against, comma separated
+    #[arg(long, env = "OCTOCOMPARE_PRODUCTS", value_delimiter = ',')]
+    pub products: Option<Vec<String>>,
+    #[arg(long, env = "OCTOCOMPARE_SMTP_HOST")]
+    pub smtp_host: Option<String>,
+    #[arg(long, env = "OCTOCOMPARE_SMTP_PORT")]
+    pub smtp_port: Option<u16>,
+    #[arg(long, env = "OCTOCOMPARE_SMTP_FROM")]
+    pub smtp_from: Option<String>,
+    /// Features to turn on, comma separated
+    #[arg(long, env = "OCTOCOMPARE_ENABLE", value_delimiter = ',')]
+    pub enable: Vec<Feature>,
+    /// Features to turn off, comma separated. Beats `--enable`
+    #[arg(long, env = "OCTOCOMPARE_DISABLE", value_delimiter = ',')]
+    pub disable: Vec<Feature>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Feature {
+    Workspace,
+    Forecast,
+    Alerts,
+    Reports,
+    Reconcile,
+    Carbon,
+    HeatPump,
+}
+
+/// Bits of the app that can be switched off, everything's on by default.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Features {
+    pub workspace: bool,
+    pub forecast: bool,
+    pub alerts: bool,
+    pub reports: bool,
+    pub reconcile: bool,
+    pub carbon: bool,
+    pub heat_pump: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            workspace: true,
+            forecast: true,
+            alerts: true,
+            reports: true,
+            reconcile: true,
+            carbon: true,
+            heat_pump: true,
+        }
+    }
+}
+
+impl Features {
+    fn set(&mut self, feature: Feature, on: bool) {
+        match feature {
+            Feature::Workspace => self.workspace = on,
+            Feature::Forecast => self.forecast = on,
+            Feature::Alerts => self.alerts = on,
+            Feature::Reports => self.reports = on,
+            Feature::Reconcile => self.reconcile = on,
+            Feature::Carbon => self.carbon = on,
+            Feature::HeatPump => self.heat_pump = on,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SmtpFile {
+    host: Option<String>,
+    port: Option<u16>,
+    from: Option<String>,
+}
+
+/// The config file, where everything's optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    host: Option<String>,
+    port: Option<u16>,
+    base_url: Option<String>,
+    octopus_url: Option<String>,
+    carbon_url: Option<String>,
+    request_timeout: Option<u64>,
+    handler_timeout: Option<u64>,
+    route_timeouts: HashMap<String, u64>,
+    shutdown_grace: Option<u64>,
+    data_dir: Option<PathBuf>,
+    cache_dir: Option<PathBuf>,
+    log_filter: Option<String>,
+    tariffs_dir: Option<PathBuf>,
+    products: Option<Vec<String>>,
+    smtp: SmtpFile,
+    features: Features,
+}
+
+impl ConfigFile {
+    fn load(cli: &Cli) -> Result<Self> {
+        let (path, required) = match &cli.config {
+            Some(path) => (path.as_path(), true),
+            None => (Path::new(DEFAULT_CONFIG_FILE), false),
+        };
+        let toml = match std::fs::read_to_string(path) {
+            Ok(toml) => toml,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => {
+                return Ok(Self::default())
+            }
+            Err(e) => return Err(e).with_context(|| format!("Unable to read {:?}", path)),
+        };
+        toml::from_str(&toml).with_context(|| format!("Unable to parse {:?}", path))
+    }
+}
+
+/// How long each route gets to respond.
+#[derive(Debug, Clone)]
+pub struct RouteTimeouts {
+    pub default: Duration,
+    /// Keyed by route as it's registered, e.g. `/reports/:report_id`
+    pub routes: HashMap<String, Duration>,
+}
+
+impl RouteTimeouts {
+    pub fn for_route(&self, route: &str) -> Duration {
+        self.routes.get(route).copied().unwrap_or(self.default)
+    }
+}
+
+/// Everything the server needs to start, resolved from flags, environment
+/// and config file in that order.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub bind: SocketAddr,
+    pub base_url: String,
+    pub octopus: ClientSettings,
+    pub carbon_url: String,
+    pub timeouts: RouteTimeouts,
+    pub shutdown_grace: Duration,
+    pub data_dir: PathBuf,
+    pub tariffs_dir: PathBuf,
+    pub products: Vec<String>,
+    pub smtp: Option<SmtpRelay>,
+    pub features: Features,
+    pub log_filter: String,
+}
+
+impl Config {
+    pub fn load(cli: Cli) -> Result<Self> {
+        let file = ConfigFile::load(&cli)?;
+        Self::resolve(cli, file)
+    }
+
+    fn resolve(cli: Cli, file: ConfigFile) -> Result<Self> {
+        let host = cli
+            .host
+            .or(file.host)
+            .unwrap_or_else(|| "127.0.0.1".to_owned());
+        let host: IpAddr = host
+            .parse()
+            .with_context(|| format!("host {:?} isn't an IP address", host))?;
+        let port = cli.port.or(file.port).unwrap_or(3000);
+        let bind = SocketAddr::new(host, port);
+
+        let base_url = cli
+            .base_url
+            .or(file.base_url)
+            .unwrap_or_else(|| format!("http://{}", bind));
+        let base_url = check_url("base_url", base_url)?;
+
+        let octopus_url = cli
+            .octopus_url
+            .or(file.octopus_url)
+            .unwrap_or_else(|| DEFAULT_BASE_URL.to_owned());
+        let octopus_url = check_url("octopus_url", octopus_url)?;
+
+        let carbon_url = cli
+            .carbon_url
+            .or(file.carbon_url)
+            .unwrap_or_else(|| DEFAULT_CARBON_URL.to_owned());
+        let carbon_url = check_url("carbon_url", carbon_url)?;
+
+        let timeout = cli.request_timeout.or(file.request_timeout).unwrap_or(30);
+        if timeout == 0 {
+            bail!("request_timeout has to be at least a second");
+        }
+
+        let handler_timeout = cli.handler_timeout.or(file.handler_timeout).unwrap_or(60);
+        // Flags add to or override the file's routes rather than replacing them all
+        let mut route_timeouts = file.route_timeouts;
+        route_timeouts.extend(cli.route_timeouts);
+        if handler_timeout == 0 {
+            bail!("handler_timeout has to be at least a second");
+        }
+        for (route, secs) in &route_timeouts {
+            if !route.starts_with('/') {
+                bail!("route_timeouts route {:?} needs to start with /", route);
+            }
+            if *secs == 0 {
+                bail!("timeout for {} has to be at least a second", route);
+            }
+        }
+        let timeouts = RouteTimeouts {
+            default: Duration::from_secs(handler_timeout),
+            routes: route_timeouts
+                .into_iter()
+                .map(|(route, secs)| (route, Duration::from_secs(secs)))
+                .collect(),
+        };
+        let shutdown_grace = cli.shutdown_grace.or(file.shutdown_grace).unwrap_or(120);
+
+        let cache_dir = cli.cache_dir.or(file.cache_dir);
+        if let Some(dir) = cache_dir.as_ref().filter(|dir| dir.is_file()) {
+            bail!("cache_dir {:?} is a file, not a directory", dir);
+        }
+
+        let log_filter = cli
+            .log_filter
+            .or(file.log_filter)
+            .unwrap_or_else(|| DEFAULT_LOG_FILTER.to_owned());
+        tracing_subscriber::EnvFilter::try_new(&log_filter)
+            .with_context(|| format!("log_filter {:?} isn't valid", log_filter))?;
+
+        let products = cli
+            .products
+            .or(file.products)
+            .unwrap_or_else(|| CANDIDATE_PRODUCTS.iter().map(|p| p.to_string()).collect());
+        let products: Vec<String> = products.iter().map(|p| p.trim().to_owned()).collect();
+        if products.is_empty() || products.iter().any(|p| p.is_empty()) {
+            bail!("products needs at least one product code, and no blank ones");
+        }
+
+        let smtp_host = cli.smtp_host.or(file.smtp.host);
+        let smtp = match smtp_host {
+            Some(host) => {
+                let from = cli
+                    .smtp_from
+                    .or(file.smtp.from)
+                    .unwrap_or_else(|| "octocompare@localhost".to_owned());
+                from.parse::<lettre::message::Mailbox>()
+                    .with_context(|| format!("smtp from address {:?} isn't valid", from))?;
+                Some(SmtpRelay {
+                    host,
+                    port: cli.smtp_port.or(file.smtp.port).unwrap_or(25),
+                    from,
+                })
+            }
+            None => None,
+        };
+
+        let mut features = file.features;
+        for feature in cli.enable {
+            features.set(feature, true);
+        }
+        for feature in cli.disable {
+            features.set(feature, false);
+        }
+        // Alerts link to the report of the comparison that triggered them
+        if features.alerts && !features.reports {
+            bail!("alerts can't be turned on without reports");
+        }
+
+        Ok(Self {
+            bind,
+            base_url,
+            octopus: ClientSettings {
+                base_url: octopus_url,
+                timeout: Duration::from_secs(timeout),
+                cache_dir,
+            },
+            carbon_url,
+            timeouts,
+            shutdown_grace: Duration::from_secs(shutdown_grace),
+            data_dir: cli.data_dir.or(file.data_dir).unwrap_or_default(),
+            tariffs_dir: cli
+                .tariffs_dir
+                .or(file.tariffs_dir)
+                .unwrap_or_else(|| PathBuf::from("tariffs")),
+            products,
+            smtp,
+            features,
+            log_filter,
+        })
+    }
+
+    pub fn reports_dir(&self) -> PathBuf {
+        self.data_dir.join("reports")
+    }
+
+    pub fn subscriptions_file(&self) -> PathBuf {
+        self.data_dir.join("subscriptions.json")
+    }
+}
+
+fn parse_route_timeout(value: &str) -> Result<(String, u64)> {
+    let Some((route, secs)) = value.split_once('=') else {
+        bail!("expected ROUTE=SECONDS, e.g. /forecast=90");
+    };
+    let secs = secs
+        .trim()
+        .parse()
+        .with_context(|| format!("{:?} isn't a number of seconds", secs))?;
+    Ok((route.trim().to_owned(), secs))
+}
+
+fn check_url(name: &str, url: String) -> Result<String> {
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        bail!("{} {:?} needs to start with http:// or https://", name, url);
+    }
+    Ok(url.trim_end_matches('/').to_owned())
+}
@@ -0,0 +1,77 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+static ACCOUNT_NUMBER: LazyLock<Regex> = LazyLock::new(|| Regex::new("^A-[0-9A-Z]{8}$").unwrap());
+
+/// An account number and API key that at least look right, so typos get
+/// caught before a round trip to Octopus.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub account_number: String,
+    pub api_key: String,
+}
+
+/// What's wrong with each field, if anything.
+#[derive(Debug, Default, Clone)]
+pub struct CredentialErrors {
+    pub account_number: Option<String>,
+    pub api_key: Option<String>,
+}
+
+impl Credentials {
+    /// Trims both, upper-cases the account number and checks they're the
+    /// right shape: `A-` then eight letters or digits, and a live secret key.
+    pub fn parse(account_number: &str, api_key: &str) -> Result<Self, CredentialErrors> {
+        let account_number = account_number.trim().to_uppercase();
+        let api_key = api_key.trim().to_owned();
+        let mut errors = CredentialErrors::default();
+
+        if account_number.is_empty() {
+            errors.account_number = Some("We need your account number.".to_owned());
+        } else if !ACCOUNT_NUMBER.is_match(&account_number) {
+            errors.account_number = Some(
+                "Account numbers look like A-1234ABCD, it's at the top of your bill.".to_owned(),
+            );
+        }
+
+        if api_key.is_empty() {
+            errors.api_key = Some("We need your API key.".to_owned());
+        } else if !api_key.starts_with("sk_live_") || api_key.len() == "sk_live_".len() {
+            errors.api_key = Some(
+                "API keys start with sk_live_, get yours from the developer settings on your Octopus account."
+                    .to_owned(),
+            );
+        } else if api_key.chars().any(|c| c.is_whitespace()) {
+            errors.api_key = Some("API keys don't have any spaces in them.".to_owned());
+        }
+
+        if errors.is_empty() {
+            Ok(Self {
+                account_number,
+                api_key,
+            })
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+impl CredentialErrors {
+    pub fn is_empty(&self) -> bool {
+        self.account_number.is_none() && self.api_key.is_none()
+    }
+}
+
+impl std::fmt::Display for CredentialErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let errors: Vec<&str> = [&self.account_number, &self.api_key]
+            .into_iter()
+            .flatten()
+            .map(|e| e.as_str())
+            .collect();
+        f.write_str(&errors.join(" "))
+    }
+}
+
+impl std::error::Error for CredentialErrors {}
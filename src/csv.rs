@@ -0,0 +1,75 @@
+use anyhow::{bail, Result};
+
+use crate::error::UserError;
+
+/// Splits a line of CSV into trimmed fields. Fields can be quoted, so they
+/// can hold commas (`"£1,234.56"`), with `""` standing for a quote inside
+/// one. Quoted fields can't run over more than one line.
+pub fn split_line(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_owned()),
+            c => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_owned());
+    fields
+}
+
+/// Picks `columns` out of each row of an uploaded CSV, in that order, with
+/// missing fields left blank. The header row is optional: if the first field
+/// of the first line passes `is_value` it's taken to be data, and the columns
+/// to be in the order given. Otherwise the header has to name every column
+/// (in any case), and the error says which one is missing from the `what`,
+/// e.g. "statement". Blank lines are skipped.
+pub fn read_columns(
+    csv: &str,
+    what: &str,
+    columns: &[&str],
+    is_value: impl Fn(&str) -> bool,
+) -> Result<Vec<Vec<String>>> {
+    let mut lines = csv
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(split_line)
+        .peekable();
+
+    let mut positions: Vec<usize> = (0..columns.len()).collect();
+    if let Some(header) = lines.peek() {
+        if !is_value(&header[0]) {
+            let names: Vec<String> = header.iter().map(|n| n.to_lowercase()).collect();
+            for (i, column) in columns.iter().enumerate() {
+                let Some(position) = names.iter().position(|n| n == column) else {
+                    bail!(UserError::BadRequest(format!(
+                        "The {} is missing its {} column.",
+                        what, column
+                    )));
+                };
+                positions[i] = position;
+            }
+            lines.next();
+        }
+    }
+
+    Ok(lines
+        .map(|fields| {
+            positions
+                .iter()
+                .map(|&p| fields.get(p).cloned().unwrap_or_default())
+                .collect()
+        })
+        .collect())
+}
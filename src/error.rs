@@ -0,0 +1,122 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use tracing::{error, info};
+
+/// Errors that deserve something other than a 500, because they're down to
+/// what was asked for or to Octopus rather than a bug here. Raise them with
+/// `bail!` like any other error, the server digs them out of the chain.
+#[derive(Debug)]
+pub enum UserError {
+    /// Something asked for doesn't exist, like a property or report
+    NotFound(String),
+    /// The details given don't make sense
+    BadRequest(String),
+    /// Octopus turned us down or fell over
+    Upstream(String),
+    /// Shutting down, so not taking on any more work
+    Unavailable(String),
+    TimedOut(String),
+}
+
+impl UserError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            UserError::NotFound(_) => StatusCode::NOT_FOUND,
+            UserError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            UserError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            UserError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            UserError::TimedOut(_) => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+}
+
+impl std::fmt::Display for UserError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UserError::NotFound(message)
+            | UserError::BadRequest(message)
+            | UserError::Upstream(message)
+            | UserError::Unavailable(message)
+            | UserError::TimedOut(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for UserError {}
+
+/// An error that's been held on to for reporting later, like a background
+/// job's, along with the status it would have been reported with at the time.
+#[derive(Debug, Clone)]
+pub struct StoredError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl From<&anyhow::Error> for StoredError {
+    fn from(error: &anyhow::Error) -> Self {
+        Self {
+            status: status_of(error),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for StoredError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for StoredError {}
+
+/// The status an error should be reported with.
+pub fn status_of(error: &anyhow::Error) -> StatusCode {
+    error
+        .chain()
+        .find_map(|e| {
+            e.downcast_ref::<UserError>()
+                .map(|e| e.status())
+                .or_else(|| e.downcast_ref::<StoredError>().map(|e| e.status))
+        })
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// What went wrong, left on the response for
+/// [`render_errors`](crate::middleware::render_errors) to show once
+/// it knows the correlation ID.
+#[derive(Clone)]
+pub(crate) struct ErrorDetail(pub(crate) String);
+
+// Make our own error that wraps `anyhow::Error`.
+pub struct AppError(pub anyhow::Error);
+
+// Tell axum how to convert `AppError` into a response.
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = status_of(&self.0);
+        if status.is_server_error() {
+            error!("{:#}", self.0);
+        } else {
+            info!("{}: {:#}", status, self.0);
+        }
+
+        let mut response = status.into_response();
+        response
+            .extensions_mut()
+            .insert(ErrorDetail(self.0.to_string()));
+        response
+    }
+}
+
+// This enables using `?` on functions that return `Result<_, anyhow::Error>` to turn them into
+// `Result<_, AppError>`. That way you don't need to do that manually.
+impl<E> From<E> for AppError
+where
+    E: Into<anyhow::Error>,
+{
+    fn from(err: E) -> Self {
+        Self(err.into())
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::ConsumptionDatum,
+    pricing::{local_date, Rates, TIMEZONE},
+};
+
+/// Products only worth comparing for people charging an EV at home.
+pub const EV_PRODUCTS: [&str; 2] = ["GO-VAR-22-10-14", "INTELLI-VAR-22-10-14"];
+
+/// Whether a product gives off-peak prices for slots it dispatches the car to
+/// charge in, on top of its fixed off-peak window.
+pub fn has_smart_dispatches(product_code: &str) -> bool {
+    product_code.starts_with("INTELLI-")
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct EvProfile {
+    pub battery_kwh: f64,
+    pub daily_miles: f64,
+    pub miles_per_kwh: f64,
+    pub charger_kw: f64,
+    /// UK local time the car gets plugged in each day
+    pub plug_in: NaiveTime,
+    /// UK local time the car needs to be charged by the next day
+    pub ready_by: NaiveTime,
+}
+
+impl EvProfile {
+    /// kWh put back in the battery each day, never more than it holds.
+    pub fn daily_energy(&self) -> f64 {
+        (self.daily_miles / self.miles_per_kwh).min(self.battery_kwh)
+    }
+
+    /// The half hours the car is plugged in, starting on `day`.
+    fn window(&self, day: NaiveDate) -> Vec<DateTime<Utc>> {
+        let Some(start) = local_instant(day, self.plug_in) else {
+            return vec![];
+        };
+        let end_day = if self.ready_by <= self.plug_in {
+            day.succ_opt().unwrap()
+        } else {
+            day
+        };
+        let Some(end) = local_instant(end_day, self.ready_by) else {
+            return vec![];
+        };
+
+        let mut slots = vec![];
+        let mut slot = start;
+        while slot < end {
+            slots.push(slot);
+            slot += Duration::minutes(30);
+        }
+        slots
+    }
+}
+
+fn local_instant(day: NaiveDate, time: NaiveTime) -> Option<DateTime<Utc>> {
+    day.and_time(time)
+        .and_local_timezone(TIMEZONE)
+        .earliest()
+        .map(|x| x.with_timezone(&Utc))
+}
+
+#[derive(Debug, Default)]
+pub struct EvCharging {
+    /// Household consumption with the car's charging added in
+    pub consumption: Vec<ConsumptionDatum>,
+    /// kWh the car drew over the whole period
+    pub energy: f64,
+    /// Slots outside the off-peak window the car was dispatched to charge in,
+    /// mapped to the off-peak rate they get billed at
+    pub dispatches: HashMap<DateTime<Utc>, f64>,
+}
+
+/// Smart charges the car each night in the cheapest slots of its plug-in
+/// window. Ties go to the earliest slot, which is roughly what Octopus does
+/// when it needs more than the off-peak window to fill the car.
+///
+/// When `dispatches` is set, any slot charged in that isn't already at the
+/// cheapest rate on offer is treated as a smart-charge dispatch and billed at
+/// that cheapest rate, house consumption included, like Intelligent Octopus Go.
+pub fn add_charging(
+    consumption: &[ConsumptionDatum],
+    rates: &dyn Rates,
+    ev: &EvProfile,
+    dispatches: bool,
+) -> EvCharging {
+    let mut charging = EvCharging {
+        consumption: consumption.to_vec(),
+        ..Default::default()
+    };
+    let by_slot: HashMap<DateTime<Utc>, usize> = charging
+        .consumption
+        .iter()
+        .enumerate()
+        .map(|(i, d)| (d.interval_start, i))
+        .collect();
+
+    let days: HashSet<NaiveDate> = consumption
+        .iter()
+        .map(|d| local_date(d.interval_start))
+        .collect();
+    let slot_energy = ev.charger_kw / 2.0;
+
+    for day in days {
+        // Only plan charging we've got readings to add it to
+        let mut window: Vec<(DateTime<Utc>, f64)> = ev
+            .window(day)
+            .into_iter()
+            .filter(|slot| by_slot.contains_key(slot))
+            .filter_map(|slot| rates.unit_rate(slot).map(|rate| (slot, rate)))
+            .collect();
+        let Some(off_peak) = window.iter().map(|(_, rate)| *rate).reduce(f64::min) else {
+            continue;
+        };
+        window.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+
+        let mut needed = ev.daily_energy();
+        for (slot, rate) in window {
+            if needed <= 0.0 {
+                break;
+            }
+            let energy = needed.min(slot_energy);
+            charging.consumption[by_slot[&slot]].consumption += energy;
+            charging.energy += energy;
+            needed -= energy;
+
+            if dispatches && rate > off_peak {
+                charging.dispatches.insert(slot, off_peak);
+            }
+        }
+    }
+
+    charging
+}
+
+/// Prices slots the car was dispatched in at the off-peak rate, and everything
+/// else as normal.
+pub struct DispatchedRates<'a> {
+    pub rates: &'a dyn Rates,
+    pub dispatches: &'a HashMap<DateTime<Utc>, f64>,
+}
+
+impl Rates for DispatchedRates<'_> {
+    fn unit_rate(&self, at: DateTime<Utc>) -> Option<f64> {
+        match self.dispatches.get(&at) {
+            Some(rate) => Some(*rate),
+            None => self.rates.unit_rate(at),
+        }
+    }
+
+    fn standing_charge(&self, day: NaiveDate) -> Option<f64> {
+        self.rates.standing_charge(day)
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::HashSet;
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc, Weekday};
+use futures::future::try_join_all;
+use tracing::info;
+
+use crate::{
+    api::{ConsumptionDatum, OctopusClient},
+    compare::{
+        find_property, import_meter_points, meter_point_consumption, property_region, AGILE_PRODUCT,
+    },
+    error::UserError,
+    pricing::{find_rate, half_hour_of_day, local_date, start_of_day, Rates},
+};
+
+#[derive(Debug)]
+pub struct ForecastRequest {
+    pub api_key: String,
+    pub account_number: String,
+    pub property_id: f64,
+    /// How long the appliance we're finding a cheap window for runs
+    pub appliance_hours: f64,
+}
+
+#[derive(Debug)]
+pub struct ForecastSlot {
+    pub start: DateTime<Utc>,
+    /// Pence/kWh (inc. VAT)
+    pub rate: f64,
+    /// Typical kWh for this half hour
+    pub consumption: f64,
+}
+
+impl ForecastSlot {
+    pub fn cost(&self) -> f64 {
+        self.rate * self.consumption
+    }
+}
+
+#[derive(Debug)]
+pub struct CheapestWindow {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub average_rate: f64,
+}
+
+#[derive(Debug)]
+pub struct Forecast {
+    pub date: NaiveDate,
+    pub tariff_code: String,
+    pub slots: Vec<ForecastSlot>,
+    /// Pence (inc. VAT)
+    pub standing_charge: f64,
+    /// Number of historical days the typical profile was built from
+    pub profile_days: usize,
+    /// Which days the profile was built from, e.g. "winter Tuesdays"
+    pub profile_description: String,
+    pub cheapest_window: Option<CheapestWindow>,
+}
+
+impl Forecast {
+    pub fn consumption(&self) -> f64 {
+        self.slots.iter().map(|s| s.consumption).sum()
+    }
+
+    pub fn total(&self) -> f64 {
+        self.standing_charge + self.slots.iter().map(|s| s.cost()).sum::<f64>()
+    }
+}
+
+fn season(month: u32) -> &'static str {
+    match month {
+        12 | 1 | 2 => "winter",
+        3..=5 => "spring",
+        6..=8 => "summer",
+        _ => "autumn",
+    }
+}
+
+/// Average consumption for each half hour of the day, over the days in the
+/// history that look most like `date`: same weekday and season if there are
+/// any, falling back to the same weekday, then to everything.
+pub fn typical_profile(
+    consumption: &[ConsumptionDatum],
+    date: NaiveDate,
+) -> ([f64; 48], usize, String) {
+    let weekday = date.weekday();
+    let season = season(date.month());
+
+    let same_weekday = |d: NaiveDate| d.weekday() == weekday;
+    let same_season = |d: NaiveDate| same_weekday(d) && self::season(d.month()) == season;
+    let any_day = |_: NaiveDate| true;
+    let filters: [(&dyn Fn(NaiveDate) -> bool, String); 3] = [
+        (
+            &same_season,
+            format!("{} {}s", season, weekday_name(weekday)),
+        ),
+        (&same_weekday, format!("{}s", weekday_name(weekday))),
+        (&any_day, "all days".to_string()),
+    ];
+
+    for (filter, description) in filters {
+        let mut totals = [0.0; 48];
+        let mut counts = [0usize; 48];
+        let mut days = HashSet::new();
+        for d in consumption {
+            let day = local_date(d.interval_start);
+            if !filter(day) {
+                continue;
+            }
+            let slot = half_hour_of_day(d.interval_start);
+            totals[slot] += d.consumption;
+            counts[slot] += 1;
+            days.insert(day);
+        }
+
+        if !days.is_empty() {
+            // Averaged per slot rather than per day, so the repeated hour on a
+            // clock change day doesn't get double weight
+            let mut profile = [0.0; 48];
+            for (average, (total, count)) in profile.iter_mut().zip(totals.iter().zip(counts)) {
+                if count > 0 {
+                    *average = total / count as f64;
+                }
+            }
+            return (profile, days.len(), description);
+        }
+    }
+
+    ([0.0; 48], 0, "no days".to_string())
+}
+
+fn weekday_name(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "Monday",
+        Weekday::Tue => "Tuesday",
+        Weekday::Wed => "Wednesday",
+        Weekday::Thu => "Thursday",
+        Weekday::Fri => "Friday",
+        Weekday::Sat => "Saturday",
+        Weekday::Sun => "Sunday",
+    }
+}
+
+/// The run of consecutive half hours with the lowest average rate.
+pub fn cheapest_window(slots: &[ForecastSlot], length: usize) -> Option<CheapestWindow> {
+    if length == 0 || slots.len() < length {
+        return None;
+    }
+
+    slots
+        .windows(length)
+        .map(|w| {
+            let average_rate = w.iter().map(|s| s.rate).sum::<f64>() / length as f64;
+            CheapestWindow {
+                start: w[0].start,
+                end: w[length - 1].start + Duration::minutes(30),
+                average_rate,
+            }
+        })
+        .min_by(|a, b| a.average_rate.total_cmp(&b.average_rate))
+}
+
+/// Projects what tomorrow would cost on Agile using the household's typical
+/// consumption. Agile prices for the next day come out in the afternoon, so
+/// before then this projects today instead.
+pub async fn forecast_agile(client: &OctopusClient, request: &ForecastRequest) -> Result<Forecast> {
+    let response = client
+        .get_account_details(&request.api_key, &request.account_number)
+        .await?;
+    let property = find_property(&response, request.property_id)?;
+    let tenancy = property.tenancy();
+    let import_points = import_meter_points(property);
+    let Some(region) = property_region(&import_points, tenancy.end()) else {
+        bail!(UserError::NotFound(
+            "We couldn't work out which region this property is in.".to_owned()
+        ));
+    };
+
+    let agile = client.get_product(AGILE_PRODUCT).await?;
+    let Some(tariff_code) = agile.electricity_tariff_code(region) else {
+        bail!(UserError::NotFound(
+            "Agile isn't available in this region.".to_owned()
+        ));
+    };
+
+    let today = local_date(chrono::offset::Utc::now());
+    let tomorrow = today.succ_opt().unwrap();
+    let (pricing, consumption) = tokio::try_join!(
+        client.get_pricing_between(
+            tariff_code,
+            start_of_day(today),
+            start_of_day(tomorrow.succ_opt().unwrap())
+        ),
+        try_join_all(import_points.iter().map(|emp| meter_point_consumption(
+            client,
+            &request.api_key,
+            emp,
+            &tenancy,
+            &()
+        )))
+    )?;
+
+    let tomorrow_published = find_rate(&pricing.unit_charges, start_of_day(tomorrow)).is_some();
+    let date = if tomorrow_published { tomorrow } else { today };
+    info!("Forecasting Agile costs for {} on {}", date, tariff_code);
+
+    // Meter points are separate supplies, so their typical days add up
+    let mut profile = [0.0; 48];
+    let mut profile_days = 0;
+    let mut profile_description = String::new();
+    for readings in &consumption {
+        let (p, days, description) = typical_profile(&readings.consumption.results, date);
+        for (total, x) in profile.iter_mut().zip(p) {
+            *total += x;
+        }
+        profile_days = profile_days.max(days);
+        profile_description = description;
+    }
+
+    let mut slots = vec![];
+    let mut start = start_of_day(date);
+    while start < start_of_day(date.succ_opt().unwrap()) {
+        if let Some(rate) = pricing.unit_rate(start) {
+            slots.push(ForecastSlot {
+                start,
+                rate,
+                consumption: profile[half_hour_of_day(start)],
+            });
+        }
+        start += Duration::minutes(30);
+    }
+
+    // Anything shorter than a half hour still needs one
+    let window_slots = ((request.appliance_hours * 2.0).round() as usize).max(1);
+    Ok(Forecast {
+        date,
+        tariff_code: tariff_code.to_owned(),
+        cheapest_window: cheapest_window(&slots, window_slots),
+        standing_charge: pricing.standing_charge(date).unwrap_or_default()
+            * import_points.len() as f64,
+        slots,
+        profile_days,
+        profile_description,
+    })
+}
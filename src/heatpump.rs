@@ -0,0 +1,600 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
+use futures::future::try_join_all;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::{agreement_at, ConsumptionDatum, OctopusClient, Period, TariffPricing},
+    compare::{
+        find_property, gas_consumption, import_meter_points, meter_point_consumption,
+        property_region, Progress, ReportProgress, AGILE_PRODUCT, FLEXIBLE_PRODUCT,
+    },
+    csv::read_columns,
+    error::UserError,
+    pricing::{half_hour_of_day, local_date, price, PricedConsumption, Rates},
+};
+
+pub const COSY_PRODUCT: &str = "COSY-22-12-08";
+
+/// Tariffs the heat pump gets priced on, Cosy first as it's the one made for them.
+const HEAT_PUMP_PRODUCTS: [&str; 3] = [COSY_PRODUCT, AGILE_PRODUCT, FLEXIBLE_PRODUCT];
+
+/// How far back to model the heat pump over.
+const DAYS_TO_CHECK: i64 = 365;
+
+/// Days warmer than this (°C) don't need any space heating.
+const DEGREE_DAY_BASE: f64 = 15.5;
+
+/// Hot water is needed whatever the weather, so this much of the annual heat
+/// demand is spread evenly over the year rather than by degree days.
+const HOT_WATER_SHARE: f64 = 0.15;
+
+/// How hard the heat pump works in each hour of the UK day, relative to the
+/// others: warming up for the morning and evening, ticking over through the
+/// day and turned down overnight.
+const HEATING_PROFILE: [f64; 24] = [
+    0.6, 0.6, 0.6, 0.6, 0.7, 1.2, 1.6, 1.6, 1.3, 1.0, 0.9, 0.8, 0.8, 0.8, 0.9, 1.0, 1.2, 1.4, 1.5,
+    1.5, 1.4, 1.2, 0.9, 0.7,
+];
+
+/// Share of each day's heat pump electricity moved into that day's cheapest
+/// half hours, by heating the house and hot water cylinder ahead of time.
+const PREHEAT_SHARE: f64 = 0.3;
+
+/// How many of the day's cheapest half hours the preheating goes into.
+/// Six hours, about two of Cosy's three hour cheap windows.
+const PREHEAT_SLOTS: usize = 12;
+
+/// A reasonably modern condensing boiler.
+const BOILER_EFFICIENCY: f64 = 0.9;
+
+/// Used when no curve is given, roughly what an air source heat pump
+/// manages with radiators at a 45°C flow temperature.
+pub const DEFAULT_COP_CURVE: &str = "-7:2.2, 2:2.8, 7:3.4, 12:4.0, 20:4.6";
+
+/// Coefficient of performance against outdoor temperature.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CopCurve {
+    /// (°C, COP), coldest first
+    pub points: Vec<(f64, f64)>,
+}
+
+impl CopCurve {
+    /// Reads `°C:COP` pairs, comma separated, e.g. `-7:2.2, 7:3.4`.
+    pub fn parse(value: &str) -> Result<Self> {
+        let mut points = vec![];
+        for pair in value.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            let parsed = pair
+                .split_once(':')
+                .and_then(|(t, cop)| Some((t.trim().parse().ok()?, cop.trim().parse().ok()?)));
+            match parsed {
+                Some((t, cop)) if cop >= 1.0 => points.push((t, cop)),
+                _ => bail!(UserError::BadRequest(format!(
+                    "{:?} in the COP curve should be a temperature and a COP of at least 1, like 7:3.4.",
+                    pair
+                ))),
+            }
+        }
+        if points.is_empty() {
+            bail!(UserError::BadRequest(
+                "The COP curve needs at least one temperature:COP pair.".to_owned()
+            ));
+        }
+        points.sort_by(|a: &(f64, f64), b| a.0.total_cmp(&b.0));
+        Ok(Self { points })
+    }
+
+    /// Interpolates between the points, holding the ends flat beyond them.
+    pub fn cop(&self, temperature: f64) -> f64 {
+        let first = self.points[0];
+        let last = self.points[self.points.len() - 1];
+        if temperature <= first.0 {
+            return first.1;
+        }
+        if temperature >= last.0 {
+            return last.1;
+        }
+        self.points
+            .windows(2)
+            .find(|w| temperature <= w[1].0)
+            .map(|w| {
+                let ((t0, c0), (t1, c1)) = (w[0], w[1]);
+                c0 + (c1 - c0) * (temperature - t0) / (t1 - t0)
+            })
+            .unwrap_or(last.1)
+    }
+}
+
+/// Mean outdoor temperature (°C) for each UK calendar day.
+pub type Weather = BTreeMap<NaiveDate, f64>;
+
+fn parse_day(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(value, "%d/%m/%Y"))
+        .ok()
+        .or_else(|| {
+            let at = DateTime::parse_from_rfc3339(value)
+                .map(|t| t.with_timezone(&Utc))
+                .or_else(|_| {
+                    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M").map(|t| t.and_utc())
+                })
+                .or_else(|_| {
+                    NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M").map(|t| t.and_utc())
+                })
+                .ok()?;
+            Some(local_date(at))
+        })
+}
+
+/// Reads outdoor temperatures from a weather CSV with `date` and
+/// `temperature` (°C) columns. Dates can be `2024-01-31` or `31/01/2024`, or
+/// times (UTC) for hourly data, which gets averaged over each day. Without a
+/// header row the columns are taken to be in that order.
+pub fn parse_weather(csv: &str) -> Result<Weather> {
+    let rows = read_columns(csv, "weather file", &["date", "temperature"], |first| {
+        parse_day(first).is_some()
+    })?;
+
+    let mut days: BTreeMap<NaiveDate, (f64, usize)> = BTreeMap::new();
+    for (i, row) in rows.iter().enumerate() {
+        let Some(date) = parse_day(&row[0]) else {
+            bail!(UserError::BadRequest(format!(
+                "Line {} of the weather file doesn't have a date.",
+                i + 1
+            )));
+        };
+        let Ok(temperature) = row[1].parse::<f64>() else {
+            bail!(UserError::BadRequest(format!(
+                "Line {} of the weather file doesn't have a temperature.",
+                i + 1
+            )));
+        };
+        let day = days.entry(date).or_default();
+        day.0 += temperature;
+        day.1 += 1;
+    }
+
+    if days.is_empty() {
+        bail!(UserError::BadRequest(
+            "There weren't any days in that weather file.".to_owned()
+        ));
+    }
+    Ok(days
+        .into_iter()
+        .map(|(date, (total, count))| (date, total / count as f64))
+        .collect())
+}
+
+/// Heat (kWh) needed on each day with weather, sharing out `annual_demand` by
+/// heating degree days. The weather's own average stands in for a year's
+/// worth of degree days, so it wants at least a year of it.
+pub fn daily_heat(weather: &Weather, annual_demand: f64) -> BTreeMap<NaiveDate, f64> {
+    let degree_days = |t: f64| (DEGREE_DAY_BASE - t).max(0.0);
+    let annual_degree_days =
+        weather.values().map(|t| degree_days(*t)).sum::<f64>() / weather.len() as f64 * 365.0;
+    let hot_water = annual_demand * HOT_WATER_SHARE / 365.0;
+    let space_heating = annual_demand * (1.0 - HOT_WATER_SHARE);
+
+    weather
+        .iter()
+        .map(|(date, t)| {
+            let heating = match annual_degree_days > 0.0 {
+                true => space_heating * degree_days(*t) / annual_degree_days,
+                false => 0.0,
+            };
+            (*date, hot_water + heating)
+        })
+        .collect()
+}
+
+/// Adds the heat pump's electricity to each day's readings, following
+/// [`HEATING_PROFILE`] over the half hours there are readings for. When
+/// `rates` price the whole day, [`PREHEAT_SHARE`] of it goes into the day's
+/// [`PREHEAT_SLOTS`] cheapest half hours instead, so tariffs with cheap
+/// windows get the credit a smart heat pump would earn on them.
+pub fn add_heat_pump(
+    consumption: &[ConsumptionDatum],
+    heat_pump: &BTreeMap<NaiveDate, f64>,
+    rates: &dyn Rates,
+) -> Vec<ConsumptionDatum> {
+    let mut days: BTreeMap<NaiveDate, Vec<usize>> = BTreeMap::new();
+    for (i, d) in consumption.iter().enumerate() {
+        days.entry(local_date(d.interval_start))
+            .or_default()
+            .push(i);
+    }
+
+    let mut results = consumption.to_vec();
+    for (date, slots) in days {
+        let Some(kwh) = heat_pump.get(&date) else {
+            continue;
+        };
+
+        let day_rates: Option<Vec<f64>> = slots
+            .iter()
+            .map(|&i| rates.unit_rate(consumption[i].interval_start))
+            .collect();
+        let preheat = match day_rates {
+            Some(_) => kwh * PREHEAT_SHARE,
+            None => 0.0,
+        };
+
+        let weight =
+            |i: usize| HEATING_PROFILE[half_hour_of_day(consumption[i].interval_start) / 2];
+        let total_weight: f64 = slots.iter().map(|&i| weight(i)).sum();
+        for &i in &slots {
+            results[i].consumption += (kwh - preheat) * weight(i) / total_weight;
+        }
+
+        if let Some(day_rates) = day_rates {
+            let mut cheapest: Vec<usize> = (0..slots.len()).collect();
+            cheapest.sort_by(|a, b| day_rates[*a].total_cmp(&day_rates[*b]));
+            cheapest.truncate(PREHEAT_SLOTS);
+            for j in &cheapest {
+                results[slots[*j]].consumption += preheat / cheapest.len() as f64;
+            }
+        }
+    }
+    results
+}
+
+/// Takes away the gas a boiler would have burnt to make `heat`, never leaving
+/// a day's readings below zero. Returns the kWh taken away as well.
+pub fn remove_gas(
+    consumption: &[ConsumptionDatum],
+    heat: &BTreeMap<NaiveDate, f64>,
+) -> (Vec<ConsumptionDatum>, f64) {
+    let mut days: BTreeMap<NaiveDate, f64> = BTreeMap::new();
+    for d in consumption {
+        *days.entry(local_date(d.interval_start)).or_default() += d.consumption;
+    }
+
+    let mut removed = 0.0;
+    let scale: BTreeMap<NaiveDate, f64> = days
+        .into_iter()
+        .map(|(date, used)| {
+            let burnt = heat.get(&date).copied().unwrap_or_default() / BOILER_EFFICIENCY;
+            let taken = burnt.min(used);
+            removed += taken;
+            let scale = match used > 0.0 {
+                true => 1.0 - taken / used,
+                false => 1.0,
+            };
+            (date, scale)
+        })
+        .collect();
+
+    let remaining = consumption
+        .iter()
+        .map(|d| {
+            let mut d = d.clone();
+            d.consumption *= scale[&local_date(d.interval_start)];
+            d
+        })
+        .collect();
+    (remaining, removed)
+}
+
+#[derive(Debug)]
+pub struct HeatPumpRequest {
+    pub api_key: String,
+    pub account_number: String,
+    pub property_id: f64,
+    /// kWh of heat a year, worked out from the gas readings when not given
+    pub heat_demand: Option<f64>,
+    pub cop_curve: CopCurve,
+    pub weather: Weather,
+    /// Most SMETS2 gas meters report m³ rather than kWh
+    pub gas_in_cubic_metres: bool,
+    /// Keep the gas supply (for cooking, say) rather than capping it
+    pub keep_gas: bool,
+}
+
+/// A year's bill one way or another.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HeatPumpBill {
+    pub tariff_code: String,
+    pub display_name: String,
+    /// Pence (inc. VAT)
+    pub electricity_cost: f64,
+    /// Pence (inc. VAT), nothing once the gas is capped
+    pub gas_cost: f64,
+    /// Set when prices didn't cover every reading
+    pub data_missing: bool,
+}
+
+impl HeatPumpBill {
+    pub fn total(&self) -> f64 {
+        self.electricity_cost + self.gas_cost
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HeatPumpComparison {
+    pub address: String,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    /// kWh of heat a year the model was run with
+    pub heat_demand: f64,
+    /// Set when the demand came from the gas readings
+    pub demand_from_gas: bool,
+    /// kWh of heat over the period
+    pub heat: f64,
+    /// kWh of electricity the heat pump took to make it
+    pub heat_pump_energy: f64,
+    /// kWh of gas the heat pump replaced
+    pub gas_removed: f64,
+    pub keep_gas: bool,
+    /// Days with readings but no weather, left out of every bill
+    pub days_without_weather: usize,
+    /// Electricity and gas as they are today
+    pub current: HeatPumpBill,
+    /// Electricity with the heat pump on each candidate tariff, cheapest first
+    pub options: Vec<HeatPumpBill>,
+}
+
+impl HeatPumpComparison {
+    /// Seasonal coefficient of performance over the period.
+    pub fn scop(&self) -> f64 {
+        match self.heat_pump_energy > 0.0 {
+            true => self.heat / self.heat_pump_energy,
+            false => 0.0,
+        }
+    }
+}
+
+fn on_days_with_weather(
+    consumption: Vec<ConsumptionDatum>,
+    weather: &Weather,
+) -> Vec<ConsumptionDatum> {
+    consumption
+        .into_iter()
+        .filter(|d| weather.contains_key(&local_date(d.interval_start)))
+        .collect()
+}
+
+fn cost(priced: &PricedConsumption) -> f64 {
+    priced.unit_cost() + priced.standing_cost()
+}
+
+/// Models a heat pump in place of the gas boiler over the last year, and
+/// prices the result on Cosy, Agile and Flexible against the electricity and
+/// gas tariffs the property is on now.
+pub async fn compare_heat_pump(
+    client: &OctopusClient,
+    request: &HeatPumpRequest,
+    progress: &dyn ReportProgress,
+) -> Result<HeatPumpComparison> {
+    progress.report(Progress::FetchingAccount);
+    let response = client
+        .get_account_details(&request.api_key, &request.account_number)
+        .await?;
+    let property = find_property(&response, request.property_id)?;
+    let tenancy = property.tenancy();
+    let import_points = import_meter_points(property);
+    let Some(region) = property_region(&import_points, tenancy.end()) else {
+        bail!(UserError::NotFound(
+            "We couldn't work out which region this property is in.".to_owned()
+        ));
+    };
+
+    let start = tenancy.end() - Duration::days(DAYS_TO_CHECK);
+    let period = Period {
+        from: Some(tenancy.from.map_or(start, |from| from.max(start))),
+        to: tenancy.to,
+    };
+
+    // Each import meter point is priced on its own tariff today
+    let mut current_codes = vec![];
+    for emp in &import_points {
+        let Some(agreement) = agreement_at(&emp.agreements, tenancy.end()) else {
+            bail!(UserError::NotFound(format!(
+                "We couldn't find the tariff MPAN {} is on.",
+                emp.mpan
+            )));
+        };
+        current_codes.push(agreement.tariff_code.clone());
+    }
+    let gas_code = property
+        .gas_meter_points
+        .iter()
+        .find_map(|gmp| agreement_at(&gmp.agreements, tenancy.end()))
+        .map(|a| a.tariff_code.clone());
+
+    let products = try_join_all(HEAT_PUMP_PRODUCTS.iter().map(|p| client.get_product(p)));
+    let electricity = try_join_all(
+        import_points
+            .iter()
+            .map(|emp| meter_point_consumption(client, &request.api_key, emp, &period, progress)),
+    );
+    let gas = gas_consumption(
+        client,
+        &request.api_key,
+        property,
+        &period,
+        request.gas_in_cubic_metres,
+        progress,
+    );
+    let (products, electricity, gas) = tokio::try_join!(products, electricity, gas)?;
+
+    let candidates: Vec<String> = products
+        .iter()
+        .filter_map(|p| p.electricity_tariff_code(region))
+        .map(|code| code.to_owned())
+        .collect();
+    let gas_code = match (gas_code, gas.is_empty()) {
+        (Some(code), _) => Some(code),
+        (None, true) => None,
+        (None, false) => bail!(UserError::NotFound(
+            "We couldn't find the gas tariff this property is on.".to_owned()
+        )),
+    };
+
+    let mut tariff_codes: Vec<String> = vec![];
+    for code in current_codes.iter().chain(&candidates).chain(&gas_code) {
+        if !tariff_codes.contains(code) {
+            tariff_codes.push(code.clone());
+        }
+    }
+    let pricing = try_join_all(tariff_codes.iter().map(|tariff_code| async move {
+        progress.report(Progress::FetchingRates {
+            tariff_code: tariff_code.clone(),
+        });
+        client.get_pricing_during(tariff_code, &period).await
+    }))
+    .await?;
+    let pricing_for = |code: &str| -> Arc<TariffPricing> {
+        pricing
+            .iter()
+            .find(|p| p.tariff_code == code)
+            .expect("Every tariff code was priced")
+            .clone()
+    };
+
+    progress.report(Progress::Computing);
+    let electricity: Vec<Vec<ConsumptionDatum>> = electricity
+        .into_iter()
+        .map(|r| r.consumption.results)
+        .collect();
+    if electricity.first().is_none_or(|r| r.is_empty()) {
+        bail!(UserError::NotFound(
+            "There aren't any half-hourly electricity readings to add a heat pump to.".to_owned()
+        ));
+    }
+    let readings_days = |readings: &[ConsumptionDatum]| -> Vec<NaiveDate> {
+        readings
+            .iter()
+            .map(|d| local_date(d.interval_start))
+            .collect()
+    };
+    let mut all_days: Vec<NaiveDate> = electricity
+        .iter()
+        .flat_map(|r| readings_days(r))
+        .chain(readings_days(&gas))
+        .collect();
+    all_days.sort();
+    all_days.dedup();
+    let days_without_weather = all_days
+        .iter()
+        .filter(|d| !request.weather.contains_key(d))
+        .count();
+
+    let electricity: Vec<Vec<ConsumptionDatum>> = electricity
+        .into_iter()
+        .map(|r| on_days_with_weather(r, &request.weather))
+        .collect();
+    let gas = on_days_with_weather(gas, &request.weather);
+    let days: Vec<NaiveDate> = all_days
+        .into_iter()
+        .filter(|d| request.weather.contains_key(d))
+        .collect();
+    let (Some(from), Some(to)) = (days.first().copied(), days.last().copied()) else {
+        bail!(UserError::BadRequest(
+            "The weather file doesn't cover any of the days there are readings for.".to_owned()
+        ));
+    };
+
+    let demand_from_gas = request.heat_demand.is_none();
+    let heat_demand = match request.heat_demand {
+        Some(demand) => demand,
+        None => {
+            let gas_used: f64 = gas.iter().map(|d| d.consumption).sum();
+            if gas_used == 0.0 {
+                bail!(UserError::BadRequest(
+                    "There aren't any gas readings to work out your heat demand from, so give us one."
+                        .to_owned()
+                ));
+            }
+            gas_used * BOILER_EFFICIENCY * 365.0 / days.len() as f64
+        }
+    };
+
+    let weather: Weather = days.iter().map(|d| (*d, request.weather[d])).collect();
+    let heat: BTreeMap<NaiveDate, f64> = daily_heat(&request.weather, heat_demand)
+        .into_iter()
+        .filter(|(date, _)| weather.contains_key(date))
+        .collect();
+    let heat_pump: BTreeMap<NaiveDate, f64> = heat
+        .iter()
+        .map(|(date, kwh)| (*date, kwh / request.cop_curve.cop(weather[date])))
+        .collect();
+
+    let gas_cost = |readings: &[ConsumptionDatum]| match &gas_code {
+        Some(code) => price(readings, pricing_for(code).as_ref()),
+        None => PricedConsumption::default(),
+    };
+    let current_gas = gas_cost(&gas);
+    let (remaining_gas, gas_removed) = remove_gas(&gas, &heat);
+    let gas_removed = match request.keep_gas {
+        true => gas_removed,
+        false => gas.iter().map(|d| d.consumption).sum(),
+    };
+    let remaining_gas = match request.keep_gas {
+        true => gas_cost(&remaining_gas),
+        false => PricedConsumption::default(),
+    };
+
+    let mut current = HeatPumpBill {
+        tariff_code: current_codes.join(" + "),
+        display_name: String::new(),
+        electricity_cost: 0.0,
+        gas_cost: cost(&current_gas),
+        data_missing: current_gas.data_missing,
+    };
+    let mut names = vec![];
+    for (code, readings) in current_codes.iter().zip(&electricity) {
+        let pricing = pricing_for(code);
+        let priced = price(readings, pricing.as_ref());
+        current.electricity_cost += cost(&priced);
+        current.data_missing |= priced.data_missing;
+        if !names.contains(&pricing.display_name) {
+            names.push(pricing.display_name.clone());
+        }
+    }
+    current.display_name = names.join(" + ");
+
+    // The heat pump only gets wired into one meter point, so stick it on the first
+    let mut options: Vec<HeatPumpBill> = candidates
+        .iter()
+        .map(|code| {
+            let pricing = pricing_for(code);
+            let mut bill = HeatPumpBill {
+                tariff_code: code.clone(),
+                display_name: pricing.display_name.clone(),
+                electricity_cost: 0.0,
+                gas_cost: cost(&remaining_gas),
+                data_missing: remaining_gas.data_missing,
+            };
+            for (i, readings) in electricity.iter().enumerate() {
+                let priced = match i {
+                    0 => {
+                        let readings = add_heat_pump(readings, &heat_pump, pricing.as_ref());
+                        price(&readings, pricing.as_ref())
+                    }
+                    _ => price(readings, pricing.as_ref()),
+                };
+                bill.electricity_cost += cost(&priced);
+                bill.data_missing |= priced.data_missing;
+            }
+            bill
+        })
+        .collect();
+    options.sort_by(|a, b| a.total().total_cmp(&b.total()));
+
+    Ok(HeatPumpComparison {
+        address: format!("{}, {}", property.address_line_1, property.postcode),
+        from,
+        to,
+        heat_demand,
+        demand_from_gas,
+        heat: heat.values().sum(),
+        heat_pump_energy: heat_pump.values().sum(),
+        gas_removed,
+        keep_gas: request.keep_gas,
+        days_without_weather,
+        current,
+        options,
+    })
+}
@@ -0,0 +1,193 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    compare::{Progress, ReportProgress},
+    error::StoredError,
+};
+
+/// How long finished jobs get to be collected when draining. Pages poll every
+/// second, so anything left after this has probably had its tab closed.
+const UNCLAIMED_WAIT: Duration = Duration::from_secs(5);
+
+/// How long a finished job sticks around after it's done, so a page that
+/// polls again (a retry, or a second tab) still gets the result. Jobs nobody
+/// ever polls go after this too.
+const FINISHED_TTL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Clone)]
+pub enum JobState<T> {
+    Running,
+    Complete(T),
+    Failed(StoredError),
+}
+
+pub struct Job<T> {
+    pub events: Vec<Progress>,
+    pub state: JobState<T>,
+}
+
+struct Entry<T> {
+    job: Job<T>,
+    finished_at: Option<Instant>,
+    /// Whether the final result has been handed out at least once
+    claimed: bool,
+}
+
+/// In-memory store of comparisons running in the background. Finished jobs
+/// are kept for [`FINISHED_TTL`], then swept out whether or not anyone came
+/// back for them.
+pub struct JobStore<T> {
+    jobs: Arc<Mutex<HashMap<String, Entry<T>>>>,
+}
+
+// Derives would want `T: Clone + Default`, which results don't need to be
+impl<T> Clone for JobStore<T> {
+    fn clone(&self) -> Self {
+        Self {
+            jobs: self.jobs.clone(),
+        }
+    }
+}
+
+impl<T> Default for JobStore<T> {
+    fn default() -> Self {
+        Self {
+            jobs: Default::default(),
+        }
+    }
+}
+
+impl<T: Clone + Send + 'static> JobStore<T> {
+    pub fn spawn<F, Fut>(&self, work: F) -> String
+    where
+        F: FnOnce(JobHandle<T>) -> Fut,
+        Fut: Future<Output = anyhow::Result<T>> + Send + 'static,
+    {
+        self.sweep();
+        let id = Uuid::new_v4().to_string();
+        self.jobs.lock().unwrap().insert(
+            id.clone(),
+            Entry {
+                job: Job {
+                    events: vec![],
+                    state: JobState::Running,
+                },
+                finished_at: None,
+                claimed: false,
+            },
+        );
+
+        let handle = JobHandle {
+            id: id.clone(),
+            store: self.clone(),
+        };
+        let fut = work(handle.clone());
+        tokio::spawn(async move {
+            let state = match fut.await {
+                Ok(result) => JobState::Complete(result),
+                Err(e) => {
+                    error!("Job {} failed: {}", handle.id, e);
+                    JobState::Failed(StoredError::from(&e))
+                }
+            };
+            info!("Job {} finished", handle.id);
+            if let Some(entry) = handle.store.jobs.lock().unwrap().get_mut(&handle.id) {
+                entry.job.state = state;
+                entry.finished_at = Some(Instant::now());
+            }
+        });
+
+        id
+    }
+
+    /// How many jobs haven't finished yet.
+    pub fn running(&self) -> usize {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.values().filter(|e| e.finished_at.is_none()).count()
+    }
+
+    /// Drops jobs that finished more than [`FINISHED_TTL`] ago.
+    pub fn sweep(&self) {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.retain(|id, entry| {
+            let expired = entry
+                .finished_at
+                .is_some_and(|at| at.elapsed() >= FINISHED_TTL);
+            if expired && !entry.claimed {
+                info!("Job {} was never collected", id);
+            }
+            !expired
+        });
+    }
+
+    /// Waits for running jobs to finish and hand out their results, for up to
+    /// `grace`. Returns how many were still running when it gave up.
+    pub async fn drain(&self, grace: Duration) -> usize {
+        let started = Instant::now();
+        let mut idle_since = None;
+        loop {
+            let running = self.running();
+            let unclaimed = self.jobs.lock().unwrap().values().any(|e| !e.claimed);
+            if !unclaimed {
+                return 0;
+            }
+            if running == 0 {
+                let since = *idle_since.get_or_insert_with(Instant::now);
+                if since.elapsed() >= UNCLAIMED_WAIT {
+                    return 0;
+                }
+            }
+            if started.elapsed() >= grace {
+                return running;
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    }
+
+    /// Gives back the progress so far for a running job, or its result once
+    /// it has finished.
+    pub fn poll(&self, id: &str) -> Option<Job<T>> {
+        self.sweep();
+        let mut jobs = self.jobs.lock().unwrap();
+        let entry = jobs.get_mut(id)?;
+        if entry.finished_at.is_some() {
+            entry.claimed = true;
+        }
+        Some(Job {
+            events: entry.job.events.clone(),
+            state: entry.job.state.clone(),
+        })
+    }
+}
+
+pub struct JobHandle<T> {
+    id: String,
+    store: JobStore<T>,
+}
+
+impl<T> Clone for JobHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            id: self.id.clone(),
+            store: self.store.clone(),
+        }
+    }
+}
+
+impl<T: Send> ReportProgress for JobHandle<T> {
+    fn report(&self, progress: Progress) {
+        info!("Job {}: {}", self.id, progress);
+        if let Some(entry) = self.store.jobs.lock().unwrap().get_mut(&self.id) {
+            entry.job.events.push(progress);
+        }
+    }
+}
@@ -0,0 +1,326 @@
+use std::time::Instant;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use tracing::{error, info, trace};
+
+use crate::{api::ClientSettings, error::UserError, metrics};
+
+/// Kraken tokens last an hour, refresh a little before that so one doesn't
+/// expire mid-request.
+const TOKEN_MARGIN: Duration = Duration::minutes(2);
+
+/// The error code Kraken gives for a token that's run out.
+const EXPIRED_TOKEN: &str = "KT-CT-1124";
+
+#[derive(Debug, Clone)]
+struct Token {
+    token: String,
+    expires_at: DateTime<Utc>,
+    refresh_token: String,
+    refresh_expires_at: DateTime<Utc>,
+}
+
+/// Octopus' GraphQL API, which has things the REST one doesn't, like bills.
+/// Every request needs a Kraken token, which is obtained from the API key and
+/// refreshed as needed, so keep one client per account around rather than
+/// making one per query.
+pub struct KrakenClient {
+    client: reqwest::Client,
+    url: String,
+    api_key: String,
+    token: Mutex<Option<Token>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQLResponse<T> {
+    data: Option<T>,
+    #[serde(default)]
+    errors: Vec<GraphQLError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQLError {
+    message: String,
+    #[serde(default)]
+    extensions: Option<Value>,
+}
+
+impl GraphQLError {
+    fn code(&self) -> Option<&str> {
+        self.extensions.as_ref()?.get("errorCode")?.as_str()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ObtainedToken {
+    token: String,
+    refresh_token: String,
+    /// Unix timestamp
+    refresh_expires_in: i64,
+    payload: TokenPayload,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenPayload {
+    /// Unix timestamp
+    exp: i64,
+}
+
+/// Pence, as Kraken sends them.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BillTotal {
+    pub net_total: Option<i64>,
+    pub gross_total: Option<i64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Bill {
+    pub id: String,
+    pub bill_type: Option<String>,
+    pub from_date: Option<NaiveDate>,
+    pub to_date: Option<NaiveDate>,
+    pub issued_date: Option<NaiveDate>,
+    pub total_charges: Option<BillTotal>,
+    pub total_credits: Option<BillTotal>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AccountBills {
+    /// Pence, positive when in credit
+    pub balance: i64,
+    pub bills: Vec<Bill>,
+}
+
+const OBTAIN_TOKEN: &str = "mutation ObtainToken($input: ObtainJSONWebTokenInput!) {
+  obtainKrakenToken(input: $input) { token refreshToken refreshExpiresIn payload }
+}";
+
+const BILLS: &str = "query Bills($accountNumber: String!, $first: Int!) {
+  account(accountNumber: $accountNumber) {
+    balance
+    bills(first: $first) {
+      edges { node { id billType fromDate toDate issuedDate
+        ... on StatementType {
+          totalCharges { netTotal grossTotal }
+          totalCredits { netTotal grossTotal }
+        }
+      } }
+    }
+  }
+}";
+
+impl KrakenClient {
+    /// The GraphQL endpoint lives alongside the REST API configured in
+    /// `settings`.
+    pub fn new(settings: &ClientSettings, api_key: &str) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(settings.timeout)
+                .build()
+                .expect("Unable to build HTTP client"),
+            url: format!("{}/graphql/", settings.base_url.trim_end_matches('/')),
+            api_key: api_key.to_owned(),
+            token: Mutex::new(None),
+        }
+    }
+
+    async fn post<T: DeserializeOwned>(
+        &self,
+        operation: &'static str,
+        query: &str,
+        variables: Value,
+        token: Option<&str>,
+    ) -> Result<GraphQLResponse<T>> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .json(&json!({ "query": query, "variables": variables }));
+        if let Some(token) = token {
+            request = request.header("Authorization", token);
+        }
+
+        let started = Instant::now();
+        let response = request.send().await;
+        let status = match &response {
+            Ok(response) => response.status().as_str().to_owned(),
+            Err(_) => "error".to_owned(),
+        };
+        trace!(
+            target: metrics::TARGET,
+            metric = "octopus_request",
+            endpoint = operation,
+            status,
+            seconds = started.elapsed().as_secs_f64()
+        );
+
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                error!("GraphQL {} failed: {}", operation, e);
+                bail!(UserError::Upstream(
+                    "We couldn't reach Octopus, try again in a bit.".to_owned()
+                ));
+            }
+        };
+        // GraphQL errors come back as 200s, anything else is the server
+        if !response.status().is_success() {
+            let body = response.text().await?;
+            error!("GraphQL {} failed: {}", operation, body);
+            bail!(UserError::Upstream("Unexpected error from API.".to_owned()));
+        }
+        Ok(response.json().await?)
+    }
+
+    async fn obtain_token(&self, input: Value) -> Result<Token> {
+        let response: GraphQLResponse<Value> = self
+            .post(
+                "obtainKrakenToken",
+                OBTAIN_TOKEN,
+                json!({ "input": input }),
+                None,
+            )
+            .await?;
+        if let Some(e) = response.errors.first() {
+            error!("Unable to get a Kraken token: {}", e.message);
+            bail!(UserError::BadRequest(
+                "Octopus didn't accept that API key.".to_owned()
+            ));
+        }
+        let obtained: ObtainedToken = serde_json::from_value(
+            response
+                .data
+                .and_then(|mut d| d.get_mut("obtainKrakenToken").map(Value::take))
+                .context("No token in Kraken's response")?,
+        )?;
+
+        Ok(Token {
+            token: obtained.token,
+            expires_at: DateTime::from_timestamp(obtained.payload.exp, 0)
+                .context("Kraken token has a bad expiry")?,
+            refresh_token: obtained.refresh_token,
+            refresh_expires_at: DateTime::from_timestamp(obtained.refresh_expires_in, 0)
+                .context("Kraken refresh token has a bad expiry")?,
+        })
+    }
+
+    /// A token that's good for a while yet, refreshing or getting a new one
+    /// if needed.
+    async fn token(&self) -> Result<String> {
+        let mut token = self.token.lock().await;
+        let now = chrono::offset::Utc::now();
+
+        match token.as_ref() {
+            Some(t) if t.expires_at - TOKEN_MARGIN > now => return Ok(t.token.clone()),
+            Some(t) if t.refresh_expires_at - TOKEN_MARGIN > now => {
+                info!("Refreshing Kraken token");
+                let refresh_token = t.refresh_token.clone();
+                match self
+                    .obtain_token(json!({ "refreshToken": refresh_token }))
+                    .await
+                {
+                    Ok(refreshed) => *token = Some(refreshed),
+                    // Fall back to the API key, which is what the refresh came from anyway
+                    Err(e) => {
+                        info!("Unable to refresh Kraken token, getting a new one: {:#}", e);
+                        *token = Some(self.obtain_token(json!({ "APIKey": self.api_key })).await?);
+                    }
+                }
+            }
+            _ => {
+                info!("Getting a Kraken token");
+                *token = Some(self.obtain_token(json!({ "APIKey": self.api_key })).await?);
+            }
+        }
+        Ok(token.as_ref().unwrap().token.clone())
+    }
+
+    /// Runs a query, getting a new token and trying once more if Kraken
+    /// says ours has expired.
+    pub async fn query<T: DeserializeOwned>(
+        &self,
+        operation: &'static str,
+        query: &str,
+        variables: Value,
+    ) -> Result<T> {
+        let mut retried = false;
+        loop {
+            let token = self.token().await?;
+            let response: GraphQLResponse<T> = self
+                .post(operation, query, variables.clone(), Some(&token))
+                .await?;
+
+            if response
+                .errors
+                .iter()
+                .any(|e| e.code() == Some(EXPIRED_TOKEN))
+                && !retried
+            {
+                info!("Kraken token expired early, getting another");
+                *self.token.lock().await = None;
+                retried = true;
+                continue;
+            }
+            if !response.errors.is_empty() {
+                let messages: Vec<&str> =
+                    response.errors.iter().map(|e| e.message.as_str()).collect();
+                error!("GraphQL {} failed: {}", operation, messages.join("; "));
+                bail!(UserError::Upstream(format!(
+                    "Octopus couldn't answer that: {}",
+                    messages.join("; ")
+                )));
+            }
+            return response
+                .data
+                .with_context(|| format!("No data in the response to {}", operation));
+        }
+    }
+
+    /// The account's balance and its most recent `first` bills, newest first.
+    pub async fn bills(&self, account_number: &str, first: usize) -> Result<AccountBills> {
+        #[derive(Deserialize)]
+        struct Response {
+            account: Account,
+        }
+        #[derive(Deserialize)]
+        struct Account {
+            balance: i64,
+            bills: Connection<Bill>,
+        }
+
+        info!("Fetching bills for {}", account_number);
+        let response: Response = self
+            .query(
+                "bills",
+                BILLS,
+                json!({ "accountNumber": account_number, "first": first }),
+            )
+            .await?;
+        Ok(AccountBills {
+            balance: response.account.balance,
+            bills: response.account.bills.nodes(),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct Connection<T> {
+    edges: Vec<Edge<T>>,
+}
+
+#[derive(Deserialize)]
+struct Edge<T> {
+    node: T,
+}
+
+impl<T> Connection<T> {
+    fn nodes(self) -> Vec<T> {
+        self.edges.into_iter().map(|e| e.node).collect()
+    }
+}
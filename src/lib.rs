@@ -1,2 +1,24 @@
+pub mod alerts;
+pub mod api;
+pub mod assets;
+pub mod carbon;
+pub mod compare;
+pub mod config;
+pub mod credentials;
+pub mod csv;
+pub mod error;
+pub mod ev;
+pub mod forecast;
+pub mod heatpump;
+pub mod jobs;
+pub mod kraken;
+pub mod metrics;
+pub mod middleware;
+pub mod pricing;
+pub mod profile;
+pub mod quality;
+pub mod reconcile;
+pub mod reports;
+pub mod tariff;
 pub mod ui;
-pub mod api;
\ No newline at end of file
+pub mod workspace;
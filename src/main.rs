@@ -1,50 +1,330 @@
 use anyhow::Result;
 use axum::{
-    http::StatusCode,
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware,
     response::{IntoResponse, Response},
-    routing::{get, post},
+    routing::{delete, get, post},
     Form, Router,
 };
 
-use chrono::Days;
+use chrono::{DateTime, NaiveTime, Utc};
+use clap::Parser;
 use maud::{html, Markup};
 use octocompare::{
-    api::{
-        get_account_details, get_consumption_data, get_pricing, AccountProperty, AccountResponse,
-        MeterInfo,
+    alerts::{
+        check_email, check_webhook, new_subscription_id, Scheduler, SchedulerHandle, Subscription,
+        SubscriptionStore,
     },
-    ui::home::{account_details, welcome},
+    api::{AccountProperty, AccountResponse, ClientSettings, OctopusClient},
+    assets::get_asset,
+    carbon::{carbon_report, parse_intensity, CarbonClient, CarbonReport, CarbonRequest},
+    compare::{compare_tariffs, CompareRequest, Comparison},
+    config::{Cli, Config, Features},
+    credentials::{CredentialErrors, Credentials},
+    error::{AppError, UserError},
+    ev::EvProfile,
+    forecast::{forecast_agile, ForecastRequest},
+    heatpump::{compare_heat_pump, parse_weather, CopCurve, HeatPumpComparison, HeatPumpRequest},
+    jobs::{JobState, JobStore},
+    kraken::KrakenClient,
+    metrics::{self, MetricsLayer},
+    middleware::{enforce_timeout, record_request, render_errors},
+    reconcile::{parse_statement, reconcile_account, Reconciliation},
+    reports::{report_cookies, saved_reports, ReportStore, SavedReport, REPORT_COOKIE},
+    tariff::{load_definitions, TariffDefinition},
+    ui::{
+        carbon::carbon_result,
+        compare::{comparison_progress, comparison_result},
+        forecast::forecast_result,
+        heatpump::heat_pump_result,
+        home::{account_details, credential_fields, welcome, CREDENTIAL_FIELDS},
+        reconcile::reconcile_result,
+        reports::{history, report_link, report_page},
+        subscriptions::subscribed,
+        workspace::{batch_result, workspace, workspace_account},
+    },
+    workspace::{compare_batch, PropertyOutcome},
 };
 use serde::Deserialize;
-use std::cmp::Ordering;
-use tower_http::services::ServeDir;
-use tracing::info;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tracing::{error, info, warn};
+use tracing_subscriber::{filter, layer::SubscriberExt, util::SubscriberInitExt, Layer};
 
 #[tokio::main]
 async fn main() {
+    let mut cli = Cli::parse();
+    // Still honour RUST_LOG, below the flag and OCTOCOMPARE_LOG
+    cli.log_filter = cli.log_filter.or(std::env::var("RUST_LOG").ok());
+
+    // Bad config is the one thing worth refusing to start over
+    let config = match Config::load(cli) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
     tracing_subscriber::registry()
         .with(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-                // axum logs rejections from built-in extractors with the `axum::rejection`
-                // target, at `TRACE` level. `axum::rejection=trace` enables showing those events
-                "octocompare=debug,axum::rejection=trace".into()
-            }),
+            tracing_subscriber::fmt::layer()
+                .with_filter(tracing_subscriber::EnvFilter::new(&config.log_filter)),
         )
-        .with(tracing_subscriber::fmt::layer())
+        .with(MetricsLayer.with_filter(filter::filter_fn(|m| m.target() == metrics::TARGET)))
         .init();
 
-    let app = Router::new()
-        .route("/", get(welcome))
+    let definitions =
+        load_definitions(&config.tariffs_dir).expect("Unable to load tariff definitions");
+    let definitions = Arc::new(definitions);
+    let reports = ReportStore::new(config.reports_dir());
+    let subscriptions = SubscriptionStore::new(config.subscriptions_file());
+    let client = config.octopus.client();
+
+    let scheduler = config.features.alerts.then(|| {
+        Scheduler {
+            subscriptions: subscriptions.clone(),
+            reports: reports.clone(),
+            definitions: definitions.clone(),
+            smtp: config.smtp.clone(),
+            client: client.clone(),
+            products: config.products.clone(),
+            base_url: config.base_url.clone(),
+        }
+        .spawn(std::time::Duration::from_secs(60 * 60))
+    });
+
+    let features = config.features;
+    let state = AppState {
+        jobs: JobStore::default(),
+        batches: JobStore::default(),
+        reconciliations: JobStore::default(),
+        carbon_reports: JobStore::default(),
+        heat_pump_jobs: JobStore::default(),
+        reports,
+        subscriptions,
+        email_alerts: config.smtp.is_some(),
+        definitions,
+        octopus: config.octopus.clone(),
+        client,
+        carbon_url: config.carbon_url.clone(),
+        products: Arc::new(config.products.clone()),
+        features,
+        shutting_down: Arc::default(),
+    };
+    let shutdown = drain(state.clone(), scheduler, config.shutdown_grace);
+
+    let mut app = Router::new()
+        .route("/", get(get_welcome))
         .route("/account-details", post(post_get_account))
         .route("/compare-tariffs", post(post_compare_tariffs))
-        .nest_service("/assets", ServeDir::new("assets"));
+        .route("/jobs/:job_id", get(get_job));
+    if features.forecast {
+        app = app.route("/forecast", post(post_forecast));
+    }
+    if features.workspace {
+        app = app
+            .route("/workspace", get(workspace))
+            .route("/workspace/accounts", post(post_workspace_account))
+            .route("/workspace/compare", post(post_workspace_compare))
+            .route("/workspace/jobs/:job_id", get(get_batch_job));
+    }
+    if features.reports {
+        app = app
+            .route("/reports", get(get_reports))
+            .route("/reports/:report_id", get(get_report).delete(delete_report));
+    }
+    if features.reconcile {
+        app = app
+            .route("/reconcile", post(post_reconcile))
+            .route("/reconcile/jobs/:job_id", get(get_reconcile_job));
+    }
+    if features.carbon {
+        app = app
+            .route("/carbon", post(post_carbon))
+            .route("/carbon/jobs/:job_id", get(get_carbon_job));
+    }
+    if features.heat_pump {
+        app = app
+            .route("/heat-pump", post(post_heat_pump))
+            .route("/heat-pump/jobs/:job_id", get(get_heat_pump_job));
+    }
+    if features.alerts {
+        app = app.route("/subscriptions", post(post_subscription)).route(
+            "/subscriptions/:subscription_id",
+            delete(delete_subscription),
+        );
+    }
+    let app = app
+        .route("/assets/:name", get(get_asset))
+        .route("/healthz", get(|| async { "ok" }))
+        .route("/readyz", get(get_readyz))
+        .route("/metrics", get(get_metrics))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::new(config.timeouts.clone()),
+            enforce_timeout,
+        ))
+        .route_layer(middleware::from_fn(record_request))
+        .with_state(state)
+        .layer(middleware::from_fn(render_errors));
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
+    let listener = tokio::net::TcpListener::bind(config.bind).await.unwrap();
+    tracing::debug!("listening on {}", listener.local_addr().unwrap());
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown)
         .await
         .unwrap();
-    tracing::debug!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+    info!("Stopped");
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Unable to listen for Ctrl-C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Unable to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Once told to stop, turns away new comparisons and waits for the running
+/// ones to finish and be collected, along with any scheduled comparison. The
+/// server keeps answering until this returns, so pages can still poll for
+/// their results, then axum drains whatever requests are left.
+async fn drain(state: AppState, scheduler: Option<SchedulerHandle>, grace: std::time::Duration) {
+    shutdown_signal().await;
+    info!("Shutting down, waiting up to {:?} for comparisons", grace);
+    state.shutting_down.store(true, Ordering::Relaxed);
+
+    let (jobs, batches, reconciliations, carbon_reports, heat_pump_jobs, scheduler) = tokio::join!(
+        state.jobs.drain(grace),
+        state.batches.drain(grace),
+        state.reconciliations.drain(grace),
+        state.carbon_reports.drain(grace),
+        state.heat_pump_jobs.drain(grace),
+        async {
+            match scheduler {
+                Some(scheduler) => scheduler.stop(grace).await,
+                None => true,
+            }
+        }
+    );
+    if !scheduler {
+        warn!("Gave up waiting on a scheduled comparison");
+    }
+    let running = jobs + batches + reconciliations + carbon_reports + heat_pump_jobs;
+    if running > 0 {
+        warn!(
+            "Gave up waiting on {} comparisons that were still running",
+            running
+        );
+    }
+}
+
+/// A finished comparison, and the report it was saved as if it was.
+#[derive(Clone)]
+struct Compared {
+    comparison: Comparison,
+    report: Option<SavedReport>,
+}
+
+#[derive(Clone)]
+struct AppState {
+    jobs: JobStore<Compared>,
+    batches: JobStore<Vec<PropertyOutcome>>,
+    reconciliations: JobStore<Reconciliation>,
+    carbon_reports: JobStore<CarbonReport>,
+    heat_pump_jobs: JobStore<HeatPumpComparison>,
+    reports: ReportStore,
+    subscriptions: SubscriptionStore,
+    email_alerts: bool,
+    definitions: Arc<Vec<TariffDefinition>>,
+    octopus: ClientSettings,
+    /// Shared by every comparison, so products and prices are only fetched
+    /// once an hour rather than once a comparison
+    client: OctopusClient,
+    carbon_url: String,
+    products: Arc<Vec<String>>,
+    features: Features,
+    /// Set once a shutdown has started, after which no new jobs are taken on
+    shutting_down: Arc<AtomicBool>,
+}
+
+impl AppState {
+    fn accepting_jobs(&self) -> Result<(), AppError> {
+        if self.shutting_down.load(Ordering::Relaxed) {
+            return Err(UserError::Unavailable(
+                "We're restarting, give it a minute and try again.".to_owned(),
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Ready once reports can be saved and sign-ups can be read.
+async fn get_readyz(State(state): State<AppState>) -> Response {
+    let check = async {
+        if state.features.reports {
+            state.reports.check().await?;
+        }
+        if state.features.alerts {
+            state.subscriptions.all().await?;
+        }
+        anyhow::Ok(())
+    };
+    match check.await {
+        Ok(()) => "ready".into_response(),
+        Err(e) => {
+            error!("Not ready: {:#}", e);
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("not ready: {:#}", e),
+            )
+                .into_response()
+        }
+    }
+}
+
+async fn get_metrics(State(state): State<AppState>) -> Response {
+    let body = metrics::render(&[
+        ("compare", state.jobs.running()),
+        ("workspace", state.batches.running()),
+        ("reconcile", state.reconciliations.running()),
+        ("carbon", state.carbon_reports.running()),
+        ("heat_pump", state.heat_pump_jobs.running()),
+    ]);
+    (
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        body,
+    )
+        .into_response()
+}
+
+async fn get_welcome(State(state): State<AppState>) -> Markup {
+    welcome(state.features)
 }
 
 #[derive(Deserialize)]
@@ -53,21 +333,64 @@ struct AccountDetails {
     account_number: String,
 }
 
-async fn post_get_account(Form(details): Form<AccountDetails>) -> Result<Markup, AppError> {
-    let response: AccountResponse =
-        get_account_details(&details.api_key, &details.account_number).await?;
+/// Sends the account number and API key fields back with what's wrong with
+/// them, in place of the originals.
+fn invalid_credentials(details: &AccountDetails, errors: &CredentialErrors) -> Response {
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        [
+            ("HX-Retarget", CREDENTIAL_FIELDS),
+            ("HX-Reswap", "outerHTML"),
+        ],
+        credential_fields(&details.account_number, &details.api_key, errors, false),
+    )
+        .into_response()
+}
+
+/// For forms that carry credentials over from an earlier step, where there's
+/// no field to show the problem next to.
+fn credentials(account_number: &str, api_key: &str) -> Result<Credentials, AppError> {
+    Credentials::parse(account_number, api_key)
+        .map_err(|e| UserError::BadRequest(e.to_string()).into())
+}
 
-    let active_properties: Vec<&AccountProperty> = response
+async fn post_get_account(
+    State(state): State<AppState>,
+    Form(details): Form<AccountDetails>,
+) -> Result<Response, AppError> {
+    let credentials = match Credentials::parse(&details.account_number, &details.api_key) {
+        Ok(credentials) => credentials,
+        Err(errors) => return Ok(invalid_credentials(&details, &errors)),
+    };
+    let response: AccountResponse = state
+        .client
+        .get_account_details(&credentials.api_key, &credentials.account_number)
+        .await?;
+
+    Ok(html! {
+        (account_details(
+            properties(&response),
+            &credentials.api_key,
+            &credentials.account_number,
+            state.features,
+        ))
+        // Clears any errors from a previous go
+        (credential_fields(&credentials.account_number, &credentials.api_key, &CredentialErrors::default(), true))
+    }
+    .into_response())
+}
+
+/// Every property the account has lived at, current ones first then the most
+/// recently left.
+fn properties(response: &AccountResponse) -> Vec<&AccountProperty> {
+    let mut properties: Vec<&AccountProperty> = response
         .properties
         .iter()
-        .filter(|p| p.moved_in_at < chrono::offset::Utc::now() && p.moved_out_at == None)
-        .collect::<Vec<&AccountProperty>>();
-
-    Ok(account_details(
-        active_properties,
-        &details.api_key,
-        &details.account_number,
-    ))
+        .filter(|p| p.moved_in_at < chrono::offset::Utc::now())
+        .collect();
+    properties
+        .sort_by_key(|p| std::cmp::Reverse(p.moved_out_at.unwrap_or(DateTime::<Utc>::MAX_UTC)));
+    properties
 }
 
 #[derive(Deserialize)]
@@ -75,161 +398,618 @@ struct CompareTariffRequest {
     api_key: String,
     account_number: String,
     property_id: f64,
+    ev: Option<String>,
+    // Only needed when modelling an EV, so they can be left blank otherwise
+    ev_battery_kwh: Option<String>,
+    ev_daily_miles: Option<String>,
+    ev_miles_per_kwh: Option<String>,
+    ev_charger_kw: Option<String>,
+    ev_plug_in: Option<String>,
+    ev_ready_by: Option<String>,
+    #[serde(default)]
+    custom_tariff: String,
+    fill_gaps: Option<String>,
 }
+
 async fn post_compare_tariffs(
+    State(state): State<AppState>,
     Form(details): Form<CompareTariffRequest>,
 ) -> Result<Markup, AppError> {
-    let response: AccountResponse =
-        get_account_details(&details.api_key, &details.account_number).await?;
+    state.accepting_jobs()?;
+    let ev = match details.ev {
+        Some(_) => Some(EvProfile {
+            battery_kwh: parse_positive(required(&details.ev_battery_kwh, "battery size")?)?,
+            daily_miles: parse_positive(required(&details.ev_daily_miles, "miles per day")?)?,
+            miles_per_kwh: parse_positive(required(&details.ev_miles_per_kwh, "miles per kWh")?)?,
+            charger_kw: parse_positive(required(&details.ev_charger_kw, "charger power")?)?,
+            plug_in: parse_time(required(&details.ev_plug_in, "plug in time")?)?,
+            ready_by: parse_time(required(&details.ev_ready_by, "ready by time")?)?,
+        }),
+        None => None,
+    };
 
-    let property: Option<&AccountProperty> = response
-        .properties
-        .iter()
-        .filter(|p| p.id == details.property_id)
-        .next();
+    let mut definitions = state.definitions.to_vec();
+    if !details.custom_tariff.trim().is_empty() {
+        let definition = TariffDefinition::parse(&details.custom_tariff).map_err(|e| {
+            UserError::BadRequest(format!("We couldn't read that tariff definition: {:#}", e))
+        })?;
+        definitions.push(definition);
+    }
+
+    let credentials = credentials(&details.account_number, &details.api_key)?;
+    let request = CompareRequest {
+        api_key: credentials.api_key,
+        account_number: credentials.account_number,
+        property_id: details.property_id,
+        ev,
+        definitions,
+        fill_gaps: details.fill_gaps.is_some(),
+        products: state.products.to_vec(),
+    };
+
+    let client = state.client.session();
+    let reports = state.features.reports.then(|| state.reports.clone());
+    let job_id = state.jobs.spawn(|handle| async move {
+        let comparison = compare_tariffs(&client, &request, &handle).await?;
+        // Not being able to save shouldn't lose the result as well
+        let report = match &reports {
+            Some(reports) => save_report(reports, &comparison).await,
+            None => None,
+        };
+        Ok(Compared { comparison, report })
+    });
+    info!("Started comparison job {}", job_id);
+
+    Ok(comparison_progress(&format!("/jobs/{}", job_id), &[]))
+}
+
+#[derive(Deserialize)]
+struct ForecastForm {
+    api_key: String,
+    account_number: String,
+    property_id: f64,
+    appliance_hours: String,
+}
 
-    if let None = property {
-        return Ok(html! { p { "Hmmmm... This is embarrassing, we couldn't find that property." }});
+async fn post_forecast(
+    State(state): State<AppState>,
+    Form(details): Form<ForecastForm>,
+) -> Result<Markup, AppError> {
+    let appliance_hours = parse_positive(&details.appliance_hours)?;
+    if appliance_hours > 24.0 {
+        return Err(UserError::BadRequest(
+            "The appliance needs to finish within a day.".to_owned(),
+        )
+        .into());
     }
+    let credentials = credentials(&details.account_number, &details.api_key)?;
+    let request = ForecastRequest {
+        api_key: credentials.api_key,
+        account_number: credentials.account_number,
+        property_id: details.property_id,
+        appliance_hours,
+    };
+    let forecast = forecast_agile(&state.client.session(), &request).await?;
 
-    let property = property.unwrap();
+    Ok(forecast_result(&forecast))
+}
 
-    for emp in &property.electricity_meter_points {
-        info!("Processing MPAN: {}", emp.mpan);
-        if !emp.is_export {
-            let consumption_data = get_consumption_data(
-                &details.api_key,
-                MeterInfo::Electricity(emp.meters[0].serial_number.clone(), emp.mpan.clone()),
-            )
-            .await?;
-
-            let agreement = emp
-                .agreements
-                .iter()
-                .filter(|a| {
-                    a.valid_from <= chrono::offset::Utc::now()
-                        && a.valid_to >= chrono::offset::Utc::now()
-                })
-                .next();
-
-            if let Some(agreement) = agreement {
-                let price_info = get_pricing(&agreement.tariff_code).await?;
-
-                let mut total_unit = 0.0;
-                let mut total_standing = 0.0;
-                let mut data_missing = false;
-                let mut earliest_date: Option<chrono::DateTime<chrono::Utc>> = None;
-                // Given that consumption and pricing date are both ordered, I could be a lot smarter about this and keep
-                // indexes into each array
-                for d in &consumption_data.results {
-                    let index = price_info.unit_charges.binary_search_by(|x| {
-                        if d.interval_start < x.valid_from {
-                            return Ordering::Less;
-                        }
-                        if let Some(end) = x.valid_to {
-                            if d.interval_start > end {
-                                return Ordering::Greater;
-                            }
-                            if end < d.interval_end {
-                                return Ordering::Less;
-                            }
-                        }
-                        return Ordering::Equal;
-                    });
-
-                    if let Ok(i) = index {
-                        total_unit += price_info.unit_charges[i].value_inc_vat * d.consumption;
-                        earliest_date = Some(d.interval_start);
-                    } else {
-                        data_missing = true;
-                    }
+async fn get_job(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Response, AppError> {
+    poll_job(&state.jobs, "/jobs", &job_id, |compared| {
+        (
+            report_cookies(&compared.report),
+            html! {
+                (comparison_result(&compared.comparison))
+                @if let Some(report) = &compared.report {
+                    (report_link(&report.id))
                 }
+            },
+        )
+    })
+}
 
-                if let Some(latest_date) = &consumption_data
-                    .results
-                    .iter()
-                    .map(|x| x.interval_end)
-                    .next()
-                {
-                    if let Some(mut working_date) = earliest_date {
-                        loop {
-                            if working_date.cmp(latest_date) == Ordering::Greater {
-                                break;
-                            }
-
-                            let index = price_info.standing_charges.binary_search_by(|x| {
-                                if working_date < x.valid_from {
-                                    return Ordering::Less;
-                                }
-                                if let Some(end) = x.valid_to {
-                                    if working_date > end {
-                                        return Ordering::Greater;
-                                    }
-                                    if end < working_date {
-                                        return Ordering::Less;
-                                    }
-                                }
-                                return Ordering::Equal;
-                            });
-
-                            if let Ok(i) = index {
-                                total_standing += price_info.standing_charges[i].value_inc_vat;
-                            }
-
-                            working_date = working_date.checked_add_days(Days::new(1)).unwrap();
-                        }
-                    }
-                }
+#[derive(Deserialize)]
+struct ReconcileForm {
+    api_key: String,
+    account_number: String,
+    property_id: f64,
+    fill_gaps: Option<String>,
+    #[serde(default)]
+    statement: String,
+    gas_m3: Option<String>,
+}
 
-                let info = format!(
-                    "Total price from {:?} to {:?} is £{:.2} for consumption and £{:.2} in standing charges{}",
-                    match earliest_date {
-                        Some(x) => x.to_string(),
-                        None => "unknown".to_string(),
-                    },
-                    &consumption_data
-                        .results
-                        .iter()
-                        .map(|x| x.interval_end.to_string())
-                        .next()
-                        .unwrap_or("unknown".to_string()),
-                    total_unit / 100.0,
-                    total_standing / 100.0,
-                    if data_missing {
-                        " (pricing data does not cover the whole consumption period)"
-                    } else {
-                        ""
-                    }
-                );
-                return Ok(html! { p { (info) }});
+async fn post_reconcile(
+    State(state): State<AppState>,
+    Form(details): Form<ReconcileForm>,
+) -> Result<Markup, AppError> {
+    state.accepting_jobs()?;
+    let statement = match details.statement.trim() {
+        "" => None,
+        csv => Some(parse_statement(csv)?),
+    };
+    let gas_in_cubic_metres = details.gas_m3.is_some();
+
+    let credentials = credentials(&details.account_number, &details.api_key)?;
+    let kraken = KrakenClient::new(&state.octopus, &credentials.api_key);
+    let request = CompareRequest {
+        api_key: credentials.api_key,
+        account_number: credentials.account_number,
+        property_id: details.property_id,
+        ev: None,
+        definitions: state.definitions.to_vec(),
+        fill_gaps: details.fill_gaps.is_some(),
+        products: state.products.to_vec(),
+    };
+
+    let client = state.client.session();
+    let job_id = state.reconciliations.spawn(|handle| async move {
+        reconcile_account(
+            &client,
+            &kraken,
+            &request,
+            statement,
+            gas_in_cubic_metres,
+            &handle,
+        )
+        .await
+    });
+    info!("Started reconciliation job {}", job_id);
+
+    Ok(comparison_progress(
+        &format!("/reconcile/jobs/{}", job_id),
+        &[],
+    ))
+}
+
+async fn get_reconcile_job(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Response, AppError> {
+    poll_job(
+        &state.reconciliations,
+        "/reconcile/jobs",
+        &job_id,
+        reconcile_result,
+    )
+}
+
+#[derive(Deserialize)]
+struct CarbonForm {
+    api_key: String,
+    account_number: String,
+    property_id: f64,
+    flexible_percent: f64,
+    #[serde(default)]
+    intensity: String,
+}
+
+async fn post_carbon(
+    State(state): State<AppState>,
+    Form(details): Form<CarbonForm>,
+) -> Result<Markup, AppError> {
+    state.accepting_jobs()?;
+    if !(0.0..=100.0).contains(&details.flexible_percent) {
+        return Err(UserError::BadRequest(
+            "The share of use that could move needs to be between 0 and 100%.".to_owned(),
+        )
+        .into());
+    }
+    let uploaded = match details.intensity.trim() {
+        "" => None,
+        csv => Some(parse_intensity(csv)?),
+    };
+
+    let credentials = credentials(&details.account_number, &details.api_key)?;
+    let request = CarbonRequest {
+        api_key: credentials.api_key,
+        account_number: credentials.account_number,
+        property_id: details.property_id,
+        flexible_share: details.flexible_percent / 100.0,
+    };
+
+    let client = state.client.session();
+    let carbon = CarbonClient::new(&state.carbon_url, state.octopus.timeout);
+    let job_id = state.carbon_reports.spawn(|handle| async move {
+        carbon_report(&client, &carbon, &request, uploaded, &handle).await
+    });
+    info!("Started carbon job {}", job_id);
+
+    Ok(comparison_progress(
+        &format!("/carbon/jobs/{}", job_id),
+        &[],
+    ))
+}
+
+async fn get_carbon_job(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Response, AppError> {
+    poll_job(
+        &state.carbon_reports,
+        "/carbon/jobs",
+        &job_id,
+        carbon_result,
+    )
+}
+
+#[derive(Deserialize)]
+struct HeatPumpForm {
+    api_key: String,
+    account_number: String,
+    property_id: f64,
+    #[serde(default)]
+    heat_demand: String,
+    cop_curve: String,
+    #[serde(default)]
+    weather: String,
+    gas_m3: Option<String>,
+    keep_gas: Option<String>,
+}
+
+async fn post_heat_pump(
+    State(state): State<AppState>,
+    Form(details): Form<HeatPumpForm>,
+) -> Result<Markup, AppError> {
+    state.accepting_jobs()?;
+    let heat_demand = match details.heat_demand.trim() {
+        "" => None,
+        demand => match demand.parse::<f64>() {
+            Ok(kwh) if kwh > 0.0 => Some(kwh),
+            _ => {
+                return Err(UserError::BadRequest(format!(
+                    "{} isn't a yearly heat demand in kWh",
+                    demand
+                ))
+                .into())
             }
+        },
+    };
+    if details.weather.trim().is_empty() {
+        return Err(UserError::BadRequest(
+            "We need outdoor temperatures to work out how hard the heat pump works.".to_owned(),
+        )
+        .into());
+    }
+
+    let credentials = credentials(&details.account_number, &details.api_key)?;
+    let request = HeatPumpRequest {
+        api_key: credentials.api_key,
+        account_number: credentials.account_number,
+        property_id: details.property_id,
+        heat_demand,
+        cop_curve: CopCurve::parse(&details.cop_curve)?,
+        weather: parse_weather(&details.weather)?,
+        gas_in_cubic_metres: details.gas_m3.is_some(),
+        keep_gas: details.keep_gas.is_some(),
+    };
+
+    let client = state.client.session();
+    let job_id = state
+        .heat_pump_jobs
+        .spawn(|handle| async move { compare_heat_pump(&client, &request, &handle).await });
+    info!("Started heat pump job {}", job_id);
+
+    Ok(comparison_progress(
+        &format!("/heat-pump/jobs/{}", job_id),
+        &[],
+    ))
+}
+
+async fn get_heat_pump_job(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Response, AppError> {
+    poll_job(
+        &state.heat_pump_jobs,
+        "/heat-pump/jobs",
+        &job_id,
+        heat_pump_result,
+    )
+}
+
+async fn post_workspace_account(
+    State(state): State<AppState>,
+    Form(details): Form<AccountDetails>,
+) -> Result<Response, AppError> {
+    let credentials = match Credentials::parse(&details.account_number, &details.api_key) {
+        Ok(credentials) => credentials,
+        Err(errors) => return Ok(invalid_credentials(&details, &errors)),
+    };
+    let response = state
+        .client
+        .get_account_details(&credentials.api_key, &credentials.account_number)
+        .await?;
+
+    Ok(html! {
+        (workspace_account(
+            properties(&response),
+            &credentials.api_key,
+            &credentials.account_number,
+        ))
+        // Empties the fields, ready for the next account
+        (credential_fields("", "", &CredentialErrors::default(), true))
+    }
+    .into_response())
+}
+
+#[derive(Deserialize)]
+struct WorkspaceCompareRequest {
+    /// `account_number:api_key` for every account in the workspace
+    #[serde(default)]
+    account: Vec<String>,
+    /// `account_number:property_id` for every ticked property
+    #[serde(default)]
+    property: Vec<String>,
+    fill_gaps: Option<String>,
+}
+
+async fn post_workspace_compare(
+    State(state): State<AppState>,
+    axum_extra::extract::Form(details): axum_extra::extract::Form<WorkspaceCompareRequest>,
+) -> Result<Markup, AppError> {
+    state.accepting_jobs()?;
+    let keys: HashMap<&str, &str> = details
+        .account
+        .iter()
+        .filter_map(|a| a.split_once(':'))
+        .collect();
+
+    let mut requests: Vec<CompareRequest> = vec![];
+    for property in &details.property {
+        let Some((account_number, property_id)) = property.split_once(':') else {
+            continue;
+        };
+        let property_id: f64 = property_id.parse().map_err(|_| {
+            UserError::BadRequest(format!("{} isn't a property we know about", property_id))
+        })?;
+        let Some(api_key) = keys.get(account_number) else {
+            continue;
+        };
+        let Credentials {
+            account_number,
+            api_key,
+        } = credentials(account_number, api_key)?;
+        // The same account can get added to the workspace twice
+        if requests
+            .iter()
+            .any(|r| r.account_number == account_number && r.property_id == property_id)
+        {
+            continue;
         }
+        requests.push(CompareRequest {
+            api_key,
+            account_number,
+            property_id,
+            ev: None,
+            definitions: state.definitions.to_vec(),
+            fill_gaps: details.fill_gaps.is_some(),
+            products: state.products.to_vec(),
+        });
+    }
+
+    if requests.is_empty() {
+        return Err(UserError::BadRequest(
+            "Add an account and tick some properties first.".to_owned(),
+        )
+        .into());
     }
 
-    Ok(html! { p { "No consumption or pricing data was found." }})
+    let client = state.client.session();
+    let reports = state.features.reports.then(|| state.reports.clone());
+    let job_id = state.batches.spawn(|handle| async move {
+        let mut outcomes = compare_batch(&client, requests, &handle).await?;
+        if let Some(reports) = &reports {
+            for outcome in outcomes.iter_mut() {
+                if let Ok(comparison) = &outcome.result {
+                    outcome.report = save_report(reports, comparison).await;
+                }
+            }
+        }
+        Ok(outcomes)
+    });
+    info!("Started batch comparison job {}", job_id);
+
+    Ok(comparison_progress(
+        &format!("/workspace/jobs/{}", job_id),
+        &[],
+    ))
 }
 
-// Make our own error that wraps `anyhow::Error`.
-struct AppError(anyhow::Error);
+async fn get_batch_job(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Response, AppError> {
+    poll_job(&state.batches, "/workspace/jobs", &job_id, |outcomes| {
+        let saved: Vec<_> = outcomes.iter().filter_map(|o| o.report.clone()).collect();
+        (report_cookies(&saved), batch_result(outcomes))
+    })
+}
 
-// Tell axum how to convert `AppError` into a response.
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        (
-            StatusCode::OK,
-            html!(p { "Something went wrong: " (self.0) }),
+/// Answers a page polling a background job, with its progress while it runs
+/// and then whatever `render` makes of the result. `path` is where the job's
+/// route lives, e.g. `/jobs`.
+fn poll_job<T: Clone + Send + 'static, R: IntoResponse>(
+    jobs: &JobStore<T>,
+    path: &str,
+    job_id: &str,
+    render: impl FnOnce(&T) -> R,
+) -> Result<Response, AppError> {
+    let Some(job) = jobs.poll(job_id) else {
+        return Err(UserError::NotFound(
+            "Hmmmm... This is embarrassing, we lost track of that comparison.".to_owned(),
         )
-            .into_response()
+        .into());
+    };
+    match job.state {
+        JobState::Running => {
+            Ok(comparison_progress(&format!("{}/{}", path, job_id), &job.events).into_response())
+        }
+        JobState::Complete(result) => Ok(render(&result).into_response()),
+        JobState::Failed(e) => Err(e.into()),
     }
 }
 
-// This enables using `?` on functions that return `Result<_, anyhow::Error>` to turn them into
-// `Result<_, AppError>`. That way you don't need to do that manually.
-impl<E> From<E> for AppError
-where
-    E: Into<anyhow::Error>,
-{
-    fn from(err: E) -> Self {
-        Self(err.into())
+async fn save_report(reports: &ReportStore, comparison: &Comparison) -> Option<SavedReport> {
+    match reports.save(comparison.clone()).await {
+        Ok(report) => Some(report.saved()),
+        Err(e) => {
+            error!("Unable to save report: {:#}", e);
+            None
+        }
     }
 }
+
+async fn get_reports(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Markup, AppError> {
+    let saved = saved_reports(&headers);
+    let ids: Vec<&str> = saved.keys().map(|id| id.as_str()).collect();
+    Ok(history(&state.reports.list(&ids).await))
+}
+
+async fn get_report(
+    State(state): State<AppState>,
+    Path(report_id): Path<String>,
+) -> Result<Markup, AppError> {
+    match state.reports.load(&report_id).await? {
+        Some(report) => Ok(report_page(&report)),
+        None => {
+            Err(UserError::NotFound("Hmmmm... We couldn't find that report.".to_owned()).into())
+        }
+    }
+}
+
+async fn delete_report(
+    State(state): State<AppState>,
+    Path(report_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let token = saved_reports(&headers)
+        .remove(&report_id)
+        .unwrap_or_default();
+    if !state.reports.delete(&report_id, &token).await? {
+        return Err(UserError::NotFound(
+            "We couldn't find that report, or it wasn't saved from this browser.".to_owned(),
+        )
+        .into());
+    }
+    let forget = format!("{}{}=; Path=/; Max-Age=0", REPORT_COOKIE, report_id);
+    // Swaps out the row it was in
+    Ok(([(header::SET_COOKIE, forget)], html! {}).into_response())
+}
+
+#[derive(Deserialize)]
+struct SubscriptionForm {
+    api_key: String,
+    account_number: String,
+    property_id: f64,
+    fill_gaps: Option<String>,
+    alert_threshold: Option<String>,
+    #[serde(default)]
+    alert_webhook: String,
+    #[serde(default)]
+    alert_email: String,
+}
+
+async fn post_subscription(
+    State(state): State<AppState>,
+    Form(details): Form<SubscriptionForm>,
+) -> Result<Markup, AppError> {
+    let non_empty = |x: String| Some(x.trim().to_owned()).filter(|x| !x.is_empty());
+    let webhook = non_empty(details.alert_webhook);
+    let email = non_empty(details.alert_email);
+    if webhook.is_none() && email.is_none() {
+        return Err(UserError::BadRequest(
+            "Give us a webhook or an email address to send alerts to.".to_owned(),
+        )
+        .into());
+    }
+    if email.is_some() && !state.email_alerts {
+        return Err(UserError::BadRequest(
+            "This server isn't set up to send email, use a webhook instead.".to_owned(),
+        )
+        .into());
+    }
+    if let Some(email) = &email {
+        check_email(email).map_err(|e| {
+            UserError::BadRequest(format!(
+                "We can't send alerts to that email address: {:#}",
+                e
+            ))
+        })?;
+    }
+    if let Some(webhook) = &webhook {
+        check_webhook(webhook).await.map_err(|e| {
+            UserError::BadRequest(format!("We can't send alerts to that webhook: {:#}", e))
+        })?;
+    }
+    let threshold = required(&details.alert_threshold, "saving to alert on")?;
+    let threshold = match threshold.parse::<f64>() {
+        Ok(pounds) if pounds.is_finite() && pounds >= 0.0 => pounds,
+        _ => {
+            return Err(
+                UserError::BadRequest(format!("{} isn't an amount in pounds", threshold)).into(),
+            )
+        }
+    };
+
+    let credentials = credentials(&details.account_number, &details.api_key)?;
+    let subscription = Subscription {
+        id: new_subscription_id(),
+        api_key: credentials.api_key,
+        account_number: credentials.account_number,
+        property_id: details.property_id,
+        fill_gaps: details.fill_gaps.is_some(),
+        threshold,
+        webhook,
+        email,
+        // They've just seen a comparison, so the first re-run is in a month
+        last_run: chrono::offset::Utc::now(),
+    };
+    state.subscriptions.add(subscription.clone()).await?;
+
+    Ok(subscribed(&subscription))
+}
+
+async fn delete_subscription(
+    State(state): State<AppState>,
+    Path(subscription_id): Path<String>,
+) -> Result<Markup, AppError> {
+    if !state.subscriptions.remove(&subscription_id).await? {
+        return Err(UserError::NotFound(
+            "We couldn't find that subscription, it may have been cancelled already.".to_owned(),
+        )
+        .into());
+    }
+    Ok(html!(p ."mt-2" { "Done, no more alerts." }))
+}
+
+/// A form field that has to be filled in, `what` naming it if it isn't.
+fn required<'a>(value: &'a Option<String>, what: &str) -> Result<&'a str, AppError> {
+    match value.as_deref().map(str::trim) {
+        Some(value) if !value.is_empty() => Ok(value),
+        _ => Err(UserError::BadRequest(format!("Fill in the {} as well.", what)).into()),
+    }
+}
+
+/// Reads a number off a form that only makes sense above zero, like a
+/// battery size.
+fn parse_positive(value: &str) -> Result<f64, AppError> {
+    match value.parse::<f64>() {
+        Ok(x) if x.is_finite() && x > 0.0 => Ok(x),
+        _ => {
+            Err(UserError::BadRequest(format!("{} needs to be a number above zero", value)).into())
+        }
+    }
+}
+
+/// Reads an `HH:MM` time off a form.
+fn parse_time(value: &str) -> Result<NaiveTime, AppError> {
+    NaiveTime::parse_from_str(value, "%H:%M")
+        .map_err(|_| UserError::BadRequest(format!("{} isn't an HH:MM time", value)).into())
+}
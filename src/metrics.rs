@@ -0,0 +1,264 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Mutex, OnceLock},
+};
+
+use tracing::{
+    field::{Field, Visit},
+    Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+/// Events with this target get counted by [`MetricsLayer`] rather than
+/// logged.
+pub const TARGET: &str = "octocompare::metrics";
+
+/// Upper bounds in seconds, from a quick page load up to a slow Octopus
+/// consumption download.
+const BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// Label values, in the order the metric's labels are named.
+type Labels = Vec<String>;
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        for (bucket, le) in self.buckets.iter_mut().zip(BUCKETS) {
+            if seconds <= le {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    http_requests: BTreeMap<Labels, u64>,
+    http_durations: BTreeMap<Labels, Histogram>,
+    octopus_requests: BTreeMap<Labels, u64>,
+    octopus_durations: BTreeMap<Labels, Histogram>,
+    cache_lookups: BTreeMap<Labels, u64>,
+}
+
+fn registry() -> &'static Mutex<Registry> {
+    static REGISTRY: OnceLock<Mutex<Registry>> = OnceLock::new();
+    REGISTRY.get_or_init(Default::default)
+}
+
+/// Fields pulled off a metrics event.
+#[derive(Default)]
+struct Fields {
+    metric: String,
+    method: String,
+    route: String,
+    endpoint: String,
+    status: String,
+    cache: String,
+    hit: bool,
+    seconds: f64,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        let value = value.to_owned();
+        match field.name() {
+            "metric" => self.metric = value,
+            "method" => self.method = value,
+            "route" => self.route = value,
+            "endpoint" => self.endpoint = value,
+            "status" => self.status = value,
+            "cache" => self.cache = value,
+            _ => {}
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if field.name() == "status" {
+            self.status = value.to_string();
+        }
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if field.name() == "seconds" {
+            self.seconds = value;
+        }
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        if field.name() == "hit" {
+            self.hit = value;
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value));
+    }
+}
+
+/// Turns `metric = "..."` events into counters and histograms, so anything
+/// that can log can be measured. Filter it to [`TARGET`].
+pub struct MetricsLayer;
+
+impl<S: Subscriber> Layer<S> for MetricsLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+
+        let mut registry = registry().lock().unwrap();
+        match fields.metric.as_str() {
+            "http_request" => {
+                *registry
+                    .http_requests
+                    .entry(vec![
+                        fields.method.clone(),
+                        fields.route.clone(),
+                        fields.status,
+                    ])
+                    .or_default() += 1;
+                registry
+                    .http_durations
+                    .entry(vec![fields.method, fields.route])
+                    .or_default()
+                    .observe(fields.seconds);
+            }
+            "octopus_request" => {
+                *registry
+                    .octopus_requests
+                    .entry(vec![fields.endpoint.clone(), fields.status])
+                    .or_default() += 1;
+                registry
+                    .octopus_durations
+                    .entry(vec![fields.endpoint])
+                    .or_default()
+                    .observe(fields.seconds);
+            }
+            "cache_lookup" => {
+                let result = if fields.hit { "hit" } else { "miss" };
+                *registry
+                    .cache_lookups
+                    .entry(vec![fields.cache, result.to_owned()])
+                    .or_default() += 1;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn label_set(names: &[&str], values: &[String]) -> String {
+    let labels: Vec<String> = names
+        .iter()
+        .zip(values)
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape_label(value)))
+        .collect();
+    labels.join(",")
+}
+
+/// Escapes a label value the way the Prometheus text format wants.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn write_counter(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    names: &[&str],
+    values: &BTreeMap<Labels, u64>,
+) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+    for (labels, value) in values {
+        let _ = writeln!(out, "{}{{{}}} {}", name, label_set(names, labels), value);
+    }
+}
+
+fn write_histogram(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    names: &[&str],
+    values: &BTreeMap<Labels, Histogram>,
+) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} histogram", name, help, name);
+    for (labels, histogram) in values {
+        let labels = label_set(names, labels);
+        for (le, count) in BUCKETS.iter().zip(histogram.buckets) {
+            let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, le, count);
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{},le=\"+Inf\"}} {}",
+            name, labels, histogram.count
+        );
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, histogram.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, histogram.count);
+    }
+}
+
+/// Everything recorded so far in Prometheus' text format, plus the number of
+/// jobs running right now for each kind of job.
+pub fn render(jobs_running: &[(&str, usize)]) -> String {
+    let registry = registry().lock().unwrap();
+    let mut out = String::new();
+    write_counter(
+        &mut out,
+        "octocompare_http_requests_total",
+        "Requests handled, by route and status.",
+        &["method", "route", "status"],
+        &registry.http_requests,
+    );
+    write_histogram(
+        &mut out,
+        "octocompare_http_request_duration_seconds",
+        "Time taken to handle requests.",
+        &["method", "route"],
+        &registry.http_durations,
+    );
+    write_counter(
+        &mut out,
+        "octocompare_octopus_requests_total",
+        "Calls to the Octopus API, by endpoint and status.",
+        &["endpoint", "status"],
+        &registry.octopus_requests,
+    );
+    write_histogram(
+        &mut out,
+        "octocompare_octopus_request_duration_seconds",
+        "Time taken by calls to the Octopus API.",
+        &["endpoint"],
+        &registry.octopus_durations,
+    );
+    write_counter(
+        &mut out,
+        "octocompare_cache_lookups_total",
+        "Product and pricing cache lookups, by whether they hit.",
+        &["cache", "result"],
+        &registry.cache_lookups,
+    );
+
+    let _ = writeln!(
+        out,
+        "# HELP octocompare_jobs_running Comparisons running in the background.\n# TYPE octocompare_jobs_running gauge"
+    );
+    for (kind, running) in jobs_running {
+        let _ = writeln!(
+            out,
+            "octocompare_jobs_running{{kind=\"{}\"}} {}",
+            kind, running
+        );
+    }
+    out
+}
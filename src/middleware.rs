@@ -0,0 +1,128 @@
+//! Layers every route goes through: timing, timeouts and turning errors
+//! into something people can read.
+
+use std::{sync::Arc, time::Instant};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tracing::{error, info_span, trace, Instrument};
+use uuid::Uuid;
+
+use crate::{
+    config::RouteTimeouts,
+    error::{AppError, ErrorDetail, UserError},
+    metrics,
+    ui::layout::{error_notice, page, NOTIFICATIONS},
+};
+
+/// Counts and times every request against the route it matched, rather than
+/// the path, so report IDs don't each get their own metric.
+pub async fn record_request(route: MatchedPath, request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let started = Instant::now();
+    let response = next.run(request).await;
+    trace!(
+        target: metrics::TARGET,
+        metric = "http_request",
+        method,
+        route = route.as_str(),
+        status = response.status().as_u16() as u64,
+        seconds = started.elapsed().as_secs_f64()
+    );
+    response
+}
+
+/// Gives up on requests that take longer than their route allows, so a hung
+/// call to Octopus can't hold a connection open forever.
+pub async fn enforce_timeout(
+    State(timeouts): State<Arc<RouteTimeouts>>,
+    route: MatchedPath,
+    request: Request,
+    next: Next,
+) -> Response {
+    let limit = timeouts.for_route(route.as_str());
+    match tokio::time::timeout(limit, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            error!("{} timed out after {:?}", route.as_str(), limit);
+            AppError(
+                UserError::TimedOut("That took too long, give it another go in a bit.".to_owned())
+                    .into(),
+            )
+            .into_response()
+        }
+    }
+}
+
+/// Gives each request a correlation ID, or keeps the one the proxy gave it,
+/// so errors shown to people can be found in the logs. Errors get rendered
+/// here once the ID is known: htmx requests get a notice retargeted to the
+/// notification area, anything else a page.
+pub async fn render_errors(request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| {
+            v.len() <= 64
+                && v.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+        .map(|v| v.to_owned())
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let htmx = request.headers().contains_key("hx-request");
+
+    let mut response = next
+        .run(request)
+        .instrument(info_span!("request", id = %id))
+        .await;
+
+    if let Some(ErrorDetail(detail)) = response.extensions_mut().remove::<ErrorDetail>() {
+        let status = response.status();
+        let (title, hint) = describe(status);
+        let notice = error_notice(title, &detail, hint, &id);
+        response = if htmx {
+            (
+                status,
+                [("HX-Retarget", NOTIFICATIONS), ("HX-Reswap", "innerHTML")],
+                notice,
+            )
+                .into_response()
+        } else {
+            (status, page(&format!("OctoCompare | {}", title), notice)).into_response()
+        };
+    }
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert("x-request-id", value);
+    }
+    response
+}
+
+/// A title and what to try next for each status we send back.
+fn describe(status: StatusCode) -> (&'static str, &'static str) {
+    match status {
+        StatusCode::BAD_REQUEST => ("That didn't look right", "Check the details and try again."),
+        StatusCode::NOT_FOUND => (
+            "We couldn't find that",
+            "It might have been deleted, or the link might be wrong.",
+        ),
+        StatusCode::BAD_GATEWAY => (
+            "Octopus didn't play ball",
+            "Their API might be having a moment, try again in a few minutes.",
+        ),
+        StatusCode::SERVICE_UNAVAILABLE => ("Back in a minute", "Try again shortly."),
+        StatusCode::GATEWAY_TIMEOUT => (
+            "That took too long",
+            "Give it another go, it's often quicker the second time.",
+        ),
+        _ => (
+            "Something went wrong",
+            "Try again, and if it keeps happening pass on the reference below.",
+        ),
+    }
+}
@@ -0,0 +1,202 @@
+use std::{cmp::Ordering, collections::BTreeMap};
+
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Timelike, Utc};
+use chrono_tz::{Europe::London, Tz};
+use serde::{Deserialize, Serialize};
+
+use crate::api::{ConsumptionDatum, PricingDatum, TariffPricing};
+
+/// Octopus bills on UK calendar days, so everything daily is worked out in
+/// Europe/London rather than UTC.
+pub const TIMEZONE: Tz = London;
+
+/// The UK calendar day a half hour falls in.
+pub fn local_date(at: DateTime<Utc>) -> NaiveDate {
+    at.with_timezone(&TIMEZONE).date_naive()
+}
+
+/// Midnight at the start of a UK calendar day. Clocks change at 1am, so
+/// midnight always exists exactly once.
+pub fn start_of_day(day: NaiveDate) -> DateTime<Utc> {
+    TIMEZONE
+        .from_local_datetime(&day.and_hms_opt(0, 0, 0).unwrap())
+        .earliest()
+        .expect("Midnight exists in Europe/London")
+        .with_timezone(&Utc)
+}
+
+/// Half hour of the UK day, 0 for 00:00-00:30 through 47 for 23:30-00:00. The
+/// repeated hour when the clocks go back shares its half hours.
+pub fn half_hour_of_day(at: DateTime<Utc>) -> usize {
+    let local = at.with_timezone(&TIMEZONE);
+    (local.hour() * 2 + local.minute() / 30) as usize
+}
+
+/// A source of prices that consumption can be costed against.
+pub trait Rates {
+    /// Unit rate in pence/kWh (inc. VAT) for the half hour starting at `at`.
+    fn unit_rate(&self, at: DateTime<Utc>) -> Option<f64>;
+
+    /// Standing charge in pence (inc. VAT) for a UK calendar day.
+    fn standing_charge(&self, day: NaiveDate) -> Option<f64>;
+}
+
+/// How often a tariff's unit rate changes.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum Granularity {
+    HalfHourly,
+    /// One rate per UK calendar day, used for every half hour in it
+    Daily,
+}
+
+impl Rates for TariffPricing {
+    fn unit_rate(&self, at: DateTime<Utc>) -> Option<f64> {
+        let at = match self.granularity {
+            Granularity::HalfHourly => at,
+            Granularity::Daily => start_of_day(local_date(at)),
+        };
+        find_rate(&self.unit_charges, at).map(|x| x.value_inc_vat)
+    }
+
+    fn standing_charge(&self, day: NaiveDate) -> Option<f64> {
+        find_rate(&self.standing_charges, start_of_day(day)).map(|x| x.value_inc_vat)
+    }
+}
+
+/// Finds the price in effect at `at`. Octopus hands prices back newest first.
+pub fn find_rate(rates: &[PricingDatum], at: DateTime<Utc>) -> Option<&PricingDatum> {
+    rates
+        .binary_search_by(|x| {
+            if at < x.valid_from {
+                Ordering::Less
+            } else if x.valid_to.is_some_and(|end| at >= end) {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        .ok()
+        .map(|i| &rates[i])
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct DailyCost {
+    pub date: NaiveDate,
+    /// Half hours priced on this day, 46 or 50 on clock change days
+    pub slots: usize,
+    /// kWh
+    pub consumption: f64,
+    /// Pence (inc. VAT)
+    pub unit_cost: f64,
+    /// Pence (inc. VAT)
+    pub standing_cost: f64,
+}
+
+impl DailyCost {
+    pub fn new(date: NaiveDate) -> Self {
+        Self {
+            date,
+            slots: 0,
+            consumption: 0.0,
+            unit_cost: 0.0,
+            standing_cost: 0.0,
+        }
+    }
+
+    pub fn total(&self) -> f64 {
+        self.unit_cost + self.standing_cost
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonthlyCost {
+    pub year: i32,
+    pub month: u32,
+    pub consumption: f64,
+    pub unit_cost: f64,
+    pub standing_cost: f64,
+}
+
+impl MonthlyCost {
+    pub fn total(&self) -> f64 {
+        self.unit_cost + self.standing_cost
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PricedConsumption {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub days: Vec<DailyCost>,
+    /// Set when some of the consumption or days had no price
+    pub data_missing: bool,
+}
+
+impl PricedConsumption {
+    pub fn unit_cost(&self) -> f64 {
+        self.days.iter().map(|d| d.unit_cost).sum()
+    }
+
+    pub fn standing_cost(&self) -> f64 {
+        self.days.iter().map(|d| d.standing_cost).sum()
+    }
+}
+
+/// Prices half-hourly consumption, grouped into UK calendar days. Every day
+/// between the first and last reading pays a standing charge, whether or not
+/// there are readings for it.
+pub fn price(consumption: &[ConsumptionDatum], rates: &dyn Rates) -> PricedConsumption {
+    let mut days: BTreeMap<NaiveDate, DailyCost> = BTreeMap::new();
+    let mut priced = PricedConsumption::default();
+
+    for d in consumption {
+        let Some(rate) = rates.unit_rate(d.interval_start) else {
+            priced.data_missing = true;
+            continue;
+        };
+
+        let date = local_date(d.interval_start);
+        let day = days.entry(date).or_insert_with(|| DailyCost::new(date));
+        day.slots += 1;
+        day.consumption += d.consumption;
+        day.unit_cost += rate * d.consumption;
+
+        priced.from = priced.from.into_iter().chain([d.interval_start]).min();
+        priced.to = priced.to.into_iter().chain([d.interval_end]).max();
+    }
+
+    if let (Some(first), Some(last)) = (days.keys().next().copied(), days.keys().last().copied()) {
+        for date in first.iter_days().take_while(|x| *x <= last) {
+            let day = days.entry(date).or_insert_with(|| DailyCost::new(date));
+            match rates.standing_charge(date) {
+                Some(charge) => day.standing_cost = charge,
+                None => priced.data_missing = true,
+            }
+        }
+    }
+
+    priced.days = days.into_values().collect();
+    priced
+}
+
+/// Rolls daily costs up into UK calendar months.
+pub fn monthly(days: &[DailyCost]) -> Vec<MonthlyCost> {
+    let mut months: Vec<MonthlyCost> = vec![];
+    for day in days {
+        match months.last_mut() {
+            Some(m) if m.year == day.date.year() && m.month == day.date.month() => {
+                m.consumption += day.consumption;
+                m.unit_cost += day.unit_cost;
+                m.standing_cost += day.standing_cost;
+            }
+            _ => months.push(MonthlyCost {
+                year: day.date.year(),
+                month: day.date.month(),
+                consumption: day.consumption,
+                unit_cost: day.unit_cost,
+                standing_cost: day.standing_cost,
+            }),
+        }
+    }
+    months
+}
@@ -0,0 +1,168 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use anyhow::{bail, Context, Result};
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+
+use crate::{
+    api::ConsumptionDatum,
+    error::UserError,
+    pricing::{half_hour_of_day, start_of_day},
+};
+
+const LOAD_PROFILES: &str = include_str!("../data/load-profiles.csv");
+
+/// The last `weekday` in `month`.
+fn last_weekday(year: i32, month: u32, weekday: Weekday) -> NaiveDate {
+    let next_month = match month {
+        12 => NaiveDate::from_ymd_opt(year + 1, 1, 1),
+        _ => NaiveDate::from_ymd_opt(year, month + 1, 1),
+    }
+    .unwrap();
+    let mut day = next_month.pred_opt().unwrap();
+    while day.weekday() != weekday {
+        day = day.pred_opt().unwrap();
+    }
+    day
+}
+
+/// Elexon's profiling season for a UK day:
+///
+/// - winter from the day the clocks go back in October to the day before
+///   they go forward in March
+/// - spring from the day the clocks go forward to the Friday before summer
+/// - summer for the ten weeks before high summer
+/// - high summer for the six weeks and two days before the August bank
+///   holiday
+/// - autumn from the August bank holiday to the day before the clocks go
+///   back
+pub fn season(day: NaiveDate) -> &'static str {
+    let year = day.year();
+    let clocks_forward = last_weekday(year, 3, Weekday::Sun);
+    let clocks_back = last_weekday(year, 10, Weekday::Sun);
+    let bank_holiday = last_weekday(year, 8, Weekday::Mon);
+    let high_summer = bank_holiday - Duration::days(44);
+    let summer = high_summer - Duration::weeks(10);
+
+    match day {
+        _ if day < clocks_forward || day >= clocks_back => "winter",
+        _ if day < summer => "spring",
+        _ if day < high_summer => "summer",
+        _ if day < bank_holiday => "high_summer",
+        _ => "autumn",
+    }
+}
+
+fn day_type(day: NaiveDate) -> &'static str {
+    match day.weekday() {
+        Weekday::Sat => "saturday",
+        Weekday::Sun => "sunday",
+        _ => "weekday",
+    }
+}
+
+type Profiles = HashMap<(u32, String, String), Vec<f64>>;
+
+/// The shipped profiles, parsed the first time they're needed.
+fn load_profiles() -> Result<&'static Profiles> {
+    static PROFILES: OnceLock<Result<Profiles, String>> = OnceLock::new();
+    PROFILES
+        .get_or_init(|| parse_profiles().map_err(|e| format!("{:#}", e)))
+        .as_ref()
+        .map_err(|e| anyhow::anyhow!("Unable to load profiles: {}", e))
+}
+
+fn parse_profiles() -> Result<Profiles> {
+    let mut profiles = HashMap::new();
+    for line in LOAD_PROFILES
+        .lines()
+        .filter(|l| !l.starts_with('#'))
+        .skip(1)
+    {
+        let mut fields = line.split(',');
+        let (Some(class), Some(season), Some(day_type)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            bail!("Load profile row {} is too short", line);
+        };
+        let values = fields
+            .map(|x| x.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("Load profile row {} isn't numbers", line))?;
+        if values.len() != 48 {
+            bail!("Load profile row {} doesn't have 48 half hours", line);
+        }
+        profiles.insert(
+            (class.parse()?, season.to_owned(), day_type.to_owned()),
+            values,
+        );
+    }
+    Ok(profiles)
+}
+
+/// Makes up half-hourly consumption for a meter we don't have readings for,
+/// by spreading its estimated annual consumption over a made-up load profile
+/// for its profile class. Covers the UK days `from` to `to` (inclusive) and
+/// comes back newest first like the API. Classes without a profile are turned
+/// down rather than given someone else's.
+pub fn synthesise(
+    profile_class: u32,
+    annual_kwh: f64,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<ConsumptionDatum>> {
+    let profiles = load_profiles()?;
+    if !profiles.keys().any(|(c, _, _)| *c == profile_class) {
+        bail!(UserError::NotFound(format!(
+            "We can only estimate use for domestic meters (profile class 1 or 2), and this one is class {}.",
+            profile_class
+        )));
+    }
+    let shape = |day: NaiveDate| -> Result<&Vec<f64>> {
+        profiles
+            .get(&(
+                profile_class,
+                season(day).to_owned(),
+                day_type(day).to_owned(),
+            ))
+            .with_context(|| format!("No load profile for {}", day))
+    };
+
+    // Unscaled, half hour by half hour, so clock change days count the
+    // half hours they actually have
+    let day_readings = |day: NaiveDate| -> Result<Vec<ConsumptionDatum>> {
+        let shape = shape(day)?;
+        let end = start_of_day(day.succ_opt().unwrap());
+        let mut start = start_of_day(day);
+        let mut readings = vec![];
+        while start < end {
+            readings.push(ConsumptionDatum {
+                consumption: shape[half_hour_of_day(start)],
+                interval_start: start,
+                interval_end: start + Duration::minutes(30),
+            });
+            start += Duration::minutes(30);
+        }
+        Ok(readings)
+    };
+
+    // Scale so a year from `from` adds up to the annual consumption
+    let mut year = 0.0;
+    for day in from.iter_days().take(365) {
+        year += day_readings(day)?
+            .iter()
+            .map(|d| d.consumption)
+            .sum::<f64>();
+    }
+    let scale = annual_kwh / year;
+
+    let mut results = vec![];
+    for day in from.iter_days().take_while(|d| *d <= to) {
+        for mut reading in day_readings(day)? {
+            reading.consumption *= scale;
+            results.push(reading);
+        }
+    }
+
+    results.reverse();
+    Ok(results)
+}
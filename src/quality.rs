@@ -0,0 +1,134 @@
+use std::cmp::Reverse;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::api::ConsumptionDatum;
+
+/// More than a 100A single phase supply can draw in half an hour.
+const SPIKE_KWH: f64 = 11.5;
+
+/// Zeros for this many half hours in a row look more like the meter losing
+/// its connection than nobody being home.
+const ZERO_RUN_SLOTS: usize = 12;
+
+/// Longest gap worth filling by drawing a line between the readings either
+/// side of it.
+const MAX_FILL_SLOTS: usize = 48;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Run {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub slots: usize,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct DataQuality {
+    /// Half hours between the first and last reading
+    pub expected: usize,
+    pub readings: usize,
+    /// Intervals more than one reading came back for
+    pub duplicates: usize,
+    pub gaps: Vec<Run>,
+    pub zero_runs: Vec<Run>,
+    /// Readings that can't be right, too big or negative
+    pub spikes: Vec<ConsumptionDatum>,
+    /// Half hours filled in by interpolation
+    pub filled: usize,
+}
+
+impl DataQuality {
+    pub fn missing(&self) -> usize {
+        self.gaps.iter().map(|g| g.slots).sum()
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.duplicates == 0
+            && self.gaps.is_empty()
+            && self.zero_runs.is_empty()
+            && self.spikes.is_empty()
+    }
+}
+
+/// Looks over a meter point's readings (newest first, one per interval) for
+/// gaps, suspicious runs of zeros and readings that can't be right.
+pub fn check(consumption: &[ConsumptionDatum], duplicates: usize) -> DataQuality {
+    let mut quality = DataQuality {
+        readings: consumption.len(),
+        duplicates,
+        ..Default::default()
+    };
+    let (Some(first), Some(last)) = (consumption.last(), consumption.first()) else {
+        return quality;
+    };
+    quality.expected = ((last.interval_end - first.interval_start).num_minutes() / 30) as usize;
+
+    let mut zeros: Option<Run> = None;
+    let mut previous: Option<&ConsumptionDatum> = None;
+    for d in consumption.iter().rev() {
+        if let Some(p) = previous {
+            if d.interval_start > p.interval_end {
+                quality.gaps.push(Run {
+                    from: p.interval_end,
+                    to: d.interval_start,
+                    slots: ((d.interval_start - p.interval_end).num_minutes() / 30) as usize,
+                });
+            }
+        }
+        previous = Some(d);
+
+        if d.consumption > SPIKE_KWH || d.consumption < 0.0 {
+            quality.spikes.push(d.clone());
+        }
+
+        if d.consumption == 0.0 {
+            let run = zeros.get_or_insert(Run {
+                from: d.interval_start,
+                to: d.interval_end,
+                slots: 0,
+            });
+            run.to = d.interval_end;
+            run.slots += 1;
+        } else if let Some(run) = zeros.take() {
+            if run.slots >= ZERO_RUN_SLOTS {
+                quality.zero_runs.push(run);
+            }
+        }
+    }
+    if let Some(run) = zeros.filter(|r| r.slots >= ZERO_RUN_SLOTS) {
+        quality.zero_runs.push(run);
+    }
+
+    quality
+}
+
+/// Fills gaps of up to a day by drawing a straight line between the readings
+/// either side. Longer gaps are left alone, a line across days of missing
+/// data doesn't say much. Returns how many half hours were filled.
+pub fn fill_gaps(consumption: &mut Vec<ConsumptionDatum>, gaps: &[Run]) -> usize {
+    let mut filled = vec![];
+    for gap in gaps.iter().filter(|g| g.slots <= MAX_FILL_SLOTS) {
+        let before = consumption.iter().find(|d| d.interval_end == gap.from);
+        let after = consumption.iter().find(|d| d.interval_start == gap.to);
+        let (Some(before), Some(after)) = (before, after) else {
+            continue;
+        };
+
+        let step = (after.consumption - before.consumption) / (gap.slots + 1) as f64;
+        for i in 0..gap.slots {
+            let start = gap.from + Duration::minutes(30 * i as i64);
+            filled.push(ConsumptionDatum {
+                consumption: before.consumption + step * (i + 1) as f64,
+                interval_start: start,
+                interval_end: start + Duration::minutes(30),
+            });
+        }
+    }
+
+    let count = filled.len();
+    consumption.extend(filled);
+    // Keep them newest first
+    consumption.sort_by_key(|d| Reverse(d.interval_start));
+    count
+}
@@ -0,0 +1,335 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use chrono::{Duration, NaiveDate};
+use futures::future::try_join_all;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::{agreement_at, AccountProperty, ConsumptionDatum, OctopusClient, Period},
+    compare::{
+        compare_tariffs, find_property, gas_consumption, import_meter_points, CompareRequest,
+        Comparison, Progress, ReportProgress,
+    },
+    csv::read_columns,
+    error::UserError,
+    kraken::{Bill, KrakenClient},
+    pricing::{local_date, price, start_of_day},
+};
+
+/// How many of the account's bills to check.
+const BILLS_TO_CHECK: usize = 12;
+
+/// Differences bigger than this (in percent) get flagged.
+pub const DISCREPANCY_THRESHOLD: f64 = 5.0;
+
+/// What someone was actually charged for a stretch of time.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BilledPeriod {
+    pub from: NaiveDate,
+    /// Inclusive
+    pub to: NaiveDate,
+    /// Pence (inc. VAT)
+    pub amount: f64,
+}
+
+impl BilledPeriod {
+    pub fn days(&self) -> i64 {
+        (self.to - self.from).num_days() + 1
+    }
+}
+
+/// Statements from Kraken, leaving out anything without dates or charges
+/// (credits, refunds and so on).
+pub fn billed_periods(bills: &[Bill]) -> Vec<BilledPeriod> {
+    bills
+        .iter()
+        .filter_map(|bill| {
+            Some(BilledPeriod {
+                from: bill.from_date?,
+                to: bill.to_date?,
+                amount: bill.total_charges.as_ref()?.gross_total? as f64,
+            })
+        })
+        .filter(|p| p.to >= p.from)
+        .collect()
+}
+
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(value, "%d/%m/%Y"))
+        .ok()
+}
+
+/// Reads an amount of money like `12.34`, `£1,234.56` or `-£5`.
+fn parse_pounds(value: &str) -> Option<f64> {
+    value.replace(['£', ','], "").parse().ok()
+}
+
+/// Reads billing periods from a statement CSV with `from`, `to` and `amount`
+/// columns. Dates can be `2024-01-31` or `31/01/2024`, amounts are pounds
+/// with or without the `£` and thousands separators. Without a header row the columns are taken to be
+/// in that order.
+pub fn parse_statement(csv: &str) -> Result<Vec<BilledPeriod>> {
+    let rows = read_columns(csv, "statement", &["from", "to", "amount"], |first| {
+        parse_date(first).is_some()
+    })?;
+
+    let mut periods = vec![];
+    for (i, row) in rows.iter().enumerate() {
+        let (Some(from), Some(to)) = (parse_date(&row[0]), parse_date(&row[1])) else {
+            bail!(UserError::BadRequest(format!(
+                "Line {} of the statement doesn't have a from and to date.",
+                i + 1
+            )));
+        };
+        let Some(pounds) = parse_pounds(&row[2]) else {
+            bail!(UserError::BadRequest(format!(
+                "Line {} of the statement doesn't have an amount.",
+                i + 1
+            )));
+        };
+        if to < from {
+            bail!(UserError::BadRequest(format!(
+                "Line {} of the statement ends before it starts.",
+                i + 1
+            )));
+        }
+        periods.push(BilledPeriod {
+            from,
+            to,
+            amount: pounds * 100.0,
+        });
+    }
+
+    if periods.is_empty() {
+        bail!(UserError::BadRequest(
+            "There weren't any billing periods in that statement.".to_owned()
+        ));
+    }
+    Ok(periods)
+}
+
+/// A billed period next to what the pricing engine makes of it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReconciledPeriod {
+    pub billed: BilledPeriod,
+    /// The tariff the property was on, if it was the same the whole period
+    pub tariff_code: Option<String>,
+    pub display_name: Option<String>,
+    /// Pence (inc. VAT), when there were readings for every day
+    pub computed: Option<f64>,
+    /// Days in the period without readings
+    pub days_missing: i64,
+    /// The bill covers gas as well, so the computed cost does too
+    #[serde(default)]
+    pub includes_gas: bool,
+    /// Pence (inc. VAT) of the computed cost that's gas
+    #[serde(default)]
+    pub gas: Option<f64>,
+}
+
+impl ReconciledPeriod {
+    /// How far out the computed cost is, as a percentage of the bill.
+    pub fn discrepancy(&self) -> Option<f64> {
+        let computed = self.computed?;
+        if self.billed.amount == 0.0 {
+            return None;
+        }
+        Some((computed - self.billed.amount) / self.billed.amount * 100.0)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Reconciliation {
+    pub address: String,
+    /// Bills came from Octopus rather than an uploaded statement
+    pub from_octopus: bool,
+    /// Octopus bills cover everything on the account, gas included
+    pub has_gas: bool,
+    pub periods: Vec<ReconciledPeriod>,
+}
+
+/// The tariff the property's (first) import meter point was on at midday
+/// on `date`.
+fn tariff_on(property: &AccountProperty, date: NaiveDate) -> Option<String> {
+    let emp = *import_meter_points(property).first()?;
+    let at = start_of_day(date) + Duration::hours(12);
+    agreement_at(&emp.agreements, at).map(|a| a.tariff_code.clone())
+}
+
+/// The tariff the property's (first) gas meter point was on at midday on
+/// `date`.
+fn gas_tariff_on(property: &AccountProperty, date: NaiveDate) -> Option<String> {
+    let gmp = property.gas_meter_points.first()?;
+    let at = start_of_day(date) + Duration::hours(12);
+    agreement_at(&gmp.agreements, at).map(|a| a.tariff_code.clone())
+}
+
+/// What the gas cost each day of the billed periods, in pence (inc. VAT),
+/// priced on whichever gas tariff the property was on that day. Days without
+/// readings or a tariff are left out.
+async fn gas_costs(
+    client: &OctopusClient,
+    api_key: &str,
+    property: &AccountProperty,
+    periods: &[BilledPeriod],
+    in_cubic_metres: bool,
+    progress: &dyn ReportProgress,
+) -> Result<BTreeMap<NaiveDate, f64>> {
+    let from = periods.iter().map(|p| p.from).min();
+    let to = periods.iter().map(|p| p.to).max();
+    let (Some(from), Some(to)) = (from, to) else {
+        return Ok(BTreeMap::new());
+    };
+    let tenancy = property.tenancy();
+    let period = Period {
+        from: Some(start_of_day(from).max(property.moved_in_at)),
+        to: Some(start_of_day(to + Duration::days(1)).min(tenancy.end())),
+    };
+
+    let readings = gas_consumption(
+        client,
+        api_key,
+        property,
+        &period,
+        in_cubic_metres,
+        progress,
+    )
+    .await?;
+    let mut by_tariff: BTreeMap<String, Vec<ConsumptionDatum>> = BTreeMap::new();
+    for reading in readings {
+        if let Some(code) = gas_tariff_on(property, local_date(reading.interval_start)) {
+            by_tariff.entry(code).or_default().push(reading);
+        }
+    }
+
+    let pricing = try_join_all(by_tariff.keys().map(|tariff_code| async {
+        progress.report(Progress::FetchingRates {
+            tariff_code: tariff_code.clone(),
+        });
+        client.get_pricing_during(tariff_code, &period).await
+    }))
+    .await?;
+
+    let mut costs = BTreeMap::new();
+    for (readings, pricing) in by_tariff.values().zip(pricing) {
+        for day in price(readings, &*pricing).days {
+            if day.slots > 0 {
+                *costs.entry(day.date).or_insert(0.0) += day.total();
+            }
+        }
+    }
+    Ok(costs)
+}
+
+/// Prices each billed period with whichever tariff the property was on at
+/// the time, as long as the comparison covered it. `gas` is the daily gas
+/// cost, for bills that cover the property's gas too.
+pub fn reconcile(
+    comparison: &Comparison,
+    property: &AccountProperty,
+    periods: &[BilledPeriod],
+    gas: Option<&BTreeMap<NaiveDate, f64>>,
+) -> Vec<ReconciledPeriod> {
+    periods
+        .iter()
+        .map(|billed| {
+            let start = tariff_on(property, billed.from);
+            let tariff_code =
+                start.filter(|code| tariff_on(property, billed.to).as_ref() == Some(code));
+            let cost = tariff_code
+                .as_ref()
+                .and_then(|code| comparison.tariffs.iter().find(|t| &t.tariff_code == code));
+
+            let electricity: BTreeMap<NaiveDate, f64> = cost
+                .map(|c| {
+                    c.days
+                        .iter()
+                        .filter(|d| d.date >= billed.from && d.date <= billed.to)
+                        .map(|d| (d.date, d.total()))
+                        .collect()
+                })
+                .unwrap_or_default();
+            let days_missing = billed
+                .from
+                .iter_days()
+                .take(billed.days() as usize)
+                .filter(|date| {
+                    !electricity.contains_key(date) || gas.is_some_and(|g| !g.contains_key(date))
+                })
+                .count() as i64;
+            let complete = cost.is_some() && days_missing == 0;
+            let gas = gas.filter(|_| complete).map(|g| {
+                g.range(billed.from..=billed.to)
+                    .map(|(_, c)| c)
+                    .sum::<f64>()
+            });
+
+            ReconciledPeriod {
+                billed: billed.clone(),
+                display_name: cost.map(|c| c.display_name.clone()),
+                computed: complete.then(|| electricity.values().sum::<f64>() + gas.unwrap_or(0.0)),
+                days_missing,
+                tariff_code,
+                includes_gas: gas.is_some(),
+                gas,
+            }
+        })
+        .collect()
+}
+
+/// Runs a comparison and checks it against the account's bills, or against
+/// `statement` if one was uploaded.
+pub async fn reconcile_account(
+    client: &OctopusClient,
+    kraken: &KrakenClient,
+    request: &CompareRequest,
+    statement: Option<Vec<BilledPeriod>>,
+    gas_in_cubic_metres: bool,
+    progress: &dyn ReportProgress,
+) -> Result<Reconciliation> {
+    let comparison = compare_tariffs(client, request, progress).await?;
+
+    progress.report(Progress::FetchingBills);
+    let from_octopus = statement.is_none();
+    let periods = match statement {
+        Some(periods) => periods,
+        None => billed_periods(
+            &kraken
+                .bills(&request.account_number, BILLS_TO_CHECK)
+                .await?
+                .bills,
+        ),
+    };
+
+    // Comparisons don't hang on to the agreements, so look them up again
+    let response = client
+        .get_account_details(&request.api_key, &request.account_number)
+        .await?;
+    let property = find_property(&response, request.property_id)?;
+
+    // Octopus bills are for the whole account, an uploaded statement might
+    // just be the electricity
+    let has_gas = !property.gas_meter_points.is_empty();
+    let gas = match from_octopus && has_gas {
+        true => Some(
+            gas_costs(
+                client,
+                &request.api_key,
+                property,
+                &periods,
+                gas_in_cubic_metres,
+                progress,
+            )
+            .await?,
+        ),
+        false => None,
+    };
+    Ok(Reconciliation {
+        address: comparison.address.clone(),
+        from_octopus,
+        has_gas,
+        periods: reconcile(&comparison, property, &periods, gas.as_ref()),
+    })
+}
@@ -0,0 +1,194 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::{Context, Result};
+use axum::{
+    http::{header, HeaderMap},
+    response::AppendHeaders,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::compare::Comparison;
+
+/// A finished comparison saved so it can be looked at (or shared) later. Only
+/// the results are kept, never the account number or API key.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Report {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub comparison: Comparison,
+    /// Only handed to whoever saved the report, anyone with the link can see
+    /// it but only they can delete it
+    #[serde(default)]
+    pub delete_token: String,
+}
+
+impl Report {
+    pub fn saved(&self) -> SavedReport {
+        SavedReport {
+            id: self.id.clone(),
+            delete_token: self.delete_token.clone(),
+        }
+    }
+}
+
+/// What the browser that saved a report keeps hold of: which report it was
+/// and the token to delete it with.
+#[derive(Debug, Clone)]
+pub struct SavedReport {
+    pub id: String,
+    pub delete_token: String,
+}
+
+/// Saved reports, one JSON file each in a directory.
+#[derive(Clone)]
+pub struct ReportStore {
+    dir: PathBuf,
+}
+
+impl ReportStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Report IDs are random UUIDs, which also stops anyone asking for
+    /// `../something` and reading files they shouldn't.
+    fn path(&self, id: &str) -> Option<PathBuf> {
+        let id = Uuid::parse_str(id).ok()?;
+        Some(self.dir.join(format!("{}.json", id)))
+    }
+
+    /// Checks reports can be saved, for readiness probes.
+    pub async fn check(&self) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .await
+            .with_context(|| format!("Unable to create {:?}", self.dir))?;
+        // Permission bits don't tell the whole story (ACLs, read only
+        // mounts), so actually try writing something
+        let probe = self.dir.join(format!(".write-check-{}", Uuid::new_v4()));
+        fs::write(&probe, b"")
+            .await
+            .with_context(|| format!("Unable to write to {:?}", self.dir))?;
+        fs::remove_file(&probe)
+            .await
+            .with_context(|| format!("Unable to remove {:?}", probe))?;
+        Ok(())
+    }
+
+    pub async fn save(&self, comparison: Comparison) -> Result<Report> {
+        let report = Report {
+            id: Uuid::new_v4().to_string(),
+            created_at: chrono::offset::Utc::now(),
+            comparison,
+            delete_token: Uuid::new_v4().to_string(),
+        };
+
+        fs::create_dir_all(&self.dir)
+            .await
+            .with_context(|| format!("Unable to create {:?}", self.dir))?;
+        let path = self.path(&report.id).unwrap();
+        fs::write(&path, serde_json::to_vec(&report)?)
+            .await
+            .with_context(|| format!("Unable to save report to {:?}", path))?;
+        info!("Saved report {}", report.id);
+
+        Ok(report)
+    }
+
+    pub async fn load(&self, id: &str) -> Result<Option<Report>> {
+        let Some(path) = self.path(id) else {
+            return Ok(None);
+        };
+        match fs::read(&path).await {
+            Ok(json) => {
+                Ok(Some(serde_json::from_slice(&json).with_context(|| {
+                    format!("Unable to parse report {:?}", path)
+                })?))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Unable to read {:?}", path)),
+        }
+    }
+
+    /// The reports with these IDs, newest first. Ones that have gone are
+    /// left out, as are ones that can't be read, which get logged.
+    pub async fn list(&self, ids: &[&str]) -> Vec<Report> {
+        let mut reports = vec![];
+        for id in ids {
+            match self.load(id).await {
+                Ok(Some(report)) => reports.push(report),
+                Ok(None) => {}
+                Err(e) => warn!("Skipping report {}: {:#}", id, e),
+            }
+        }
+
+        reports.sort_by_key(|r| std::cmp::Reverse(r.created_at));
+        reports
+    }
+
+    /// Deletes a report if `token` is the one it was saved with, returning
+    /// whether there was one to delete.
+    pub async fn delete(&self, id: &str, token: &str) -> Result<bool> {
+        let Some(report) = self.load(id).await? else {
+            return Ok(false);
+        };
+        if report.delete_token.is_empty() || report.delete_token != token {
+            return Ok(false);
+        }
+        let Some(path) = self.path(id) else {
+            return Ok(false);
+        };
+        match fs::remove_file(&path).await {
+            Ok(()) => {
+                info!("Deleted report {}", id);
+                Ok(true)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e).with_context(|| format!("Unable to delete {:?}", path)),
+        }
+    }
+}
+
+/// Reports this browser saved are remembered in a cookie each, holding the
+/// token that lets it delete them. There's no index of everyone's reports.
+pub const REPORT_COOKIE: &str = "report_";
+
+/// Cookies handing the browser the reports it just saved. Polling again for
+/// the same result just sets them again.
+pub fn report_cookies<'a>(
+    reports: impl IntoIterator<Item = &'a SavedReport>,
+) -> AppendHeaders<Vec<(header::HeaderName, String)>> {
+    AppendHeaders(
+        reports
+            .into_iter()
+            .map(|r| {
+                (
+                    header::SET_COOKIE,
+                    format!(
+                        "{}{}={}; Path=/; Max-Age=31536000; HttpOnly; SameSite=Lax",
+                        REPORT_COOKIE, r.id, r.delete_token
+                    ),
+                )
+            })
+            .collect(),
+    )
+}
+
+/// The reports this browser saved, with their delete tokens. Cookies that
+/// aren't ours or are missing an ID or token are skipped.
+pub fn saved_reports(headers: &HeaderMap) -> HashMap<String, String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .filter_map(|(name, value)| {
+            let id = name.strip_prefix(REPORT_COOKIE)?;
+            (!id.is_empty() && !value.is_empty()).then(|| (id.to_owned(), value.to_owned()))
+        })
+        .collect()
+}
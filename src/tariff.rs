@@ -0,0 +1,334 @@
+use std::{fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Timelike, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    api::{PricingDatum, TariffPricing},
+    error::UserError,
+    pricing::{start_of_day, Granularity, TIMEZONE},
+};
+
+/// A tariff described in TOML or JSON rather than fetched from Octopus, which
+/// can be from any supplier. Rates are in pence inc. VAT and all times are UK
+/// local time.
+///
+/// ```toml
+/// code = "E7-GENERIC"
+/// name = "Economy 7"
+/// supplier = "Some Energy Co"
+/// exit_fee = 7500.0
+///
+/// [[standing_charges]]
+/// value = 53.0
+///
+/// [[bands]]
+/// name = "Night"
+/// rate = 14.5
+/// times = ["00:30-07:30"]
+///
+/// [[bands]]
+/// name = "Day"
+/// rate = 30.1
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TariffDefinition {
+    pub code: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub supplier: Option<String>,
+    /// Pence, for leaving before the end of the contract
+    #[serde(default)]
+    pub exit_fee: Option<f64>,
+    pub standing_charges: Vec<StandingCharge>,
+    /// Checked in order, the first band that matches a half hour prices it
+    pub bands: Vec<RateBand>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StandingCharge {
+    /// Pence per day
+    pub value: f64,
+    /// `YYYY-MM-DD`, inclusive
+    #[serde(default)]
+    pub valid_from: Option<String>,
+    /// `YYYY-MM-DD`, exclusive
+    #[serde(default)]
+    pub valid_to: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RateBand {
+    /// e.g. `Night`, to say which band is wrong when one doesn't compile
+    pub name: String,
+    /// Pence per kWh
+    pub rate: f64,
+    /// `HH:MM-HH:MM` ranges, which can wrap past midnight. Empty means all day.
+    #[serde(default)]
+    pub times: Vec<String>,
+    /// e.g. `["Sat", "Sun"]`. Empty means every day.
+    #[serde(default)]
+    pub weekdays: Vec<String>,
+    /// Months of the year (1-12) for seasonal bands. Empty means all year.
+    #[serde(default)]
+    pub months: Vec<u32>,
+    #[serde(default)]
+    pub valid_from: Option<String>,
+    #[serde(default)]
+    pub valid_to: Option<String>,
+}
+
+struct Validity {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+impl Validity {
+    fn parse(from: &Option<String>, to: &Option<String>) -> Result<Self> {
+        let parse = |x: &Option<String>| -> Result<Option<NaiveDate>> {
+            x.as_deref()
+                .map(|d| {
+                    NaiveDate::parse_from_str(d, "%Y-%m-%d")
+                        .with_context(|| format!("{} isn't a YYYY-MM-DD date", d))
+                })
+                .transpose()
+        };
+        Ok(Self {
+            from: parse(from)?,
+            to: parse(to)?,
+        })
+    }
+
+    fn contains(&self, day: NaiveDate) -> bool {
+        self.from.is_none_or(|from| from <= day) && self.to.is_none_or(|to| day < to)
+    }
+}
+
+struct CompiledBand {
+    rate: f64,
+    /// Minutes since midnight, end exclusive
+    times: Vec<(u32, u32)>,
+    weekdays: Vec<Weekday>,
+    months: Vec<u32>,
+    validity: Validity,
+}
+
+impl CompiledBand {
+    fn matches(&self, day: NaiveDate, minute: u32) -> bool {
+        self.validity.contains(day)
+            && (self.weekdays.is_empty() || self.weekdays.contains(&day.weekday()))
+            && (self.months.is_empty() || self.months.contains(&day.month()))
+            && (self.times.is_empty()
+                || self.times.iter().any(|&(from, to)| {
+                    if from < to {
+                        from <= minute && minute < to
+                    } else {
+                        minute >= from || minute < to
+                    }
+                }))
+    }
+}
+
+/// Minutes since midnight for an `HH:MM` time. `24:00` is allowed as the end
+/// of a range running up to midnight.
+fn parse_minutes(time: &str) -> Result<u32> {
+    let parsed = time
+        .trim()
+        .split_once(':')
+        .and_then(|(h, m)| Some((h.parse::<u32>().ok()?, m.parse::<u32>().ok()?)));
+    match parsed {
+        Some((24, 0)) => Ok(24 * 60),
+        Some((h, m)) if h < 24 && m < 60 => Ok(h * 60 + m),
+        _ => bail!("{} isn't an HH:MM time", time),
+    }
+}
+
+fn compile_band(band: &RateBand) -> Result<CompiledBand> {
+    let times = band
+        .times
+        .iter()
+        .map(|range| {
+            let Some((from, to)) = range.split_once('-') else {
+                bail!("{} isn't an HH:MM-HH:MM range", range);
+            };
+            let (from, to) = (parse_minutes(from)?, parse_minutes(to)?);
+            if from == 24 * 60 {
+                bail!("{} can't start at 24:00, start it at 00:00", range);
+            }
+            if from == to {
+                bail!(
+                    "{} starts and ends at the same time, leave out times for a band that's on all day",
+                    range
+                );
+            }
+            Ok((from, to))
+        })
+        .collect::<Result<_>>()?;
+    let weekdays = band
+        .weekdays
+        .iter()
+        .map(|d| {
+            d.parse::<Weekday>()
+                .map_err(|_| anyhow::anyhow!("{} isn't a day of the week", d))
+        })
+        .collect::<Result<_>>()?;
+    if let Some(month) = band.months.iter().find(|m| !(1..=12).contains(*m)) {
+        bail!("{} isn't a month", month);
+    }
+
+    Ok(CompiledBand {
+        rate: band.rate,
+        times,
+        weekdays,
+        months: band.months.clone(),
+        validity: Validity::parse(&band.valid_from, &band.valid_to)?,
+    })
+}
+
+impl TariffDefinition {
+    pub fn from_toml(source: &str) -> Result<Self> {
+        Ok(toml::from_str(source)?)
+    }
+
+    pub fn from_json(source: &str) -> Result<Self> {
+        Ok(serde_json::from_str(source)?)
+    }
+
+    /// Reads a definition that could be either TOML or JSON, checking it
+    /// compiles.
+    pub fn parse(source: &str) -> Result<Self> {
+        let definition = if source.trim_start().starts_with('{') {
+            Self::from_json(source)?
+        } else {
+            Self::from_toml(source)?
+        };
+        definition.compile_bands()?;
+        Ok(definition)
+    }
+
+    fn compile_bands(&self) -> Result<Vec<CompiledBand>> {
+        if self.bands.is_empty() {
+            bail!("Tariff {} doesn't have any rate bands", self.code);
+        }
+
+        self.bands
+            .iter()
+            .map(|band| {
+                compile_band(band).map_err(|e| {
+                    UserError::BadRequest(format!(
+                        "Band {} in tariff {} is invalid: {:#}",
+                        band.name, self.code, e
+                    ))
+                    .into()
+                })
+            })
+            .collect()
+    }
+
+    /// Turns the definition into prices for every half hour of the UK days
+    /// `from` to `to` (inclusive), in the same shape Octopus hands them back so
+    /// the rest of the engine can't tell the difference.
+    pub fn compile(&self, from: NaiveDate, to: NaiveDate) -> Result<TariffPricing> {
+        let bands = self.compile_bands()?;
+        let standing_charges = self
+            .standing_charges
+            .iter()
+            .map(|s| Ok((Validity::parse(&s.valid_from, &s.valid_to)?, s.value)))
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("Standing charge in tariff {} is invalid", self.code))?;
+
+        let end = start_of_day(to.succ_opt().unwrap());
+        let mut unit_charges: Vec<PricingDatum> = vec![];
+        let mut slot = start_of_day(from);
+        while slot < end {
+            let local = slot.with_timezone(&TIMEZONE);
+            let minute = local.hour() * 60 + local.minute();
+            let rate = bands
+                .iter()
+                .find(|b| b.matches(local.date_naive(), minute))
+                .map(|b| b.rate);
+            push_rate(&mut unit_charges, rate, slot, slot + Duration::minutes(30));
+            slot += Duration::minutes(30);
+        }
+
+        let mut standing: Vec<PricingDatum> = vec![];
+        for day in from.iter_days().take_while(|d| *d <= to) {
+            let value = standing_charges
+                .iter()
+                .find(|(validity, _)| validity.contains(day))
+                .map(|(_, value)| *value);
+            let next = day.succ_opt().unwrap();
+            push_rate(&mut standing, value, start_of_day(day), start_of_day(next));
+        }
+
+        // Octopus hands prices back newest first
+        unit_charges.reverse();
+        standing.reverse();
+
+        Ok(TariffPricing {
+            tariff_code: self.code.clone(),
+            product_code: self.code.clone(),
+            display_name: self.name.clone(),
+            granularity: Granularity::HalfHourly,
+            standing_charges: standing,
+            unit_charges,
+        })
+    }
+}
+
+/// Extends the last price if it's the same, so a day of a flat rate ends up as
+/// one entry rather than 48.
+fn push_rate(
+    rates: &mut Vec<PricingDatum>,
+    value: Option<f64>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) {
+    let Some(value) = value else {
+        return;
+    };
+    match rates.last_mut() {
+        Some(last) if last.value_inc_vat == value && last.valid_to == Some(from) => {
+            last.valid_to = Some(to);
+        }
+        _ => rates.push(PricingDatum {
+            value_exc_vat: value / 1.05,
+            value_inc_vat: value,
+            valid_from: from,
+            valid_to: Some(to),
+        }),
+    }
+}
+
+/// Reads every `.toml` and `.json` tariff definition in a directory. A missing
+/// directory just means there aren't any.
+pub fn load_definitions(dir: &Path) -> Result<Vec<TariffDefinition>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut definitions = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let source =
+            || fs::read_to_string(&path).with_context(|| format!("Unable to read {:?}", path));
+        let definition = match path.extension().and_then(|x| x.to_str()) {
+            Some("toml") => TariffDefinition::from_toml(&source()?),
+            Some("json") => TariffDefinition::from_json(&source()?),
+            _ => continue,
+        }
+        .with_context(|| format!("Unable to parse tariff definition {:?}", path))?;
+
+        // Catch bad bands now rather than halfway through a comparison
+        definition.compile_bands()?;
+        info!("Loaded tariff {} from {:?}", definition.code, path);
+        definitions.push(definition);
+    }
+
+    definitions.sort_by(|a, b| a.code.cmp(&b.code));
+    Ok(definitions)
+}
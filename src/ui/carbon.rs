@@ -0,0 +1,80 @@
+use crate::{carbon::CarbonReport, ui::layout::heading2};
+use chrono::NaiveDate;
+use maud::{html, Markup};
+
+fn month_name(year: i32, month: u32) -> String {
+    NaiveDate::from_ymd_opt(year, month, 1)
+        .map(|d| d.format("%b %Y").to_string())
+        .unwrap_or_default()
+}
+
+pub fn carbon_result(report: &CarbonReport) -> Markup {
+    let shifted = report.shifted_emissions();
+
+    html! {
+        div ."mt-4" {
+            (heading2(&format!("Carbon for {}", report.address)))
+            @if report.months.is_empty() {
+                p ."mt-2" { "None of the carbon intensity figures lined up with your readings." }
+            } @else {
+                p ."mt-2" {
+                    "From " (report.from.format("%-d %b %Y")) " to " (report.to.format("%-d %b %Y")) " you used "
+                    span ."text-white" { (format!("{:.0}", report.consumption())) " kWh" }
+                    ", responsible for about "
+                    span ."text-white" { (format!("{:.0}", report.emissions())) " kgCO2" }
+                    " at an average of " (format!("{:.0}", report.average_intensity())) " gCO2/kWh."
+                }
+                @if let Some(shifted) = shifted {
+                    p ."mt-2" {
+                        "Moving " (format!("{:.0}", report.flexible_percent)) "% of each day's use to the cheapest Agile half hours would make that "
+                        span ."text-white" { (format!("{:.0}", shifted)) " kgCO2" }
+                        @if report.emissions() > 0.0 {
+                            ", "
+                            span ."text-white" {
+                                (format!("{:.1}", (report.emissions() - shifted) / report.emissions() * 100.0)) "%"
+                            }
+                            " less"
+                        }
+                        "."
+                    }
+                } @else {
+                    p ."mt-2"."text-sm" { "We couldn't get Agile prices for this property, so there's no load-shifted figure." }
+                }
+                table ."mt-2"."table-auto"."text-left" {
+                    thead {
+                        tr ."text-white" {
+                            th ."pr-4" { "Month" }
+                            th ."pr-4" { "Consumption" }
+                            th ."pr-4" { "Emissions" }
+                            @if shifted.is_some() {
+                                th ."pr-4" { "Shifted to Agile" }
+                            }
+                        }
+                    }
+                    tbody {
+                        @for month in &report.months {
+                            tr {
+                                td ."pr-4" { (month_name(month.year, month.month)) }
+                                td ."pr-4" { (format!("{:.0}", month.consumption)) " kWh" }
+                                td ."pr-4"."text-white" { (format!("{:.1}", month.emissions)) " kg" }
+                                @if let Some(shifted) = month.shifted_emissions {
+                                    td ."pr-4" { (format!("{:.1}", shifted)) " kg" }
+                                }
+                            }
+                        }
+                    }
+                }
+                p ."mt-2"."text-sm" {
+                    @match &report.region {
+                        Some(region) => { "Intensity is National Grid's half-hourly forecast for " (region) ". " }
+                        None => { "Intensity is from the figures you uploaded. " }
+                    }
+                    @if report.slots_missing > 0 {
+                        (report.slots_missing) " half hours of readings had no intensity and are left out. "
+                    }
+                    "This covers electricity import only, gas isn't included."
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use maud::{html, Markup};
+
+use crate::{compare::TariffCost, pricing::MonthlyCost};
+
+const COLOURS: [&str; 8] = [
+    "#6366f1", "#22c55e", "#f59e0b", "#ec4899", "#06b6d4", "#ef4444", "#a855f7", "#84cc16",
+];
+
+const HEIGHT: f64 = 200.0;
+const BAR_WIDTH: f64 = 10.0;
+const GAP: f64 = 12.0;
+
+/// Bar chart of each tariff's monthly cost, drawn as an inline SVG so it
+/// works in saved reports without any JavaScript.
+pub fn monthly_chart(costs: &[TariffCost]) -> Markup {
+    let months: Vec<Vec<MonthlyCost>> = costs.iter().map(|c| c.months()).collect();
+    let mut labels: Vec<(i32, u32)> = months.iter().flatten().map(|m| (m.year, m.month)).collect();
+    labels.sort();
+    labels.dedup();
+
+    let max = months
+        .iter()
+        .flatten()
+        .map(|m| m.total())
+        .fold(0.0, f64::max);
+    if labels.is_empty() || max <= 0.0 {
+        return html! {};
+    }
+
+    let group_width = BAR_WIDTH * costs.len() as f64 + GAP;
+    let width = group_width * labels.len() as f64;
+
+    html! {
+        div ."mt-4" {
+            svg width=(width) height=(HEIGHT + 20.0) viewBox=(format!("0 0 {} {}", width, HEIGHT + 20.0)) role="img" {
+                title { "Monthly cost per tariff" }
+                @for (i, (year, month)) in labels.iter().enumerate() {
+                    @for (j, tariff_months) in months.iter().enumerate() {
+                        @if let Some(m) = tariff_months.iter().find(|m| m.year == *year && m.month == *month) {
+                            @let height = m.total() / max * HEIGHT;
+                            rect
+                                x=(i as f64 * group_width + j as f64 * BAR_WIDTH)
+                                y=(HEIGHT - height)
+                                width=(BAR_WIDTH - 1.0)
+                                height=(height)
+                                fill=(COLOURS[j % COLOURS.len()]) {
+                                    title { (costs[j].display_name) " " (format!("{}-{:02}", year, month)) ": £" (format!("{:.2}", m.total() / 100.0)) }
+                                }
+                        }
+                    }
+                    text x=(i as f64 * group_width) y=(HEIGHT + 14.0) font-size="10" fill="#94a3b8" {
+                        (format!("{:02}/{:02}", month, year % 100))
+                    }
+                }
+            }
+            ul ."flex"."flex-row"."flex-wrap"."text-sm"."mt-2" {
+                @for (j, cost) in costs.iter().enumerate() {
+                    li ."mr-4" {
+                        span style=(format!("color: {}", COLOURS[j % COLOURS.len()])) { "■ " }
+                        (cost.display_name)
+                    }
+                }
+            }
+        }
+    }
+}
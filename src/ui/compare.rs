@@ -0,0 +1,285 @@
+use crate::{
+    compare::{Comparison, MeterPointComparison, Progress, TariffCost},
+    pricing::{MonthlyCost, TIMEZONE},
+    quality::{DataQuality, Run},
+    ui::{chart::monthly_chart, layout::heading2},
+};
+use maud::{html, Markup};
+
+/// Progress panel that keeps polling `poll_url` until the job's done.
+pub fn comparison_progress(poll_url: &str, events: &[Progress]) -> Markup {
+    html! {
+        div hx-get=(poll_url) hx-trigger="every 1s" hx-swap="outerHTML" ."mt-4" {
+            (heading2("Comparing tariffs..."))
+            ul ."mt-2" {
+                @for (i, event) in events.iter().enumerate() {
+                    @if i == events.len() - 1 {
+                        li ."text-white" { (event) "..." }
+                    } @else {
+                        li { (event) " ✓" }
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn comparison_result(comparison: &Comparison) -> Markup {
+    let first = comparison.tariffs.iter().find(|x| x.from.is_some());
+
+    html! {
+        div ."mt-4" {
+            (heading2(&comparison.address))
+            @if comparison.tariffs.is_empty() {
+                p { "No consumption or pricing data was found." }
+            } @else {
+                @if let Some(first) = first {
+                    p ."mt-2" {
+                        "From " (first.from.map(|x| x.to_string()).unwrap_or("unknown".to_string()))
+                        " to " (first.to.map(|x| x.to_string()).unwrap_or("unknown".to_string()))
+                    }
+                }
+                @if let Some(moved_out_at) = comparison.moved_out_at {
+                    p ."mt-2"."text-sm" {
+                        "You moved out on " (moved_out_at.format("%-d %b %Y")) ", so this only covers your time there. "
+                        "\"Current\" is the tariff you were on when you left."
+                    }
+                }
+                @if comparison.estimated {
+                    p ."mt-2"."text-amber-400" {
+                        strong { "Estimated. " }
+                        "We couldn't get half-hourly readings for "
+                        @for (i, meter_point) in comparison.meter_points.iter().filter(|m| m.estimated_from.is_some()).enumerate() {
+                            @if i > 0 { ", " }
+                            "MPAN " (meter_point.mpan) " (" (format!("{:.0}", meter_point.estimated_from.unwrap_or_default())) " kWh a year)"
+                        }
+                        ", so we've spread its estimated annual consumption over a made-up typical day. "
+                        "That's our own guess at a household's use, not a published profile, so treat these costs as a rough guide, especially for time of use tariffs."
+                    }
+                }
+                @if !comparison.unavailable.is_empty() {
+                    p ."mt-2"."text-sm" {
+                        "Octopus wouldn't give us "
+                        @for (i, product) in comparison.unavailable.iter().enumerate() {
+                            @if i > 0 { ", " }
+                            span ."text-white" { (product) }
+                        }
+                        " right now, so they're left out. They may have been withdrawn."
+                    }
+                }
+                (cost_table(&comparison.tariffs))
+                (monthly_chart(&comparison.tariffs))
+                (monthly_table(&comparison.tariffs))
+                (quality_panel(&comparison.meter_points))
+                @if comparison.meter_points.len() > 1 || comparison.meter_points.iter().any(|m| m.meters.len() > 1) {
+                    @for meter_point in &comparison.meter_points {
+                        details ."mt-4" {
+                            summary { "MPAN " span ."text-white" { (meter_point.mpan) } }
+                            ul ."mt-2"."ml-4" {
+                                @for (serial_number, readings) in &meter_point.meters {
+                                    li { "Meter " (serial_number) ": " (readings) " readings" }
+                                }
+                                @if let Some(annual) = meter_point.estimated_from {
+                                    li { "Estimated from " (format!("{:.0}", annual)) " kWh a year" }
+                                }
+                            }
+                            (cost_table(&meter_point.tariffs))
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn cost_table(costs: &[TariffCost]) -> Markup {
+    let mut tariffs: Vec<_> = costs.iter().collect();
+    tariffs.sort_by(|a, b| a.total().total_cmp(&b.total()));
+    let exit_fees = tariffs.iter().any(|x| x.exit_fee.is_some());
+
+    html! {
+        table ."mt-2"."table-auto"."text-left" {
+            thead {
+                tr ."text-white" {
+                    th ."pr-4" { "Tariff" }
+                    th ."pr-4" { "Consumption" }
+                    th ."pr-4" { "Standing charges" }
+                    th ."pr-4" { "Total" }
+                    @if exit_fees {
+                        th ."pr-4" { "Exit fee" }
+                    }
+                }
+            }
+            tbody {
+                @for cost in &tariffs {
+                    tr {
+                        td ."pr-4" {
+                            span ."text-white" { (cost.display_name) }
+                            " " span ."text-xs" { (cost.tariff_code) }
+                            @if cost.is_current {
+                                strong ."text-white" { " (current)" }
+                            }
+                            @if cost.data_missing {
+                                " *"
+                            }
+                            @if let Some(supplier) = &cost.supplier {
+                                br;
+                                span ."text-xs" { (supplier) }
+                            }
+                            @if cost.switching_fee > 0.0 {
+                                br;
+                                span ."text-xs" {
+                                    "includes £" (format!("{:.2}", cost.switching_fee / 100.0)) " to leave your current tariff"
+                                }
+                            }
+                            @if cost.ev_energy > 0.0 {
+                                br;
+                                span ."text-xs" {
+                                    "includes " (format!("{:.0}", cost.ev_energy)) " kWh of EV charging"
+                                    @if cost.dispatches > 0 {
+                                        ", " (cost.dispatches) " dispatched slots at the off-peak rate"
+                                    }
+                                }
+                            }
+                        }
+                        td ."pr-4" { "£" (format!("{:.2}", cost.unit_cost / 100.0)) }
+                        td ."pr-4" { "£" (format!("{:.2}", cost.standing_cost / 100.0)) }
+                        td ."pr-4"."text-white" { "£" (format!("{:.2}", cost.total() / 100.0)) }
+                        @if exit_fees {
+                            td ."pr-4" {
+                                @if let Some(fee) = cost.exit_fee {
+                                    "£" (format!("{:.2}", fee / 100.0))
+                                } @else {
+                                    "-"
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        @if tariffs.iter().any(|x| x.data_missing) {
+            p ."mt-2"."text-sm" { "* pricing data does not cover the whole consumption period" }
+        }
+        @if exit_fees {
+            p ."mt-2"."text-sm" {
+                "Exit fees are what leaving a tariff before its contract ends would cost. "
+                "Only your current tariff's is in the totals, as it's what switching now would cost you."
+            }
+        }
+    }
+}
+
+fn monthly_table(costs: &[TariffCost]) -> Markup {
+    let months: Vec<Vec<MonthlyCost>> = costs.iter().map(|c| c.months()).collect();
+    let mut labels: Vec<(i32, u32)> = months.iter().flatten().map(|m| (m.year, m.month)).collect();
+    labels.sort();
+    labels.dedup();
+
+    html! {
+        details ."mt-4" {
+            summary { "Monthly breakdown" }
+            table ."mt-2"."table-auto"."text-left" {
+                thead {
+                    tr ."text-white" {
+                        th ."pr-4" { "Month" }
+                        @for cost in costs {
+                            th ."pr-4" { (cost.display_name) }
+                        }
+                    }
+                }
+                tbody {
+                    @for (year, month) in &labels {
+                        tr {
+                            td ."pr-4" { (format!("{}-{:02}", year, month)) }
+                            @for tariff_months in &months {
+                                @let m = tariff_months.iter().find(|m| m.year == *year && m.month == *month);
+                                td ."pr-4" {
+                                    @if let Some(m) = m {
+                                        "£" (format!("{:.2}", m.total() / 100.0))
+                                    } @else {
+                                        "-"
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn quality_panel(meter_points: &[MeterPointComparison]) -> Markup {
+    let checked: Vec<(&str, &DataQuality)> = meter_points
+        .iter()
+        .filter_map(|m| m.quality.as_ref().map(|q| (m.mpan.as_str(), q)))
+        .collect();
+    let all_clean = checked.iter().all(|(_, q)| q.is_clean());
+
+    html! {
+        @if !checked.is_empty() {
+            details ."mt-4" open[!all_clean] {
+                summary {
+                    "Data quality"
+                    @if all_clean { " ✓" } @else { " - some readings look off" }
+                }
+                @for (mpan, quality) in &checked {
+                    div ."mt-2"."ml-4" {
+                        p ."text-white" { "MPAN " (mpan) }
+                        ul ."ml-4" {
+                            li { (quality.readings) " of " (quality.expected) " half hours have readings" }
+                            @if quality.duplicates > 0 {
+                                li { (quality.duplicates) " half hours were reported more than once" }
+                            }
+                            @if !quality.gaps.is_empty() {
+                                li {
+                                    (quality.missing()) " half hours missing across " (quality.gaps.len()) " gaps"
+                                    @if quality.filled > 0 {
+                                        ", " (quality.filled) " filled in by interpolating"
+                                    }
+                                    (runs(&quality.gaps))
+                                }
+                            }
+                            @if !quality.zero_runs.is_empty() {
+                                li {
+                                    (quality.zero_runs.len()) " runs of zero readings, which can mean the meter lost its connection"
+                                    (runs(&quality.zero_runs))
+                                }
+                            }
+                            @if !quality.spikes.is_empty() {
+                                li {
+                                    (quality.spikes.len()) " readings too big (or negative) to be real"
+                                    ul ."ml-4"."text-sm" {
+                                        @for spike in quality.spikes.iter().take(5) {
+                                            li { (local_time(spike.interval_start)) ": " (format!("{:.2}", spike.consumption)) " kWh" }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn runs(runs: &[Run]) -> Markup {
+    html! {
+        ul ."ml-4"."text-sm" {
+            @for run in runs.iter().take(5) {
+                li { (local_time(run.from)) " to " (local_time(run.to)) }
+            }
+            @if runs.len() > 5 {
+                li { "and " (runs.len() - 5) " more" }
+            }
+        }
+    }
+}
+
+fn local_time(at: chrono::DateTime<chrono::Utc>) -> String {
+    at.with_timezone(&TIMEZONE)
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
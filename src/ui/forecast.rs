@@ -0,0 +1,73 @@
+use crate::{forecast::Forecast, pricing::TIMEZONE, ui::layout::heading2};
+use chrono::{DateTime, Utc};
+use maud::{html, Markup};
+
+fn local_time(at: DateTime<Utc>) -> String {
+    at.with_timezone(&TIMEZONE).format("%H:%M").to_string()
+}
+
+pub fn forecast_result(forecast: &Forecast) -> Markup {
+    let cheapest = forecast
+        .slots
+        .iter()
+        .map(|s| s.rate)
+        .reduce(f64::min)
+        .unwrap_or_default();
+
+    html! {
+        div ."mt-4" {
+            (heading2(&format!("Agile on {}", forecast.date.format("%A %-d %B"))))
+            @if forecast.slots.len() < 46 {
+                p ."mt-2" {
+                    "Only " (forecast.slots.len()) " half hours of prices are out for this day so far. "
+                    "Octopus usually publishes the next day's Agile prices around 4pm."
+                }
+            }
+            p ."mt-2" {
+                "On a typical day you'd use "
+                span ."text-white" { (format!("{:.1}", forecast.consumption())) " kWh" }
+                ", which would cost about "
+                span ."text-white" { "£" (format!("{:.2}", forecast.total() / 100.0)) }
+                " including the standing charge."
+            }
+            p ."mt-2"."text-sm" {
+                "Based on your average usage over " (forecast.profile_days) " "
+                (forecast.profile_description) ". This is an estimate, not a prediction of your bill."
+            }
+            @if let Some(window) = &forecast.cheapest_window {
+                p ."mt-2" {
+                    "Cheapest time to run big appliances: "
+                    span ."text-white" { (local_time(window.start)) " to " (local_time(window.end)) }
+                    " at an average of " (format!("{:.2}", window.average_rate)) "p/kWh."
+                }
+            }
+            details ."mt-4" {
+                summary { "Half-hourly breakdown" }
+                table ."mt-2"."table-auto"."text-left" {
+                    thead {
+                        tr ."text-white" {
+                            th ."pr-4" { "Time" }
+                            th ."pr-4" { "Rate" }
+                            th ."pr-4" { "Typical use" }
+                            th ."pr-4" { "Cost" }
+                        }
+                    }
+                    tbody {
+                        @for slot in &forecast.slots {
+                            tr {
+                                td ."pr-4" { (local_time(slot.start)) }
+                                @if slot.rate == cheapest {
+                                    td ."pr-4"."text-white" { (format!("{:.2}", slot.rate)) "p" }
+                                } @else {
+                                    td ."pr-4" { (format!("{:.2}", slot.rate)) "p" }
+                                }
+                                td ."pr-4" { (format!("{:.3}", slot.consumption)) " kWh" }
+                                td ."pr-4" { (format!("{:.1}", slot.cost())) "p" }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use crate::{
+    heatpump::{HeatPumpBill, HeatPumpComparison},
+    ui::layout::heading2,
+};
+use maud::{html, Markup};
+
+fn pounds(pence: f64) -> String {
+    format!("£{:.2}", pence / 100.0)
+}
+
+/// A row for one way of paying, with how it compares to `current` when given.
+fn bill_row(bill: &HeatPumpBill, current: Option<&HeatPumpBill>) -> Markup {
+    html! {
+        tr {
+            td ."pr-4" {
+                span ."text-white" { (bill.display_name) }
+                " " span ."text-xs" { (bill.tariff_code) }
+            }
+            td ."pr-4" { (pounds(bill.electricity_cost)) }
+            td ."pr-4" { (pounds(bill.gas_cost)) }
+            td ."pr-4"."text-white" {
+                (pounds(bill.total()))
+                @if bill.data_missing { " *" }
+            }
+            td ."pr-4" {
+                @match current.map(|c| c.total() - bill.total()) {
+                    None => "-",
+                    Some(saving) if saving > 0.0 => span ."text-green-400" { (pounds(saving)) " less" },
+                    Some(saving) => span ."text-red-400" { (pounds(-saving)) " more" },
+                }
+            }
+        }
+    }
+}
+
+pub fn heat_pump_result(comparison: &HeatPumpComparison) -> Markup {
+    let any_missing =
+        comparison.current.data_missing || comparison.options.iter().any(|o| o.data_missing);
+
+    html! {
+        div ."mt-4" {
+            (heading2(&format!("A heat pump at {}", comparison.address)))
+            p ."mt-2" {
+                "From " (comparison.from.format("%-d %b %Y")) " to " (comparison.to.format("%-d %b %Y"))
+                " a heat pump would have made "
+                span ."text-white" { (format!("{:.0}", comparison.heat)) " kWh" }
+                " of heat from "
+                span ."text-white" { (format!("{:.0}", comparison.heat_pump_energy)) " kWh" }
+                " of electricity, a seasonal COP of " (format!("{:.1}", comparison.scop())) ", "
+                @if comparison.keep_gas {
+                    "taking " (format!("{:.0}", comparison.gas_removed)) " kWh off your gas."
+                } @else {
+                    "replacing all " (format!("{:.0}", comparison.gas_removed)) " kWh of your gas and its standing charge."
+                }
+            }
+            table ."mt-2"."table-auto"."text-left" {
+                thead {
+                    tr ."text-white" {
+                        th ."pr-4" { "Setup" }
+                        th ."pr-4" { "Electricity" }
+                        th ."pr-4" { "Gas" }
+                        th ."pr-4" { "Total" }
+                        th ."pr-4" { "Against now" }
+                    }
+                }
+                tbody {
+                    tr ."border-b"."border-indigo-500" {
+                        td ."pr-4"."text-white" colspan="5" { "Now, with gas" }
+                    }
+                    (bill_row(&comparison.current, None))
+                    tr ."border-b"."border-indigo-500" {
+                        td ."pr-4"."text-white"."pt-2" colspan="5" { "With a heat pump" }
+                    }
+                    @for option in &comparison.options {
+                        (bill_row(option, Some(&comparison.current)))
+                    }
+                }
+            }
+            p ."mt-2"."text-sm" {
+                @if comparison.demand_from_gas {
+                    "Heat demand of " (format!("{:.0}", comparison.heat_demand)) " kWh a year worked out from your gas use with a 90% efficient boiler. "
+                } @else {
+                    "Heat demand of " (format!("{:.0}", comparison.heat_demand)) " kWh a year. "
+                }
+                "It's shared out by heating degree days with some for hot water every day. "
+                "The heat pump runs hardest for the morning and evening warm-ups, with almost a third of each day's "
+                "electricity moved into that tariff's cheapest six hours by heating ahead of time. "
+                "That's a rough guide to how it would do on Cosy or Agile rather than a quote."
+                @if comparison.days_without_weather > 0 {
+                    " " (comparison.days_without_weather) " days without weather are left out of every bill."
+                }
+                @if any_missing {
+                    " * Prices didn't cover all of the readings, so this one's short."
+                }
+            }
+        }
+    }
+}
@@ -1,27 +1,29 @@
 use crate::{
-    api::AccountProperty,
+    api::{agreement_at, AccountProperty},
+    config::Features,
+    credentials::CredentialErrors,
+    heatpump::DEFAULT_COP_CURVE,
     ui::layout::{heading1, heading2, page, post_button},
 };
 use maud::{html, Markup};
 
-pub async fn welcome() -> Markup {
+pub fn welcome(features: Features) -> Markup {
     page(
         "OctoCompare | Home",
         html! {
             (heading1("About"))
             p { "Welcome! OctoCompare is all about which Octopus Energy tariff works best using your historical consumption. This is for interest and information only and does not constitute a recommendation for a particular tariff." }
             p."mt-2" { "If you're happy with that, let's dive in." }
+            @if features.workspace {
+                p."mt-2"."text-sm" { "Looking after more than one account? Use the " a href="/workspace" ."underline" { "workspace" } " to compare several properties at once." }
+            }
+            @if features.reports {
+                p."mt-2"."text-sm" { "Finished comparisons end up in " a href="/reports" ."underline" { "saved reports" } "." }
+            }
             div."border-indigo-500"."border-2"."rounded"."p-4"."w-96"."mt-4" {
                 (heading2("Your Details"))
                 form ."flex"."flex-col" {
-                    div."mt-2" {
-                        label for="account_number" ."w-32"."inline-block"."mr-2" { "Account number" }
-                        input name="account_number" #"account_number" placeholder="A-000000A0" ."rounded"."mt-2"."text-slate-800" {}
-                    }
-                    div."mt-2" {
-                        label for="api_key" ."w-32"."inline-block"."mr-2" { "Api key" }
-                        input name="api_key" #"api_key" placeholder="sk_live_AAa4a" ."rounded"."mt-2"."text-slate-800" {}
-                    }
+                    (credential_fields("", "", &CredentialErrors::default(), false))
                     (post_button("/account-details", "#property-result", "let's go!"))
                 }
             }
@@ -32,19 +34,53 @@ pub async fn welcome() -> Markup {
     )
 }
 
+pub const CREDENTIAL_FIELDS: &str = "#credential-fields";
+
+/// The account number and API key inputs, with whatever was wrong with them
+/// last time underneath. Sent back on their own when they don't check out, or
+/// out of band to clear old errors once they do.
+pub fn credential_fields(
+    account_number: &str,
+    api_key: &str,
+    errors: &CredentialErrors,
+    oob: bool,
+) -> Markup {
+    html! {
+        div #"credential-fields" hx-swap-oob=[oob.then_some("true")] {
+            div."mt-2" {
+                label for="account_number" ."w-32"."inline-block"."mr-2" { "Account number" }
+                input name="account_number" #"account_number" value=(account_number) placeholder="A-000000A0" aria-invalid=[errors.account_number.as_ref().map(|_| "true")] ."rounded"."mt-2"."text-slate-800" {}
+                @if let Some(error) = &errors.account_number {
+                    p ."mt-1"."text-sm"."text-red-400" { (error) }
+                }
+            }
+            div."mt-2" {
+                label for="api_key" ."w-32"."inline-block"."mr-2" { "Api key" }
+                input name="api_key" #"api_key" value=(api_key) placeholder="sk_live_AAa4a" aria-invalid=[errors.api_key.as_ref().map(|_| "true")] ."rounded"."mt-2"."text-slate-800" {}
+                @if let Some(error) = &errors.api_key {
+                    p ."mt-1"."text-sm"."text-red-400" { (error) }
+                }
+            }
+        }
+    }
+}
+
 pub fn account_details(
-    active_properties: Vec<&AccountProperty>,
+    properties: Vec<&AccountProperty>,
     api_key: &str,
     account_number: &str,
+    features: Features,
 ) -> Markup {
-    let first_property = active_properties.iter().next();
+    let first_property = properties.first();
     html!(
-        (heading2("Active Properties"))
+        (heading2("Properties"))
         form {
             input name="api_key" type="hidden" value=(api_key) { }
             input name="account_number" type="hidden" value=(account_number) { }
 
-            @for property in &active_properties {
+            @for property in &properties {
+                // Past properties show the tariffs they were on when the account left
+                @let end = property.tenancy().end();
                 @if first_property.unwrap().id == property.id {
                     input #"property_id" name="property_id" type="radio" value=(property.id.to_string()) checked;
                 } @else {
@@ -53,12 +89,17 @@ pub fn account_details(
                 label for="property_id" .font-bold.text-white."mt-2"."ml-2" {
                     (property.address_line_1) ", " (property.postcode)
                 }
+                @if let Some(moved_out_at) = property.moved_out_at {
+                    span ."ml-2"."text-sm" {
+                        "(moved out, lived here " (property.moved_in_at.format("%-d %b %Y")) " to " (moved_out_at.format("%-d %b %Y")) ")"
+                    }
+                }
                 div ."flex"."flex-row" {
                     div ."basis-1/2" ."border-indigo-500" ."border-e-2" ."px-4" ."py-2" {
                         p { "Electricity Meter Points"}
                         ul {
                             @for emp in &property.electricity_meter_points {
-                                @let agreement = emp.agreements.iter().filter(|a| a.valid_from < chrono::offset::Utc::now() && chrono::offset::Utc::now() < a.valid_to).next();
+                                @let agreement = agreement_at(&emp.agreements, end);
                                 li ."mb-2" {
                                     "MPAN: "
                                     span ."text-white" {
@@ -79,7 +120,7 @@ pub fn account_details(
                         p { "Gas Meter Points"}
                         ul {
                             @for gmp in &property.gas_meter_points {
-                                @let agreement = gmp.agreements.iter().filter(|a| a.valid_from < chrono::offset::Utc::now() && chrono::offset::Utc::now() < a.valid_to).next();
+                                @let agreement = agreement_at(&gmp.agreements, end);
                                 li ."mb-2" {
                                     "MPRN: "
                                     span ."text-white" { (gmp.mprn) }
@@ -93,10 +134,195 @@ pub fn account_details(
                     }
                 }
             }
+            (ev_fields())
+            (custom_tariff_field())
+            div ."mt-4" {
+                input #"fill_gaps" name="fill_gaps" type="checkbox";
+                label for="fill_gaps" ."ml-2" { "Fill gaps of up to a day in the readings by interpolating" }
+            }
             (post_button("/compare-tariffs", "#comparison-result", "compare some tariffs"))
+            @if features.forecast {
+                (forecast_fields())
+            }
+            @if features.alerts {
+                (alert_fields())
+            }
+            @if features.reconcile {
+                (reconcile_fields())
+            }
+            @if features.carbon {
+                (carbon_fields())
+            }
+            @if features.heat_pump {
+                (heat_pump_fields())
+            }
+        }
+        div #"forecast-result" {
+
+        }
+        div #"reconcile-result" {
+
+        }
+        div #"carbon-result" {
+
+        }
+        div #"heat-pump-result" {
+
+        }
+        div #"subscription-result" {
+
         }
         div #"comparison-result" {
 
         }
     )
 }
+
+fn ev_fields() -> Markup {
+    html!(
+        details ."mt-4" {
+            summary { "Got an electric car?" }
+            div ."flex"."flex-col"."ml-4" {
+                div ."mt-2" {
+                    input #"ev" name="ev" type="checkbox";
+                    label for="ev" ."ml-2" { "Add smart charging and compare Octopus Go and Intelligent Octopus Go" }
+                }
+                (input_field("ev_battery_kwh", "Battery (kWh)", "number", "60"))
+                (input_field("ev_daily_miles", "Miles per day", "number", "25"))
+                (input_field("ev_miles_per_kwh", "Miles per kWh", "number", "3.5"))
+                (input_field("ev_charger_kw", "Charger (kW)", "number", "7"))
+                (input_field("ev_plug_in", "Plugged in at", "time", "18:00"))
+                (input_field("ev_ready_by", "Ready by", "time", "07:00"))
+                p ."mt-2"."text-sm" {
+                    "With more than one electricity meter, the charging goes on the first one."
+                }
+            }
+        }
+    )
+}
+
+fn input_field(name: &str, label: &str, input_type: &str, value: &str) -> Markup {
+    html!(
+        div ."mt-2" {
+            label for=(name) ."w-32"."inline-block"."mr-2" { (label) }
+            input name=(name) #(name) type=(input_type) step="any" value=(value) ."rounded"."text-slate-800" {}
+        }
+    )
+}
+
+fn custom_tariff_field() -> Markup {
+    html!(
+        details ."mt-4" {
+            summary { "Compare a tariff from another supplier?" }
+            div ."flex"."flex-col"."ml-4" {
+                p ."mt-2"."text-sm" {
+                    "Paste a tariff definition in TOML or JSON, with its standing charge, rate bands and any exit fee. "
+                    "See the " code { "tariffs" } " directory for examples."
+                }
+                textarea name="custom_tariff" rows="8" ."mt-2"."rounded"."text-slate-800"."font-mono"."text-sm" {}
+            }
+        }
+    )
+}
+
+fn forecast_fields() -> Markup {
+    html!(
+        details ."mt-4" {
+            summary { "What would tomorrow cost on Agile?" }
+            div ."flex"."flex-col"."ml-4" {
+                (input_field("appliance_hours", "Appliance runs for (hours)", "number", "2"))
+                (post_button("/forecast", "#forecast-result", "see tomorrow"))
+            }
+        }
+    )
+}
+
+fn alert_fields() -> Markup {
+    html!(
+        details ."mt-4" {
+            summary { "Tell me when another tariff would be cheaper" }
+            div ."flex"."flex-col"."ml-4" {
+                p ."mt-2"."text-sm" {
+                    "We'll re-run this comparison every month and let you know if the cheapest tariff beats yours by more than this. "
+                    "We have to keep your API key to do that."
+                }
+                (input_field("alert_threshold", "Saving over (£)", "number", "50"))
+                (input_field("alert_webhook", "Webhook URL", "url", ""))
+                (input_field("alert_email", "or email", "email", ""))
+                (post_button("/subscriptions", "#subscription-result", "alert me"))
+            }
+        }
+    )
+}
+
+fn reconcile_fields() -> Markup {
+    html!(
+        details ."mt-4" {
+            summary { "Check the costs against my bills" }
+            div ."flex"."flex-col"."ml-4" {
+                p ."mt-2"."text-sm" {
+                    "We'll price your last few bills with the tariff you were on and show how far out we are. "
+                    "Leave this empty to use your bills from Octopus, or paste a statement as CSV with "
+                    code { "from" } ", " code { "to" } " and " code { "amount" } " (in pounds) columns."
+                }
+                textarea name="statement" rows="6" placeholder="from,to,amount\n2024-01-01,2024-01-31,84.20"
+                    ."mt-2"."rounded"."text-slate-800"."font-mono"."text-sm" {}
+                div ."mt-2" {
+                    input #"reconcile_gas_m3" name="gas_m3" type="checkbox";
+                    label for="reconcile_gas_m3" ."ml-2" { "My gas meter reads in cubic metres (most SMETS2 meters do)" }
+                }
+                (post_button("/reconcile", "#reconcile-result", "check my bills"))
+            }
+        }
+    )
+}
+
+fn carbon_fields() -> Markup {
+    html!(
+        details ."mt-4" {
+            summary { "How much carbon does my electricity use?" }
+            div ."flex"."flex-col"."ml-4" {
+                p ."mt-2"."text-sm" {
+                    "We'll match your last year of half-hourly readings to the grid's carbon intensity in your region, "
+                    "and see how much moving some of each day's use to the cheapest Agile half hours would save. "
+                    "Leave this empty to use National Grid's figures, or paste your own as CSV with "
+                    code { "from" } " (UTC) and " code { "intensity" } " (gCO2/kWh) columns."
+                }
+                (input_field("flexible_percent", "Use that could move (%)", "number", "20"))
+                textarea name="intensity" rows="6" placeholder="from,intensity\n2024-01-01T00:00Z,182"
+                    ."mt-2"."rounded"."text-slate-800"."font-mono"."text-sm" {}
+                (post_button("/carbon", "#carbon-result", "work out my emissions"))
+            }
+        }
+    )
+}
+
+fn heat_pump_fields() -> Markup {
+    html!(
+        details ."mt-4" {
+            summary { "Would a heat pump and Cosy Octopus work for me?" }
+            div ."flex"."flex-col"."ml-4" {
+                p ."mt-2"."text-sm" {
+                    "We'll add a heat pump to your last year of electricity readings in place of your gas boiler, "
+                    "and price it on Cosy Octopus, Agile and Flexible against what you're on now. "
+                    "Paste a year of outdoor temperatures as CSV with " code { "date" } " and "
+                    code { "temperature" } " (°C) columns, daily or hourly."
+                }
+                (input_field("heat_demand", "Heat a year (kWh)", "number", ""))
+                p ."mt-1"."text-xs" { "Leave empty to work it out from your gas use." }
+                (input_field("cop_curve", "COP at °C", "text", DEFAULT_COP_CURVE))
+                textarea name="weather" rows="6" placeholder="date,temperature\n2024-01-01,4.2"
+                    ."mt-2"."rounded"."text-slate-800"."font-mono"."text-sm" {}
+                div ."mt-2" {
+                    input #"gas_m3" name="gas_m3" type="checkbox";
+                    label for="gas_m3" ."ml-2" { "My gas meter reads in cubic metres (most SMETS2 meters do)" }
+                }
+                div ."mt-2" {
+                    input #"keep_gas" name="keep_gas" type="checkbox";
+                    label for="keep_gas" ."ml-2" { "Keep the gas supply for cooking" }
+                }
+                (post_button("/heat-pump", "#heat-pump-result", "try a heat pump"))
+            }
+        }
+    )
+}
@@ -1,4 +1,6 @@
-use maud::{html, Markup, DOCTYPE};
+use maud::{html, Markup, PreEscaped, DOCTYPE};
+
+use crate::assets::asset_url;
 
 pub fn page(title: &str, content: Markup) -> Markup {
     /// A basic header with a dynamic `page_title`.
@@ -9,8 +11,22 @@ pub fn page(title: &str, content: Markup) -> Markup {
             head {
                 meta charset="utf-8";
                 meta name="viewport" content="width=device-width, initial-scale=1.0";
-                link rel="stylesheet" type="text/css" href="/assets/style.css";
-                script src="https://unpkg.com/htmx.org@1.9.11"
+                link rel="stylesheet" type="text/css" href=(asset_url("style.css"));
+                script src=(asset_url("htmx.min.js")) {}
+                // htmx leaves error responses alone unless told otherwise, and
+                // ours say where they want to go with HX-Retarget. Anything
+                // polling gives up, as asking again won't go any better
+                script {
+                    (PreEscaped(r#"document.addEventListener("htmx:beforeSwap", function (e) {
+                        if (e.detail.xhr.status >= 400 && e.detail.xhr.getResponseHeader("HX-Retarget")) {
+                            e.detail.shouldSwap = true;
+                            e.detail.isError = false;
+                            if (/^every /.test(e.detail.requestConfig.elt.getAttribute("hx-trigger") || "")) {
+                                e.detail.requestConfig.elt.remove();
+                            }
+                        }
+                    });"#))
+                }
                 title { (page_title) }
             }
         }
@@ -33,6 +49,7 @@ pub fn page(title: &str, content: Markup) -> Markup {
             (header())
 
             main ."container"."mx-auto"."mt-2" {
+                div #"notifications" {}
                 (content)
             }
         }
@@ -64,3 +81,19 @@ pub(crate) fn post_button(post_to: &str, target: &str, content: &str) -> Markup
             }
     }
 }
+
+/// Where errors end up, so they don't replace whatever was on the page.
+pub const NOTIFICATIONS: &str = "#notifications";
+
+/// Something that went wrong, with what to do about it and a reference to
+/// find it in the logs.
+pub fn error_notice(title: &str, detail: &str, hint: &str, correlation_id: &str) -> Markup {
+    html! {
+        div role="alert" ."border-red-500"."border-2"."rounded"."p-4"."mt-4"."text-sm" {
+            p ."font-bold"."text-white" { (title) }
+            p ."mt-1" { (detail) }
+            p ."mt-1" { (hint) }
+            p ."mt-1"."text-xs" { "Reference: " code { (correlation_id) } }
+        }
+    }
+}
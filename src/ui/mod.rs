@@ -1,2 +1,11 @@
+pub mod carbon;
+pub mod chart;
+pub mod compare;
+pub mod forecast;
+pub mod heatpump;
 pub mod home;
 pub mod layout;
+pub mod reconcile;
+pub mod reports;
+pub mod subscriptions;
+pub mod workspace;
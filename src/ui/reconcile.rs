@@ -0,0 +1,93 @@
+use crate::{
+    reconcile::{ReconciledPeriod, Reconciliation, DISCREPANCY_THRESHOLD},
+    ui::layout::heading2,
+};
+use maud::{html, Markup};
+
+fn pounds(pence: f64) -> String {
+    format!("£{:.2}", pence / 100.0)
+}
+
+fn difference(period: &ReconciledPeriod) -> Markup {
+    match period.discrepancy() {
+        Some(d) if d.abs() > DISCREPANCY_THRESHOLD * 2.0 => {
+            html! { strong ."text-red-400" { (format!("{:+.1}%", d)) } }
+        }
+        Some(d) if d.abs() > DISCREPANCY_THRESHOLD => {
+            html! { strong ."text-amber-400" { (format!("{:+.1}%", d)) } }
+        }
+        Some(d) => html! { (format!("{:+.1}%", d)) },
+        None => html! { "-" },
+    }
+}
+
+pub fn reconcile_result(reconciliation: &Reconciliation) -> Markup {
+    html! {
+        div ."mt-4" {
+            (heading2(&format!("Bills for {}", reconciliation.address)))
+            @if reconciliation.periods.is_empty() {
+                p ."mt-2" { "Octopus didn't have any bills with dates and charges for this account." }
+            } @else {
+                table ."mt-2"."table-auto"."text-left" {
+                    thead {
+                        tr ."text-white" {
+                            th ."pr-4" { "Period" }
+                            th ."pr-4" { "Tariff" }
+                            th ."pr-4" { "Billed" }
+                            th ."pr-4" { "Computed" }
+                            th ."pr-4" { "Difference" }
+                        }
+                    }
+                    tbody {
+                        @for period in &reconciliation.periods {
+                            tr {
+                                td ."pr-4" {
+                                    (period.billed.from.format("%-d %b %Y")) " to " (period.billed.to.format("%-d %b %Y"))
+                                }
+                                td ."pr-4" {
+                                    @match (&period.display_name, &period.tariff_code) {
+                                        (Some(name), Some(code)) => {
+                                            span ."text-white" { (name) }
+                                            " " span ."text-xs" { (code) }
+                                        }
+                                        (None, Some(code)) => span ."text-xs" { (code) " (not compared)" },
+                                        _ => "changed part way through",
+                                    }
+                                }
+                                td ."pr-4" { (pounds(period.billed.amount)) }
+                                td ."pr-4"."text-white" {
+                                    @if let Some(computed) = period.computed {
+                                        (pounds(computed))
+                                        @if let Some(gas) = period.gas {
+                                            " " span ."text-xs" { "(" (pounds(gas)) " gas)" }
+                                        }
+                                    } @else if period.days_missing > 0 && period.display_name.is_some() {
+                                        span ."text-xs" { (period.days_missing) " days without readings" }
+                                    } @else {
+                                        "-"
+                                    }
+                                }
+                                td ."pr-4" { (difference(period)) }
+                            }
+                        }
+                    }
+                }
+                p ."mt-2"."text-sm" {
+                    "Differences over " (DISCREPANCY_THRESHOLD) "% are highlighted. "
+                    @if reconciliation.from_octopus && reconciliation.has_gas {
+                        "Computed costs are electricity import and gas, priced from your half-hourly readings, "
+                        "as Octopus bills cover everything on the account. Gas is billed on the actual "
+                        "calorific value and the bills include any adjustments and credits, so expect "
+                        "them to come out a little different."
+                    } @else if reconciliation.from_octopus {
+                        "Computed costs are electricity import only, priced from your half-hourly readings."
+                        " Octopus bills cover everything on the account, plus any adjustments and "
+                        "credits, so expect them to come out higher."
+                    } @else {
+                        "Computed costs are electricity import only, priced from your half-hourly readings."
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use crate::{
+    reports::Report,
+    ui::{
+        compare::comparison_result,
+        layout::{heading1, page},
+    },
+};
+use maud::{html, Markup};
+
+pub fn report_page(report: &Report) -> Markup {
+    page(
+        "OctoCompare | Report",
+        html! {
+            (heading1("Saved comparison"))
+            p ."mt-2"."text-sm" {
+                "Saved " (report.created_at.format("%-d %B %Y %H:%M")) " UTC. "
+                a href="/reports" ."underline" { "Your saved reports" }
+            }
+            (comparison_result(&report.comparison))
+        },
+    )
+}
+
+/// Link to a report once it's been saved, shown under a fresh comparison.
+pub fn report_link(id: &str) -> Markup {
+    html! {
+        p ."mt-4"."text-sm" {
+            "Saved. Share or come back to it at "
+            a href=(format!("/reports/{}", id)) ."underline"."text-white" { "/reports/" (id) }
+        }
+    }
+}
+
+pub fn history(reports: &[Report]) -> Markup {
+    page(
+        "OctoCompare | Saved reports",
+        html! {
+            (heading1("Saved reports"))
+            p ."mt-2"."text-sm" {
+                "Comparisons saved from this browser. Anyone you share a link with can see one, but only this browser can delete it."
+            }
+            @if reports.is_empty() {
+                p ."mt-2" { "Nothing saved yet. Comparisons are saved here once they finish." }
+            } @else {
+                table ."mt-4"."table-auto"."text-left" {
+                    thead {
+                        tr ."text-white" {
+                            th ."pr-4" { "Saved" }
+                            th ."pr-4" { "Property" }
+                            th ."pr-4" { "Cheapest" }
+                            th ."pr-4" {}
+                        }
+                    }
+                    tbody {
+                        @for report in reports {
+                            tr {
+                                td ."pr-4" { (report.created_at.format("%Y-%m-%d %H:%M")) }
+                                td ."pr-4" {
+                                    a href=(format!("/reports/{}", report.id)) ."underline"."text-white" { (report.comparison.address) }
+                                }
+                                td ."pr-4" {
+                                    @if let Some(cheapest) = report.comparison.cheapest() {
+                                        (cheapest.display_name) " £" (format!("{:.2}", cheapest.total() / 100.0))
+                                    } @else { "-" }
+                                }
+                                td ."pr-4" {
+                                    button
+                                        hx-delete=(format!("/reports/{}", report.id))
+                                        hx-confirm="Delete this report?"
+                                        hx-target="closest tr"
+                                        hx-swap="outerHTML"
+                                        ."text-sm"."underline" {
+                                            "delete"
+                                        }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        },
+    )
+}
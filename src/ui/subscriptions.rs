@@ -0,0 +1,21 @@
+use crate::alerts::Subscription;
+use maud::{html, Markup};
+
+pub fn subscribed(subscription: &Subscription) -> Markup {
+    html! {
+        div ."mt-2" {
+            p {
+                "You're signed up. We'll check again on "
+                span ."text-white" { (subscription.next_run().format("%-d %B %Y")) }
+                " and let you know if you could save more than £" (format!("{:.2}", subscription.threshold)) "."
+            }
+            button
+                hx-delete=(format!("/subscriptions/{}", subscription.id))
+                hx-target="closest div"
+                hx-swap="outerHTML"
+                ."text-sm"."underline"."mt-2" {
+                    "stop alerts"
+                }
+        }
+    }
+}
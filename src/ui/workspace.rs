@@ -0,0 +1,161 @@
+use crate::{
+    api::AccountProperty,
+    credentials::CredentialErrors,
+    ui::{
+        compare::comparison_result,
+        home::credential_fields,
+        layout::{heading1, heading2, page, post_button},
+        reports::report_link,
+    },
+    workspace::{batch_totals, PropertyOutcome},
+};
+use maud::{html, Markup};
+
+fn pounds(pence: f64) -> String {
+    format!("£{:.2}", pence / 100.0)
+}
+
+pub async fn workspace() -> Markup {
+    page(
+        "OctoCompare | Workspace",
+        html! {
+            (heading1("Workspace"))
+            p { "Add as many accounts as you like, tick the properties you're interested in and compare them all in one go." }
+            div."border-indigo-500"."border-2"."rounded"."p-4"."w-96"."mt-4" {
+                (heading2("Add an account"))
+                form ."flex"."flex-col" {
+                    (credential_fields("", "", &CredentialErrors::default(), false))
+                    button
+                        hx-post="/workspace/accounts"
+                        hx-target="#workspace-accounts"
+                        hx-swap="beforeend"
+                        type="button"
+                        ."ml-auto"."text-white"."focus:ring-4"."font-medium"."rounded-lg"."text-sm"."px-2.5"."py-1"."me-4"."mb-2"
+                        ."bg-blue-600"."hover:bg-blue-700"."focus:outline-none"."focus:ring-blue-800"."mt-4" {
+                            "add"
+                        }
+                }
+            }
+            form ."mt-4" {
+                div #"workspace-accounts" {}
+                div ."mt-4" {
+                    input #"fill_gaps" name="fill_gaps" type="checkbox";
+                    label for="fill_gaps" ."ml-2" { "Fill gaps of up to a day in the readings by interpolating" }
+                }
+                (post_button("/workspace/compare", "#workspace-result", "compare ticked properties"))
+            }
+            div #"workspace-result" {}
+        },
+    )
+}
+
+/// An account's properties, added to the workspace form. The account's
+/// credentials ride along in a hidden field like the single account form.
+pub fn workspace_account(
+    properties: Vec<&AccountProperty>,
+    api_key: &str,
+    account_number: &str,
+) -> Markup {
+    html! {
+        div ."mt-4" {
+            p ."text-white"."font-bold" { "Account " (account_number) }
+            input name="account" type="hidden" value=(format!("{}:{}", account_number, api_key));
+            @if properties.is_empty() {
+                p ."ml-4" { "No properties on this account." }
+            }
+            @for property in &properties {
+                @let value = format!("{}:{}", account_number, property.id);
+                div ."ml-4"."mt-2" {
+                    input #(value) name="property" type="checkbox" value=(value) checked[property.moved_out_at.is_none()];
+                    label for=(value) ."ml-2" {
+                        (property.address_line_1) ", " (property.postcode)
+                        @if let Some(moved_out_at) = property.moved_out_at {
+                            span ."text-sm" { " (moved out " (moved_out_at.format("%-d %b %Y")) ")" }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn batch_result(outcomes: &[PropertyOutcome]) -> Markup {
+    let totals = batch_totals(outcomes);
+
+    html! {
+        div ."mt-4" {
+            (heading2("Summary"))
+            table ."mt-2"."table-auto"."text-left" {
+                thead {
+                    tr ."text-white" {
+                        th ."pr-4" { "Account" }
+                        th ."pr-4" { "Property" }
+                        th ."pr-4" { "Current" }
+                        th ."pr-4" { "Cheapest" }
+                        th ."pr-4" { "Saving" }
+                    }
+                }
+                tbody {
+                    @for outcome in outcomes {
+                        tr {
+                            td ."pr-4" { (outcome.account_number) }
+                            @match &outcome.result {
+                                Ok(comparison) => {
+                                    @let current = comparison.current();
+                                    @let cheapest = comparison.cheapest();
+                                    td ."pr-4"."text-white" { (comparison.address) }
+                                    td ."pr-4" {
+                                        @if let Some(current) = current {
+                                            (current.display_name) " " (pounds(current.total()))
+                                        } @else { "-" }
+                                    }
+                                    td ."pr-4" {
+                                        @if let Some(cheapest) = cheapest {
+                                            (cheapest.display_name) " " (pounds(cheapest.total()))
+                                        } @else { "-" }
+                                    }
+                                    td ."pr-4"."text-white" {
+                                        @if let (Some(current), Some(cheapest)) = (current, cheapest) {
+                                            (pounds(current.total() - cheapest.total()))
+                                        } @else { "-" }
+                                    }
+                                }
+                                Err(e) => {
+                                    td ."pr-4" colspan="4" { "Property " (outcome.property_id) " failed: " (e) }
+                                }
+                            }
+                        }
+                    }
+                }
+                tfoot {
+                    tr ."text-white"."border-t-2"."border-indigo-500" {
+                        td ."pr-4" { "Total" }
+                        td ."pr-4" {}
+                        td ."pr-4" { (pounds(totals.current)) }
+                        td ."pr-4" { (pounds(totals.cheapest)) }
+                        td ."pr-4" { (pounds(totals.saving())) }
+                    }
+                    @if totals.properties < outcomes.len() {
+                        tr {
+                            td ."pr-4"."text-sm" colspan="5" {
+                                "Totals cover the " (totals.properties) " of " (outcomes.len())
+                                " properties with both a current and a cheapest tariff."
+                            }
+                        }
+                    }
+                }
+            }
+            @for outcome in outcomes {
+                @if let Ok(comparison) = &outcome.result {
+                    details ."mt-4" {
+                        summary { (comparison.address) " (" (outcome.account_number) ")" }
+                        (comparison_result(comparison))
+                        @if let Some(report) = &outcome.report {
+                            (report_link(&report.id))
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
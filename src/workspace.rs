@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use crate::{
+    api::{AccountResponse, OctopusClient},
+    compare::{compare_property, CompareRequest, Comparison, Progress, ReportProgress},
+    reports::SavedReport,
+};
+
+/// How one property in a batch got on. One property failing (a revoked API
+/// key, say) shouldn't lose the rest of the batch.
+#[derive(Debug, Clone)]
+pub struct PropertyOutcome {
+    pub account_number: String,
+    pub property_id: f64,
+    pub result: Result<Comparison, String>,
+    /// Set once the comparison has been saved as a report
+    pub report: Option<SavedReport>,
+}
+
+/// Compares a batch of properties, which can be spread across several
+/// accounts. They run one after another so a big batch doesn't hammer the
+/// API, sharing a client so prices for the same region are only fetched once.
+/// Each account is only fetched once too, however many of its properties are
+/// in the batch.
+pub async fn compare_batch(
+    client: &OctopusClient,
+    requests: Vec<CompareRequest>,
+    progress: &dyn ReportProgress,
+) -> Result<Vec<PropertyOutcome>> {
+    let total = requests.len();
+
+    let mut accounts: HashMap<String, Result<AccountResponse, String>> = HashMap::new();
+    let mut outcomes = vec![];
+    for (i, request) in requests.iter().enumerate() {
+        progress.report(Progress::ComparingProperty {
+            account_number: request.account_number.clone(),
+            index: i + 1,
+            total,
+        });
+        if !accounts.contains_key(&request.account_number) {
+            progress.report(Progress::FetchingAccount);
+            let account = client
+                .get_account_details(&request.api_key, &request.account_number)
+                .await
+                .map_err(|e| e.to_string());
+            accounts.insert(request.account_number.clone(), account);
+        }
+        let result = match &accounts[&request.account_number] {
+            Ok(account) => compare_property(client, request, account, progress)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.clone()),
+        };
+        outcomes.push(PropertyOutcome {
+            account_number: request.account_number.clone(),
+            property_id: request.property_id,
+            result,
+            report: None,
+        });
+    }
+
+    Ok(outcomes)
+}
+
+/// What the batch summary adds up to. Only properties with both a current
+/// and a cheapest tariff count, so every column covers the same properties.
+#[derive(Debug, Default, PartialEq)]
+pub struct BatchTotals {
+    /// Pence
+    pub current: f64,
+    /// Pence
+    pub cheapest: f64,
+    pub properties: usize,
+}
+
+impl BatchTotals {
+    pub fn saving(&self) -> f64 {
+        self.current - self.cheapest
+    }
+}
+
+pub fn batch_totals(outcomes: &[PropertyOutcome]) -> BatchTotals {
+    let mut totals = BatchTotals::default();
+    for comparison in outcomes.iter().filter_map(|o| o.result.as_ref().ok()) {
+        if let (Some(current), Some(cheapest)) = (comparison.current(), comparison.cheapest()) {
+            totals.current += current.total();
+            totals.cheapest += cheapest.total();
+            totals.properties += 1;
+        }
+    }
+    totals
+}
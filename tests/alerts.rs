@@ -0,0 +1,118 @@
+use octocompare::alerts::{check_email, check_webhook};
+
+#[tokio::test]
+async fn webhooks_have_to_be_https() {
+    let error = check_webhook("http://8.8.8.8/hook").await.unwrap_err();
+    assert!(error.to_string().contains("https://"));
+}
+
+#[tokio::test]
+async fn webhooks_cant_reach_private_addresses() {
+    for url in [
+        "https://127.0.0.1/hook",
+        "https://10.1.2.3/hook",
+        "https://192.168.0.10:8443/hook",
+        "https://169.254.169.254/latest/meta-data",
+        "https://100.64.0.1/hook",
+        "https://0.0.0.0/hook",
+        "https://[::1]/hook",
+        "https://[fd00::1]/hook",
+        "https://[::ffff:127.0.0.1]/hook",
+        "https://[::ffff:10.0.0.1]/hook",
+        "https://[::127.0.0.1]/hook",
+        "https://[::192.168.1.1]/hook",
+        "https://[64:ff9b::7f00:1]/hook",
+        "https://[64:ff9b:1::a00:1]/hook",
+        "https://[2002:7f00:1::1]/hook",
+        "https://[2002:808:808::1]/hook",
+        "https://[2001:db8::1]/hook",
+    ] {
+        let error = check_webhook(url).await.unwrap_err();
+        assert!(
+            error.to_string().contains("isn't a public address"),
+            "{}: {}",
+            url,
+            error
+        );
+    }
+}
+
+#[tokio::test]
+async fn public_addresses_in_ipv6_clothing_are_allowed() {
+    for url in ["https://[::ffff:8.8.8.8]/hook", "https://[::8.8.8.8]/hook"] {
+        assert!(check_webhook(url).await.is_ok(), "{}", url);
+    }
+}
+
+#[test]
+fn emails_have_to_be_addresses() {
+    for email in [
+        "someone@example.com",
+        "first.last+alerts@mail.example.co.uk",
+    ] {
+        assert!(check_email(email).is_ok(), "{}", email);
+    }
+    for email in [
+        "",
+        "someone",
+        "someone@",
+        "@example.com",
+        "some one@example.com",
+    ] {
+        let error = check_email(email).unwrap_err();
+        assert!(
+            error.to_string().contains("isn't an email address"),
+            "{}: {}",
+            email,
+            error
+        );
+    }
+}
+
+#[tokio::test]
+async fn public_webhooks_are_pinned_to_their_address() {
+    let (url, host, address) = check_webhook("https://8.8.8.8/hook").await.unwrap();
+
+    assert_eq!(url.path(), "/hook");
+    assert_eq!(host, "8.8.8.8");
+    assert_eq!(address.to_string(), "8.8.8.8:443");
+}
+
+#[tokio::test]
+async fn webhooks_have_to_be_urls() {
+    assert!(check_webhook("not a url").await.is_err());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn subscriptions_are_only_readable_by_the_server() {
+    use octocompare::alerts::{new_subscription_id, Subscription, SubscriptionStore};
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join(new_subscription_id());
+    let path = dir.join("subscriptions.json");
+    std::fs::create_dir_all(&dir).unwrap();
+    // One left behind by an older version, readable by anyone
+    std::fs::write(&path, "[]").unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+    let store = SubscriptionStore::new(&path);
+    store
+        .add(Subscription {
+            id: new_subscription_id(),
+            api_key: "sk_live_secret".to_owned(),
+            account_number: "A-1234ABCD".to_owned(),
+            property_id: 1.0,
+            fill_gaps: false,
+            threshold: 50.0,
+            webhook: None,
+            email: Some("someone@example.com".to_owned()),
+            last_run: chrono::offset::Utc::now(),
+        })
+        .await
+        .unwrap();
+
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(mode & 0o777, 0o600);
+}
@@ -0,0 +1,119 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use axum::{routing::get, Json, Router};
+use octocompare::api::{ClientSettings, OctopusClient};
+use serde_json::json;
+
+/// An Octopus stand-in with one product and its prices. Counts the requests.
+async fn octopus() -> (String, Arc<AtomicUsize>) {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let (product, prices) = (requests.clone(), requests.clone());
+    let app = Router::new()
+        .route(
+            "/products/:product_code/",
+            get(move || async move {
+                product.fetch_add(1, Ordering::SeqCst);
+                Json(json!({
+                    "code": "AGILE-24-10-01",
+                    "full_name": "Agile Octopus October 2024 v1",
+                    "display_name": "Agile Octopus",
+                    "is_tracker": false,
+                    "is_variable": true,
+                }))
+            }),
+        )
+        .route(
+            "/products/:product_code/electricity-tariffs/:tariff_code/:prices",
+            get(move || async move {
+                prices.fetch_add(1, Ordering::SeqCst);
+                Json(json!({
+                    "count": 1,
+                    "next": null,
+                    "previous": null,
+                    "results": [{
+                        "value_exc_vat": 20.0,
+                        "value_inc_vat": 21.0,
+                        "valid_from": "2024-01-01T00:00:00Z",
+                        "valid_to": null,
+                    }],
+                }))
+            }),
+        );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (base_url, requests)
+}
+
+fn client(base_url: &str, cache_dir: Option<&PathBuf>) -> OctopusClient {
+    ClientSettings {
+        base_url: base_url.to_owned(),
+        cache_dir: cache_dir.cloned(),
+        ..Default::default()
+    }
+    .client()
+}
+
+fn cache_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("octocompare-{}", uuid::Uuid::new_v4()))
+}
+
+#[tokio::test]
+async fn prices_cached_on_disk_outlast_the_client() {
+    let (base_url, requests) = octopus().await;
+    let dir = cache_dir();
+
+    let first = client(&base_url, Some(&dir))
+        .get_pricing("E-1R-AGILE-24-10-01-C")
+        .await
+        .unwrap();
+    // The product, standing charges and unit rates
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+    // A restart, say
+    let second = client(&base_url, Some(&dir))
+        .get_pricing("E-1R-AGILE-24-10-01-C")
+        .await
+        .unwrap();
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+    assert_eq!(second.display_name, first.display_name);
+    assert_eq!(second.unit_charges.len(), 1);
+    assert_eq!(second.unit_charges[0].value_inc_vat, 21.0);
+
+    // Without the directory it's only cached in memory
+    client(&base_url, None)
+        .get_pricing("E-1R-AGILE-24-10-01-C")
+        .await
+        .unwrap();
+    assert_eq!(requests.load(Ordering::SeqCst), 6);
+}
+
+#[tokio::test]
+async fn unreadable_cache_files_are_fetched_again() {
+    let (base_url, requests) = octopus().await;
+    let dir = cache_dir();
+
+    client(&base_url, Some(&dir))
+        .get_product("AGILE-24-10-01")
+        .await
+        .unwrap();
+    let path = dir.join("product").join("AGILE-24-10-01.json");
+    assert!(path.is_file());
+    std::fs::write(&path, "{ not json").unwrap();
+
+    let product = client(&base_url, Some(&dir))
+        .get_product("AGILE-24-10-01")
+        .await
+        .unwrap();
+    assert_eq!(product.display_name, "Agile Octopus");
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+    // And written back
+    let cached = std::fs::read_to_string(&path).unwrap();
+    assert!(cached.contains("Agile Octopus"));
+}
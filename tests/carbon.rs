@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use octocompare::{
+    api::ConsumptionDatum,
+    carbon::{outward_code, parse_intensity, shift_to_agile},
+    pricing::Rates,
+};
+
+fn utc(s: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+}
+
+/// Agile-ish prices, only for the half hours given.
+struct Prices(HashMap<DateTime<Utc>, f64>);
+
+impl Rates for Prices {
+    fn unit_rate(&self, at: DateTime<Utc>) -> Option<f64> {
+        self.0.get(&at).copied()
+    }
+
+    fn standing_charge(&self, _day: NaiveDate) -> Option<f64> {
+        Some(50.0)
+    }
+}
+
+/// `count` half hours of 1kWh from `from`, priced by `price` (`None` for no
+/// price).
+fn day(
+    from: &str,
+    count: usize,
+    price: impl Fn(usize) -> Option<f64>,
+) -> (Vec<ConsumptionDatum>, Prices) {
+    let from = utc(from);
+    let mut readings = vec![];
+    let mut prices = HashMap::new();
+    for i in 0..count {
+        let start = from + Duration::minutes(30 * i as i64);
+        readings.push(ConsumptionDatum {
+            consumption: 1.0,
+            interval_start: start,
+            interval_end: start + Duration::minutes(30),
+        });
+        if let Some(p) = price(i) {
+            prices.insert(start, p);
+        }
+    }
+    (readings, Prices(prices))
+}
+
+fn total(readings: &[ConsumptionDatum]) -> f64 {
+    readings.iter().map(|d| d.consumption).sum()
+}
+
+#[test]
+fn moves_the_share_into_the_six_cheapest_half_hours() {
+    // Cheapest from 02:00 to 05:00
+    let (readings, prices) = day("2024-01-10T00:00:00Z", 48, |i| {
+        Some(if (4..10).contains(&i) { 5.0 } else { 25.0 })
+    });
+    let shifted = shift_to_agile(&readings, &prices, 0.2);
+
+    assert_eq!(shifted.len(), 48);
+    assert!((total(&shifted) - 48.0).abs() < 1e-9);
+    for (i, d) in shifted.iter().enumerate() {
+        let expected = if (4..10).contains(&i) {
+            0.8 + 48.0 * 0.2 / 6.0
+        } else {
+            0.8
+        };
+        assert!((d.consumption - expected).abs() < 1e-9, "slot {}", i);
+    }
+}
+
+#[test]
+fn leaves_days_missing_prices_alone() {
+    let (mut readings, mut prices) = day("2024-01-10T00:00:00Z", 48, |i| {
+        (i != 20).then_some(i as f64)
+    });
+    let (next, next_prices) = day("2024-01-11T00:00:00Z", 48, |i| Some(i as f64));
+    readings.extend(next);
+    prices.0.extend(next_prices.0);
+
+    let shifted = shift_to_agile(&readings, &prices, 0.5);
+
+    assert!(shifted[..48].iter().all(|d| d.consumption == 1.0));
+    assert!((shifted[48].consumption - (0.5 + 48.0 * 0.5 / 6.0)).abs() < 1e-9);
+    assert!((shifted[95].consumption - 0.5).abs() < 1e-9);
+}
+
+#[test]
+fn short_days_spread_the_share_over_what_there_is() {
+    let (readings, prices) = day("2024-01-10T00:00:00Z", 4, |i| Some(i as f64));
+    let shifted = shift_to_agile(&readings, &prices, 0.5);
+
+    assert_eq!(shifted.len(), 4);
+    assert!(shifted.iter().all(|d| (d.consumption - 1.0).abs() < 1e-9));
+}
+
+#[test]
+fn nothing_to_move_changes_nothing() {
+    let (readings, prices) = day("2024-01-10T00:00:00Z", 48, |i| Some(i as f64));
+    let shifted = shift_to_agile(&readings, &prices, 0.0);
+    assert!(shifted.iter().all(|d| d.consumption == 1.0));
+    assert!(shift_to_agile(&[], &prices, 0.5).is_empty());
+}
+
+#[test]
+fn reads_intensity_in_any_of_the_time_formats() {
+    let csv = "Intensity,From\n120,2024-01-31T23:30Z\n\"95.5\",2024-02-01T00:00:00+00:00\n80,2024-02-01 00:30\n";
+    let intensity = parse_intensity(csv).unwrap();
+
+    let times: Vec<_> = intensity.iter().map(|d| d.from).collect();
+    assert_eq!(
+        times,
+        [
+            utc("2024-01-31T23:30:00Z"),
+            utc("2024-02-01T00:00:00Z"),
+            utc("2024-02-01T00:30:00Z")
+        ]
+    );
+    let values: Vec<_> = intensity.iter().map(|d| d.intensity).collect();
+    assert_eq!(values, [120.0, 95.5, 80.0]);
+}
+
+#[test]
+fn reads_intensity_without_a_header() {
+    let intensity = parse_intensity("2024-01-31T23:30Z,120").unwrap();
+    assert_eq!(intensity.len(), 1);
+    assert_eq!(intensity[0].intensity, 120.0);
+}
+
+#[test]
+fn explains_what_is_wrong_with_the_intensity() {
+    let cases = [
+        (
+            "from,gco2\n2024-01-31T23:30Z,120",
+            "missing its intensity column",
+        ),
+        (
+            "2024-01-31T23:30Z,high",
+            "Line 1 of the carbon intensity file doesn't have an intensity",
+        ),
+        (
+            "from,intensity\n2024-01-31,120",
+            "Line 1 of the carbon intensity file doesn't have a time",
+        ),
+        ("from,intensity\n", "There weren't any half hours"),
+    ];
+    for (csv, expected) in cases {
+        let error = parse_intensity(csv).unwrap_err().to_string();
+        assert!(error.contains(expected), "{:?}: {}", csv, error);
+    }
+}
+
+#[test]
+fn outward_codes() {
+    let cases = [
+        ("SW1A 1AA", "SW1A"),
+        ("sw1a1aa", "SW1A"),
+        (" M1 1AE ", "M1"),
+        ("M11AE", "M1"),
+        ("B338TH", "B33"),
+        ("EC1A", "EC1A"),
+        ("", ""),
+    ];
+    for (postcode, expected) in cases {
+        assert_eq!(outward_code(postcode), expected, "{:?}", postcode);
+    }
+}
@@ -0,0 +1,188 @@
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use octocompare::{
+    api::{ConsumptionDatum, ConsumptionResponse},
+    compare::{merge_consumption, property_totals, MeterPointComparison, TariffCost},
+    pricing::DailyCost,
+};
+
+fn utc(s: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+}
+
+fn response(readings: &[(&str, f64)]) -> ConsumptionResponse {
+    ConsumptionResponse {
+        count: readings.len() as i64,
+        results: readings
+            .iter()
+            .map(|(start, consumption)| ConsumptionDatum {
+                consumption: *consumption,
+                interval_start: utc(start),
+                interval_end: utc(start) + Duration::minutes(30),
+            })
+            .collect(),
+        next: None,
+    }
+}
+
+#[test]
+fn merging_keeps_the_largest_reading_for_each_half_hour() {
+    let old_meter = response(&[("2024-01-10T00:30:00Z", 0.0), ("2024-01-10T00:00:00Z", 0.4)]);
+    let new_meter = response(&[
+        ("2024-01-10T01:00:00Z", 0.2),
+        ("2024-01-10T00:30:00Z", 0.3),
+        ("2024-01-10T00:00:00Z", 0.1),
+    ]);
+
+    let merged = merge_consumption(vec![old_meter, new_meter]);
+
+    let readings: Vec<_> = merged
+        .results
+        .iter()
+        .map(|d| (d.interval_start, d.consumption))
+        .collect();
+    assert_eq!(
+        readings,
+        [
+            (utc("2024-01-10T01:00:00Z"), 0.2),
+            (utc("2024-01-10T00:30:00Z"), 0.3),
+            (utc("2024-01-10T00:00:00Z"), 0.4),
+        ]
+    );
+    assert_eq!(merged.count, 3);
+    assert_eq!(merged.next, None);
+}
+
+#[test]
+fn merging_nothing_is_empty() {
+    let merged = merge_consumption(vec![]);
+    assert!(merged.results.is_empty());
+    assert_eq!(merged.count, 0);
+}
+
+fn day(d: u32, unit_cost: f64) -> DailyCost {
+    DailyCost {
+        date: NaiveDate::from_ymd_opt(2024, 1, d).unwrap(),
+        slots: 48,
+        consumption: unit_cost / 25.0,
+        unit_cost,
+        standing_cost: 50.0,
+    }
+}
+
+fn cost(code: &str, is_current: bool, unit_cost: f64, days: Vec<DailyCost>) -> TariffCost {
+    TariffCost {
+        tariff_code: code.to_owned(),
+        display_name: code.to_lowercase(),
+        supplier: None,
+        exit_fee: None,
+        switching_fee: 0.0,
+        is_current,
+        from: Some(utc("2024-01-01T00:00:00Z")),
+        to: Some(utc("2024-01-03T00:00:00Z")),
+        unit_cost,
+        standing_cost: 100.0,
+        data_missing: false,
+        days,
+        ev_energy: 0.0,
+        dispatches: 0,
+    }
+}
+
+fn meter_point(mpan: &str, tariffs: Vec<TariffCost>) -> MeterPointComparison {
+    MeterPointComparison {
+        mpan: mpan.to_owned(),
+        meters: vec![],
+        tariffs,
+        estimated_from: None,
+        quality: None,
+    }
+}
+
+#[test]
+fn current_tariffs_on_different_meter_points_become_one_row() {
+    let meter_points = [
+        meter_point(
+            "1000000000001",
+            vec![
+                cost("E-1R-A", true, 1000.0, vec![day(1, 600.0), day(2, 400.0)]),
+                cost("E-1R-X", false, 900.0, vec![day(1, 500.0), day(2, 400.0)]),
+            ],
+        ),
+        meter_point(
+            "1000000000002",
+            vec![
+                cost("E-1R-B", true, 300.0, vec![day(2, 300.0)]),
+                cost("E-1R-X", false, 200.0, vec![day(2, 200.0)]),
+            ],
+        ),
+    ];
+
+    let totals = property_totals(&meter_points, &["E-1R-X".to_owned()]);
+
+    assert_eq!(totals.len(), 2);
+    let candidate = &totals[0];
+    assert_eq!(candidate.tariff_code, "E-1R-X");
+    assert!(!candidate.is_current);
+    assert_eq!(candidate.unit_cost, 1100.0);
+    assert_eq!(candidate.standing_cost, 200.0);
+
+    let current = &totals[1];
+    assert_eq!(current.tariff_code, "E-1R-A + E-1R-B");
+    assert_eq!(current.display_name, "e-1r-a + e-1r-b");
+    assert!(current.is_current);
+    assert_eq!(current.unit_cost, 1300.0);
+    assert_eq!(current.days.len(), 2);
+    assert_eq!(current.days[1].unit_cost, 700.0);
+    assert_eq!(current.days[1].standing_cost, 100.0);
+}
+
+#[test]
+fn the_same_current_tariff_is_only_named_once() {
+    let meter_points = [
+        meter_point("1000000000001", vec![cost("E-1R-A", true, 100.0, vec![])]),
+        meter_point("1000000000002", vec![cost("E-1R-A", true, 50.0, vec![])]),
+    ];
+
+    let totals = property_totals(&meter_points, &[]);
+
+    assert_eq!(totals.len(), 1);
+    assert_eq!(totals[0].tariff_code, "E-1R-A");
+    assert_eq!(totals[0].unit_cost, 150.0);
+}
+
+#[test]
+fn a_candidate_only_some_meter_points_are_on_isnt_current() {
+    let meter_points = [
+        meter_point("1000000000001", vec![cost("E-1R-A", true, 100.0, vec![])]),
+        meter_point(
+            "1000000000002",
+            vec![
+                cost("E-1R-B", true, 50.0, vec![]),
+                cost("E-1R-A", false, 60.0, vec![]),
+            ],
+        ),
+    ];
+
+    let totals = property_totals(&meter_points, &["E-1R-A".to_owned()]);
+
+    assert_eq!(totals.len(), 2);
+    assert_eq!(totals[0].tariff_code, "E-1R-A");
+    assert!(!totals[0].is_current);
+    assert_eq!(totals[0].unit_cost, 160.0);
+    assert_eq!(totals[1].tariff_code, "E-1R-A + E-1R-B");
+    assert_eq!(totals[1].unit_cost, 150.0);
+}
+
+#[test]
+fn a_current_tariff_everywhere_needs_no_extra_row() {
+    let meter_points = [
+        meter_point("1000000000001", vec![cost("E-1R-A", true, 100.0, vec![])]),
+        meter_point("1000000000002", vec![cost("E-1R-A", true, 50.0, vec![])]),
+    ];
+
+    let totals = property_totals(&meter_points, &["E-1R-A".to_owned()]);
+
+    assert_eq!(totals.len(), 1);
+    assert!(totals[0].is_current);
+    assert_eq!(totals[0].unit_cost, 150.0);
+}
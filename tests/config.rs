@@ -0,0 +1,297 @@
+use std::{path::PathBuf, time::Duration};
+
+use clap::Parser;
+use octocompare::config::{Cli, Config, Feature};
+
+/// Resolves `cli` against a config file holding `toml`.
+fn resolve(mut cli: Cli, toml: &str) -> anyhow::Result<Config> {
+    let path = std::env::temp_dir().join(format!("octocompare-{}.toml", uuid::Uuid::new_v4()));
+    std::fs::write(&path, toml).unwrap();
+    cli.config = Some(path.clone());
+    let config = Config::load(cli);
+    std::fs::remove_file(&path).unwrap();
+    config
+}
+
+#[test]
+fn flags_beat_the_file_which_beats_defaults() {
+    let cases: [(&str, Cli, &str, u16, &str); 4] = [
+        (
+            "defaults",
+            Cli::default(),
+            "",
+            3000,
+            "http://127.0.0.1:3000",
+        ),
+        (
+            "file",
+            Cli::default(),
+            "port = 5000",
+            5000,
+            "http://127.0.0.1:5000",
+        ),
+        (
+            "flag over file",
+            Cli {
+                port: Some(4000),
+                ..Default::default()
+            },
+            "port = 5000",
+            4000,
+            "http://127.0.0.1:4000",
+        ),
+        (
+            "base URL from the file, port from a flag",
+            Cli {
+                port: Some(4000),
+                ..Default::default()
+            },
+            "base_url = \"https://octocompare.example/\"",
+            4000,
+            "https://octocompare.example",
+        ),
+    ];
+    for (name, cli, toml, port, base_url) in cases {
+        let config = resolve(cli, toml).unwrap();
+        assert_eq!(config.bind.port(), port, "{}", name);
+        assert_eq!(config.base_url, base_url, "{}", name);
+    }
+}
+
+#[test]
+fn environment_beats_the_file_and_flags_beat_the_environment() {
+    // The only test that reads the environment, so nothing else sees this
+    std::env::set_var("OCTOCOMPARE_SHUTDOWN_GRACE", "45");
+    std::env::set_var("OCTOCOMPARE_LOG", "octocompare=info");
+    std::env::set_var("OCTOCOMPARE_CACHE_DIR", "/var/cache/octocompare");
+    let from_env = Cli::try_parse_from(["octocompare"]).unwrap();
+    let from_flag = Cli::try_parse_from([
+        "octocompare",
+        "--shutdown-grace",
+        "5",
+        "--log-filter",
+        "octocompare=trace",
+        "--cache-dir",
+        "cache",
+    ])
+    .unwrap();
+    std::env::remove_var("OCTOCOMPARE_SHUTDOWN_GRACE");
+    std::env::remove_var("OCTOCOMPARE_LOG");
+    std::env::remove_var("OCTOCOMPARE_CACHE_DIR");
+
+    let toml = r#"
+shutdown_grace = 10
+log_filter = "warn"
+cache_dir = "/tmp/octocompare"
+"#;
+    let cases = [
+        (
+            "environment",
+            from_env,
+            45,
+            "octocompare=info",
+            "/var/cache/octocompare",
+        ),
+        ("flags", from_flag, 5, "octocompare=trace", "cache"),
+        ("file", Cli::default(), 10, "warn", "/tmp/octocompare"),
+    ];
+    for (name, cli, grace, log_filter, cache_dir) in cases {
+        let config = resolve(cli, toml).unwrap();
+        assert_eq!(
+            config.shutdown_grace,
+            Duration::from_secs(grace),
+            "{}",
+            name
+        );
+        assert_eq!(config.log_filter, log_filter, "{}", name);
+        assert_eq!(
+            config.octopus.cache_dir,
+            Some(PathBuf::from(cache_dir)),
+            "{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn logging_and_caching_default_to_what_they_always_did() {
+    let config = resolve(Cli::default(), "").unwrap();
+    assert_eq!(config.log_filter, "octocompare=debug,axum::rejection=trace");
+    assert_eq!(config.octopus.cache_dir, None);
+}
+
+#[test]
+fn route_timeout_flags_add_to_the_files() {
+    let cli = Cli {
+        handler_timeout: Some(20),
+        route_timeouts: vec![("/forecast".to_owned(), 30), ("/heat-pump".to_owned(), 200)],
+        ..Default::default()
+    };
+    let toml = r#"
+[route_timeouts]
+"/forecast" = 90
+"/compare-tariffs" = 100
+"#;
+    let timeouts = resolve(cli, toml).unwrap().timeouts;
+
+    let cases = [
+        ("/forecast", 30),
+        ("/compare-tariffs", 100),
+        ("/heat-pump", 200),
+        ("/reports/:report_id", 20),
+    ];
+    for (route, secs) in cases {
+        assert_eq!(
+            timeouts.for_route(route),
+            Duration::from_secs(secs),
+            "{}",
+            route
+        );
+    }
+}
+
+#[test]
+fn alerts_need_reports() {
+    let cases: [(&str, Cli, &str, bool); 5] = [
+        ("everything on", Cli::default(), "", true),
+        (
+            "reports off in the file",
+            Cli::default(),
+            "[features]\nreports = false",
+            false,
+        ),
+        (
+            "reports and alerts off",
+            Cli {
+                disable: vec![Feature::Reports, Feature::Alerts],
+                ..Default::default()
+            },
+            "",
+            true,
+        ),
+        (
+            "alerts turned back on by a flag",
+            Cli {
+                enable: vec![Feature::Alerts],
+                ..Default::default()
+            },
+            "[features]\nreports = false\nalerts = false",
+            false,
+        ),
+        (
+            "disable beats enable",
+            Cli {
+                enable: vec![Feature::Alerts],
+                disable: vec![Feature::Alerts, Feature::Reports],
+                ..Default::default()
+            },
+            "",
+            true,
+        ),
+    ];
+    for (name, cli, toml, ok) in cases {
+        match resolve(cli, toml) {
+            Ok(config) => {
+                assert!(ok, "{} should have failed", name);
+                assert!(!config.features.alerts || config.features.reports);
+            }
+            Err(e) => {
+                assert!(!ok, "{}: {:#}", name, e);
+                assert!(
+                    e.to_string().contains("without reports"),
+                    "{}: {:#}",
+                    name,
+                    e
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn nonsense_is_rejected() {
+    let cases: [(&str, Cli, &str, &str); 10] = [
+        (
+            "host name",
+            Cli {
+                host: Some("localhost".to_owned()),
+                ..Default::default()
+            },
+            "",
+            "isn't an IP address",
+        ),
+        (
+            "zero timeout",
+            Cli::default(),
+            "request_timeout = 0",
+            "at least a second",
+        ),
+        (
+            "relative route",
+            Cli::default(),
+            "[route_timeouts]\nforecast = 90",
+            "needs to start with /",
+        ),
+        (
+            "zero route timeout",
+            Cli {
+                route_timeouts: vec![("/forecast".to_owned(), 0)],
+                ..Default::default()
+            },
+            "",
+            "at least a second",
+        ),
+        (
+            "not http",
+            Cli {
+                octopus_url: Some("ftp://example.com".to_owned()),
+                ..Default::default()
+            },
+            "",
+            "needs to start with http",
+        ),
+        (
+            "blank product",
+            Cli {
+                products: Some(vec!["AGILE-24-10-01".to_owned(), " ".to_owned()]),
+                ..Default::default()
+            },
+            "",
+            "no blank ones",
+        ),
+        (
+            "unknown setting",
+            Cli::default(),
+            "prot = 3000",
+            "unknown field",
+        ),
+        (
+            "bad email",
+            Cli {
+                smtp_host: Some("localhost".to_owned()),
+                ..Default::default()
+            },
+            "[smtp]\nfrom = \"not an address\"",
+            "isn't valid",
+        ),
+        (
+            "bad log filter",
+            Cli {
+                log_filter: Some("octocompare=loud".to_owned()),
+                ..Default::default()
+            },
+            "",
+            "log_filter \"octocompare=loud\" isn't valid",
+        ),
+        (
+            "cache in a file",
+            Cli::default(),
+            "cache_dir = \"Cargo.toml\"",
+            "is a file, not a directory",
+        ),
+    ];
+    for (name, cli, toml, expected) in cases {
+        let error = format!("{:#}", resolve(cli, toml).unwrap_err());
+        assert!(error.contains(expected), "{}: {}", name, error);
+    }
+}
@@ -0,0 +1,67 @@
+use octocompare::credentials::Credentials;
+
+const KEY: &str = "sk_live_abc123";
+
+#[test]
+fn trims_both_and_upper_cases_the_account_number() {
+    let credentials = Credentials::parse("  a-1234abcd\n", &format!("\t{} ", KEY)).unwrap();
+    assert_eq!(credentials.account_number, "A-1234ABCD");
+    assert_eq!(credentials.api_key, KEY);
+}
+
+#[test]
+fn api_keys_keep_their_case() {
+    let credentials = Credentials::parse("A-1234ABCD", "sk_live_AbC").unwrap();
+    assert_eq!(credentials.api_key, "sk_live_AbC");
+}
+
+#[test]
+fn bad_account_numbers() {
+    let cases = [
+        ("", "We need your account number."),
+        ("   ", "We need your account number."),
+        ("1234ABCD", "Account numbers look like A-1234ABCD"),
+        ("A-1234ABC", "Account numbers look like A-1234ABCD"),
+        ("A-1234ABCDE", "Account numbers look like A-1234ABCD"),
+        ("A-1234 ABC", "Account numbers look like A-1234ABCD"),
+        ("B-1234ABCD", "Account numbers look like A-1234ABCD"),
+    ];
+    for (account_number, expected) in cases {
+        let errors = Credentials::parse(account_number, KEY).unwrap_err();
+        let message = errors.account_number.unwrap();
+        assert!(
+            message.starts_with(expected),
+            "{:?}: {}",
+            account_number,
+            message
+        );
+        assert!(errors.api_key.is_none(), "{:?}", account_number);
+    }
+}
+
+#[test]
+fn bad_api_keys() {
+    let cases = [
+        ("", "We need your API key."),
+        (" ", "We need your API key."),
+        ("sk_live_", "API keys start with sk_live_"),
+        ("sk_test_abc123", "API keys start with sk_live_"),
+        ("SK_LIVE_abc123", "API keys start with sk_live_"),
+        ("sk_live_abc 123", "API keys don't have any spaces in them."),
+    ];
+    for (api_key, expected) in cases {
+        let errors = Credentials::parse("A-1234ABCD", api_key).unwrap_err();
+        let message = errors.api_key.unwrap();
+        assert!(message.starts_with(expected), "{:?}: {}", api_key, message);
+        assert!(errors.account_number.is_none(), "{:?}", api_key);
+    }
+}
+
+#[test]
+fn reports_every_field_that_is_wrong() {
+    let errors = Credentials::parse("", "").unwrap_err();
+    assert_eq!(
+        errors.to_string(),
+        "We need your account number. We need your API key."
+    );
+}
@@ -0,0 +1,53 @@
+use octocompare::csv::{read_columns, split_line};
+
+#[test]
+fn splits_quoted_fields() {
+    let cases: [(&str, &[&str]); 6] = [
+        ("a,b,c", &["a", "b", "c"]),
+        (" a , b ,c ", &["a", "b", "c"]),
+        (r#"2024-01-01,"£1,234.56""#, &["2024-01-01", "£1,234.56"]),
+        (r#""say ""hi""",x"#, &[r#"say "hi""#, "x"]),
+        (r#" "padded" ,x"#, &["padded", "x"]),
+        ("a,,", &["a", "", ""]),
+    ];
+    for (line, expected) in cases {
+        assert_eq!(split_line(line), expected, "{}", line);
+    }
+}
+
+fn is_number(value: &str) -> bool {
+    value.parse::<f64>().is_ok()
+}
+
+#[test]
+fn picks_columns_by_header_in_any_order_and_case() {
+    let rows = read_columns(
+        "Extra,Y,X\nq,2,1\n\nr,4,3\n",
+        "file",
+        &["x", "y"],
+        is_number,
+    )
+    .unwrap();
+    assert_eq!(rows, [["1", "2"], ["3", "4"]]);
+}
+
+#[test]
+fn takes_columns_in_order_without_a_header() {
+    let rows = read_columns("1,2\n3\n", "file", &["x", "y"], is_number).unwrap();
+    assert_eq!(rows, [["1", "2"], ["3", ""]]);
+}
+
+#[test]
+fn says_which_column_is_missing() {
+    let error = read_columns("x,z\n1,2\n", "weather file", &["x", "y"], is_number).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "The weather file is missing its y column."
+    );
+}
+
+#[test]
+fn nothing_but_a_header_is_no_rows() {
+    let rows = read_columns("x,y\n", "file", &["x", "y"], is_number).unwrap();
+    assert!(rows.is_empty());
+}
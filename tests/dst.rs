@@ -0,0 +1,173 @@
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use octocompare::{
+    api::{ConsumptionDatum, PricingDatum, TariffPricing},
+    pricing::{local_date, monthly, price, start_of_day, Granularity},
+};
+
+fn utc(s: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+}
+
+fn date(s: &str) -> NaiveDate {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+}
+
+/// 1kWh every half hour between two instants, newest first like the API.
+fn flat_consumption(from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<ConsumptionDatum> {
+    let mut results = vec![];
+    let mut start = from;
+    while start < to {
+        results.push(ConsumptionDatum {
+            consumption: 1.0,
+            interval_start: start,
+            interval_end: start + Duration::minutes(30),
+        });
+        start += Duration::minutes(30);
+    }
+    results.reverse();
+    results
+}
+
+fn rate(value: f64, from: &str, to: Option<&str>) -> PricingDatum {
+    PricingDatum {
+        value_exc_vat: value / 1.05,
+        value_inc_vat: value,
+        valid_from: utc(from),
+        valid_to: to.map(utc),
+    }
+}
+
+fn flat_tariff(standing_charges: Vec<PricingDatum>) -> TariffPricing {
+    TariffPricing {
+        tariff_code: "E-1R-VAR-22-11-01-C".to_string(),
+        product_code: "VAR-22-11-01".to_string(),
+        display_name: "Flexible Octopus".to_string(),
+        granularity: Granularity::HalfHourly,
+        standing_charges,
+        unit_charges: vec![rate(20.0, "2024-01-01T00:00:00Z", None)],
+    }
+}
+
+#[test]
+fn local_days_follow_british_summer_time() {
+    assert_eq!(local_date(utc("2024-07-01T22:30:00Z")), date("2024-07-01"));
+    assert_eq!(local_date(utc("2024-07-01T23:00:00Z")), date("2024-07-02"));
+    assert_eq!(local_date(utc("2024-12-01T23:00:00Z")), date("2024-12-01"));
+
+    assert_eq!(
+        start_of_day(date("2024-07-02")),
+        utc("2024-07-01T23:00:00Z")
+    );
+    assert_eq!(
+        start_of_day(date("2024-12-02")),
+        utc("2024-12-02T00:00:00Z")
+    );
+}
+
+#[test]
+fn spring_clock_change_day_has_46_half_hours() {
+    // Midnight 30th March to midnight 1st April, clocks go forward on the 31st
+    let consumption = flat_consumption(utc("2024-03-30T00:00:00Z"), utc("2024-03-31T23:00:00Z"));
+    let tariff = flat_tariff(vec![rate(50.0, "2024-01-01T00:00:00Z", None)]);
+
+    let priced = price(&consumption, &tariff);
+
+    assert!(!priced.data_missing);
+    assert_eq!(priced.days.len(), 2);
+    assert_eq!(priced.days[0].date, date("2024-03-30"));
+    assert_eq!(priced.days[0].slots, 48);
+    assert_eq!(priced.days[0].unit_cost, 960.0);
+    assert_eq!(priced.days[1].date, date("2024-03-31"));
+    assert_eq!(priced.days[1].slots, 46);
+    assert_eq!(priced.days[1].unit_cost, 920.0);
+
+    assert_eq!(priced.unit_cost(), 1880.0);
+    assert_eq!(priced.standing_cost(), 100.0);
+}
+
+#[test]
+fn autumn_clock_change_day_has_50_half_hours() {
+    // Midnight 26th October to midnight 28th October, clocks go back on the 27th
+    let consumption = flat_consumption(utc("2024-10-25T23:00:00Z"), utc("2024-10-28T00:00:00Z"));
+    let tariff = flat_tariff(vec![rate(50.0, "2024-01-01T00:00:00Z", None)]);
+
+    let priced = price(&consumption, &tariff);
+
+    assert!(!priced.data_missing);
+    assert_eq!(priced.days.len(), 2);
+    assert_eq!(priced.days[0].date, date("2024-10-26"));
+    assert_eq!(priced.days[0].slots, 48);
+    assert_eq!(priced.days[0].unit_cost, 960.0);
+    assert_eq!(priced.days[1].date, date("2024-10-27"));
+    assert_eq!(priced.days[1].slots, 50);
+    assert_eq!(priced.days[1].unit_cost, 1000.0);
+
+    assert_eq!(priced.unit_cost(), 1960.0);
+    assert_eq!(priced.standing_cost(), 100.0);
+}
+
+#[test]
+fn standing_charge_changes_at_local_midnight() {
+    // The new charge starts at midnight on the 27th, which is still 11pm UTC
+    // on the 26th
+    let consumption = flat_consumption(utc("2024-10-25T23:00:00Z"), utc("2024-10-28T00:00:00Z"));
+    let tariff = flat_tariff(vec![
+        rate(60.0, "2024-10-26T23:00:00Z", None),
+        rate(50.0, "2024-01-01T00:00:00Z", Some("2024-10-26T23:00:00Z")),
+    ]);
+
+    let priced = price(&consumption, &tariff);
+
+    assert_eq!(priced.days[0].standing_cost, 50.0);
+    assert_eq!(priced.days[1].standing_cost, 60.0);
+    assert_eq!(priced.standing_cost(), 110.0);
+}
+
+#[test]
+fn monthly_totals_span_both_clock_changes() {
+    // All of British Summer Time 2024, plus the days either side
+    let consumption = flat_consumption(utc("2024-03-30T00:00:00Z"), utc("2024-10-28T00:00:00Z"));
+    let tariff = flat_tariff(vec![rate(50.0, "2024-01-01T00:00:00Z", None)]);
+
+    let priced = price(&consumption, &tariff);
+    let months = monthly(&priced.days);
+
+    // 212 days, one of which is 2 half hours short and one 2 half hours long
+    assert_eq!(priced.days.len(), 212);
+    assert_eq!(priced.days.iter().map(|d| d.slots).sum::<usize>(), 212 * 48);
+    assert_eq!(priced.standing_cost(), 212.0 * 50.0);
+
+    assert_eq!(months.len(), 8);
+    assert_eq!((months[0].year, months[0].month), (2024, 3));
+    assert_eq!(months[0].consumption, 48.0 + 46.0);
+    assert_eq!((months[7].year, months[7].month), (2024, 10));
+    assert_eq!(months[7].consumption, 27.0 * 48.0 + 2.0);
+    assert_eq!(
+        months.iter().map(|m| m.total()).sum::<f64>(),
+        priced.unit_cost() + priced.standing_cost()
+    );
+}
+
+#[test]
+fn daily_rates_apply_to_every_half_hour_of_the_local_day() {
+    // Tracker style prices, one per UK day. The 27th starts at 11pm UTC on the
+    // 26th and runs for 25 hours.
+    let consumption = flat_consumption(utc("2024-10-25T23:00:00Z"), utc("2024-10-28T00:00:00Z"));
+    let tariff = TariffPricing {
+        tariff_code: "E-1R-SILVER-24-10-01-C".to_string(),
+        product_code: "SILVER-24-10-01".to_string(),
+        display_name: "Octopus Tracker".to_string(),
+        granularity: Granularity::Daily,
+        standing_charges: vec![rate(50.0, "2024-01-01T00:00:00Z", None)],
+        unit_charges: vec![
+            rate(30.0, "2024-10-26T23:00:00Z", Some("2024-10-28T00:00:00Z")),
+            rate(10.0, "2024-10-25T23:00:00Z", Some("2024-10-26T23:00:00Z")),
+        ],
+    };
+
+    let priced = price(&consumption, &tariff);
+
+    assert!(!priced.data_missing);
+    assert_eq!(priced.days[0].unit_cost, 48.0 * 10.0);
+    assert_eq!(priced.days[1].unit_cost, 50.0 * 30.0);
+}
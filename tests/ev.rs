@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Timelike, Utc};
+use octocompare::{
+    api::ConsumptionDatum,
+    ev::{add_charging, DispatchedRates, EvProfile},
+    pricing::Rates,
+};
+
+fn utc(s: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+}
+
+/// Go-ish: cheap from `cheap_from` to 04:30, dear the rest of the time.
+/// January, so UTC is UK time.
+struct Go {
+    cheap_from: u32,
+}
+
+impl Rates for Go {
+    fn unit_rate(&self, at: DateTime<Utc>) -> Option<f64> {
+        let minutes = at.hour() * 60 + at.minute();
+        match (self.cheap_from..270).contains(&minutes) {
+            true => Some(7.5),
+            false => Some(27.0),
+        }
+    }
+
+    fn standing_charge(&self, _day: NaiveDate) -> Option<f64> {
+        Some(45.0)
+    }
+}
+
+/// Half hours of 0.1 kWh from 00:00 on 10 January for `days` days.
+fn readings(days: i64) -> Vec<ConsumptionDatum> {
+    let from = utc("2024-01-10T00:00:00Z");
+    (0..days * 48)
+        .map(|i| {
+            let start = from + Duration::minutes(30 * i);
+            ConsumptionDatum {
+                consumption: 0.1,
+                interval_start: start,
+                interval_end: start + Duration::minutes(30),
+            }
+        })
+        .collect()
+}
+
+fn car(daily_miles: f64) -> EvProfile {
+    EvProfile {
+        battery_kwh: 60.0,
+        daily_miles,
+        miles_per_kwh: 3.5,
+        charger_kw: 7.0,
+        plug_in: NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
+        ready_by: NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+    }
+}
+
+/// kWh of charging added to each half hour that got some.
+fn charged(before: &[ConsumptionDatum], after: &[ConsumptionDatum]) -> Vec<(DateTime<Utc>, f64)> {
+    before
+        .iter()
+        .zip(after)
+        .filter(|(b, a)| a.consumption > b.consumption)
+        .map(|(b, a)| {
+            let added = ((a.consumption - b.consumption) * 1000.0).round() / 1000.0;
+            (a.interval_start, added)
+        })
+        .collect()
+}
+
+#[test]
+fn a_days_driving_is_capped_by_the_battery() {
+    assert_eq!(car(35.0).daily_energy(), 10.0);
+    assert_eq!(car(1000.0).daily_energy(), 60.0);
+}
+
+#[test]
+fn charges_in_the_cheapest_slots_of_the_night() {
+    let before = readings(2);
+    let charging = add_charging(&before, &Go { cheap_from: 30 }, &car(35.0), false);
+
+    let slots = charged(&before, &charging.consumption);
+    assert_eq!(
+        slots[..3],
+        [
+            (utc("2024-01-11T00:30:00Z"), 3.5),
+            (utc("2024-01-11T01:00:00Z"), 3.5),
+            (utc("2024-01-11T01:30:00Z"), 3.0),
+        ]
+    );
+    assert!(charging.dispatches.is_empty());
+}
+
+#[test]
+fn only_charges_where_there_are_readings() {
+    let before = readings(2);
+    let charging = add_charging(&before, &Go { cheap_from: 30 }, &car(35.0), false);
+
+    // The second night runs past the readings, so it gets the evening at peak
+    let slots = charged(&before, &charging.consumption);
+    assert_eq!(
+        slots[3..],
+        [
+            (utc("2024-01-11T18:00:00Z"), 3.5),
+            (utc("2024-01-11T18:30:00Z"), 3.5),
+            (utc("2024-01-11T19:00:00Z"), 3.0),
+        ]
+    );
+    assert!((charging.energy - 20.0).abs() < 1e-9);
+}
+
+#[test]
+fn smart_charging_past_the_off_peak_window_is_dispatched() {
+    // Only an hour off-peak, not enough for 10 kWh
+    let rates = Go { cheap_from: 210 };
+    let before = readings(2);
+    let charging = add_charging(&before, &rates, &car(35.0), true);
+
+    // The first night fills 03:30 and 04:00, then the earliest peak slot.
+    // The second night has no off-peak readings, so nothing is cheaper
+    let dispatched: HashMap<_, _> = [(utc("2024-01-10T18:00:00Z"), 7.5)].into();
+    assert_eq!(charging.dispatches, dispatched);
+
+    let without = add_charging(&before, &rates, &car(35.0), false);
+    assert!(without.dispatches.is_empty());
+    assert_eq!(
+        charged(&before, &without.consumption),
+        charged(&before, &charging.consumption)
+    );
+}
+
+#[test]
+fn dispatched_slots_are_billed_off_peak() {
+    let go = Go { cheap_from: 30 };
+    let dispatches: HashMap<_, _> = [(utc("2024-01-10T18:00:00Z"), 7.5)].into();
+    let rates = DispatchedRates {
+        rates: &go,
+        dispatches: &dispatches,
+    };
+
+    assert_eq!(rates.unit_rate(utc("2024-01-10T18:00:00Z")), Some(7.5));
+    assert_eq!(rates.unit_rate(utc("2024-01-10T18:30:00Z")), Some(27.0));
+    assert_eq!(rates.unit_rate(utc("2024-01-10T01:00:00Z")), Some(7.5));
+    let day = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+    assert_eq!(rates.standing_charge(day), Some(45.0));
+}
@@ -0,0 +1,116 @@
+use chrono::{Duration, NaiveDate};
+use octocompare::{
+    api::ConsumptionDatum,
+    forecast::{cheapest_window, typical_profile, ForecastSlot},
+    pricing::start_of_day,
+};
+
+fn date(s: &str) -> NaiveDate {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+}
+
+/// A UK day of half hours all using `kwh`.
+fn day(s: &str, kwh: f64) -> Vec<ConsumptionDatum> {
+    let start = start_of_day(date(s));
+    (0..48)
+        .map(|i| ConsumptionDatum {
+            consumption: kwh,
+            interval_start: start + Duration::minutes(30 * i),
+            interval_end: start + Duration::minutes(30 * (i + 1)),
+        })
+        .collect()
+}
+
+#[test]
+fn profiles_come_from_the_most_similar_days_there_are() {
+    // 16 January 2024 is a winter Tuesday
+    let tuesday = date("2024-01-16");
+    let cases: [(&str, Vec<ConsumptionDatum>, usize, &str, f64); 4] = [
+        (
+            "winter Tuesdays",
+            [
+                day("2024-01-09", 1.0),
+                day("2024-01-02", 3.0),
+                day("2023-07-11", 9.0),
+            ]
+            .concat(),
+            2,
+            "winter Tuesdays",
+            2.0,
+        ),
+        (
+            "Tuesdays from another season",
+            [day("2023-07-11", 0.5), day("2024-01-10", 9.0)].concat(),
+            1,
+            "Tuesdays",
+            0.5,
+        ),
+        (
+            "any day at all",
+            [day("2024-01-10", 0.25), day("2024-01-11", 0.75)].concat(),
+            2,
+            "all days",
+            0.5,
+        ),
+        ("nothing", vec![], 0, "no days", 0.0),
+    ];
+
+    for (name, consumption, days, description, average) in cases {
+        let (profile, profile_days, profile_description) = typical_profile(&consumption, tuesday);
+        assert_eq!(profile_days, days, "{}", name);
+        assert_eq!(profile_description, description, "{}", name);
+        assert!(
+            profile.iter().all(|kwh| (kwh - average).abs() < 1e-9),
+            "{}: {:?}",
+            name,
+            profile
+        );
+    }
+}
+
+#[test]
+fn clock_change_days_dont_double_count_the_repeated_hour() {
+    // The clocks went back on Sunday 27 October 2024, so it has 50 half hours
+    let consumption = [day("2024-10-27", 1.0), day("2024-10-20", 1.0)].concat();
+    let (profile, days, _) = typical_profile(&consumption, date("2024-11-03"));
+    assert_eq!(days, 2);
+    assert!(profile.iter().all(|kwh| (kwh - 1.0).abs() < 1e-9));
+}
+
+fn slots(rates: &[f64]) -> Vec<ForecastSlot> {
+    let start = start_of_day(date("2024-01-16"));
+    rates
+        .iter()
+        .enumerate()
+        .map(|(i, rate)| ForecastSlot {
+            start: start + Duration::minutes(30 * i as i64),
+            rate: *rate,
+            consumption: 0.2,
+        })
+        .collect()
+}
+
+#[test]
+fn finds_the_cheapest_run_of_half_hours() {
+    let slots = slots(&[30.0, 20.0, 10.0, 15.0, 40.0]);
+
+    let window = cheapest_window(&slots, 2).unwrap();
+    assert_eq!(window.start, slots[2].start);
+    assert_eq!(window.end, slots[4].start);
+    assert_eq!(window.average_rate, 12.5);
+
+    let window = cheapest_window(&slots, 1).unwrap();
+    assert_eq!(window.start, slots[2].start);
+
+    let window = cheapest_window(&slots, 5).unwrap();
+    assert_eq!(window.start, slots[0].start);
+    assert_eq!(window.average_rate, 23.0);
+}
+
+#[test]
+fn no_window_that_doesnt_fit() {
+    let slots = slots(&[30.0, 20.0]);
+    assert!(cheapest_window(&slots, 0).is_none());
+    assert!(cheapest_window(&slots, 3).is_none());
+    assert!(cheapest_window(&[], 1).is_none());
+}
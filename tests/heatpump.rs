@@ -0,0 +1,125 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use octocompare::{
+    api::ConsumptionDatum,
+    heatpump::{add_heat_pump, parse_weather},
+    pricing::Rates,
+};
+
+fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(y, m, d).unwrap()
+}
+
+#[test]
+fn averages_hourly_weather_over_each_day() {
+    let csv =
+        "Date,Temperature\n2024-01-01T00:00:00Z,2\n2024-01-01T12:00,6\n\"2024-01-02 12:00\",-1.5\n";
+    let weather = parse_weather(csv).unwrap();
+    assert_eq!(weather.len(), 2);
+    assert_eq!(weather[&date(2024, 1, 1)], 4.0);
+    assert_eq!(weather[&date(2024, 1, 2)], -1.5);
+}
+
+#[test]
+fn explains_what_is_wrong_with_the_weather() {
+    let cases = [
+        ("day,temperature\n2024-01-01,4", "missing its date column"),
+        (
+            "2024-01-01,warm",
+            "Line 1 of the weather file doesn't have a temperature",
+        ),
+        (
+            "date,temperature\nyesterday,4",
+            "Line 1 of the weather file doesn't have a date",
+        ),
+        ("date,temperature", "There weren't any days"),
+    ];
+    for (csv, expected) in cases {
+        let error = parse_weather(csv).unwrap_err().to_string();
+        assert!(error.contains(expected), "{:?}: {}", csv, error);
+    }
+}
+
+/// Prices for the half hours given, like a tariff.
+struct Prices(HashMap<DateTime<Utc>, f64>);
+
+impl Rates for Prices {
+    fn unit_rate(&self, at: DateTime<Utc>) -> Option<f64> {
+        self.0.get(&at).copied()
+    }
+
+    fn standing_charge(&self, _day: NaiveDate) -> Option<f64> {
+        Some(50.0)
+    }
+}
+
+/// A day of empty half hours from midnight, and Cosy-ish prices for them:
+/// cheap 04:00-07:00, 13:00-16:00 and 22:00-00:00.
+fn cosy_day(from: &str) -> (Vec<ConsumptionDatum>, Prices) {
+    let from = DateTime::parse_from_rfc3339(from)
+        .unwrap()
+        .with_timezone(&Utc);
+    let mut readings = vec![];
+    let mut prices = HashMap::new();
+    for i in 0..48 {
+        let start = from + Duration::minutes(30 * i);
+        readings.push(ConsumptionDatum {
+            consumption: 0.0,
+            interval_start: start,
+            interval_end: start + Duration::minutes(30),
+        });
+        let hour = i / 2;
+        let cheap = (4..7).contains(&hour) || (13..16).contains(&hour) || hour >= 22;
+        prices.insert(start, if cheap { 13.0 } else { 30.0 });
+    }
+    (readings, Prices(prices))
+}
+
+#[test]
+fn heat_pump_load_keeps_its_total() {
+    let (readings, prices) = cosy_day("2024-01-10T00:00:00Z");
+    let load = BTreeMap::from([(date(2024, 1, 10), 24.0)]);
+
+    let with = add_heat_pump(&readings, &load, &prices);
+    let total: f64 = with.iter().map(|d| d.consumption).sum();
+    assert!((total - 24.0).abs() < 1e-9);
+
+    let without = add_heat_pump(&readings, &BTreeMap::new(), &prices);
+    assert!(without.iter().all(|d| d.consumption == 0.0));
+}
+
+#[test]
+fn heat_pump_load_follows_the_heating_profile() {
+    let (readings, _) = cosy_day("2024-01-10T00:00:00Z");
+    let load = BTreeMap::from([(date(2024, 1, 10), 24.0)]);
+    let with = add_heat_pump(&readings, &load, &Prices(HashMap::new()));
+
+    // 02:00 is overnight, 07:00 the morning warm-up and 18:00 the evening's
+    assert!(with[4].consumption < with[14].consumption);
+    assert!(with[4].consumption < with[36].consumption);
+}
+
+#[test]
+fn heat_pump_load_leans_into_cheap_windows() {
+    let (readings, prices) = cosy_day("2024-01-10T00:00:00Z");
+    let load = BTreeMap::from([(date(2024, 1, 10), 24.0)]);
+    let with = add_heat_pump(&readings, &load, &prices);
+
+    let cost = |readings: &[ConsumptionDatum]| -> f64 {
+        readings
+            .iter()
+            .map(|d| d.consumption * prices.0[&d.interval_start])
+            .sum()
+    };
+    let even: Vec<_> = readings
+        .iter()
+        .map(|d| ConsumptionDatum {
+            consumption: 0.5,
+            ..d.clone()
+        })
+        .collect();
+    assert!(cost(&with) < cost(&even));
+    // 05:00 is in a cheap window, 08:00 isn't
+    assert!(with[10].consumption > with[16].consumption);
+}
@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use octocompare::{
+    error::UserError,
+    jobs::{JobState, JobStore},
+};
+use tokio::time::Instant;
+
+/// Lets spawned jobs run until none of them are still going.
+async fn settle<T: Clone + Send + 'static>(jobs: &JobStore<T>) {
+    while jobs.running() > 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn polling_hands_out_progress_then_the_result() {
+    let jobs = JobStore::default();
+    let id = jobs.spawn(|_| async {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        Ok(42)
+    });
+
+    let job = jobs.poll(&id).unwrap();
+    assert!(matches!(job.state, JobState::Running));
+
+    settle(&jobs).await;
+    let job = jobs.poll(&id).unwrap();
+    assert!(matches!(job.state, JobState::Complete(42)));
+    // Polling again still gets it, for retries and second tabs
+    assert!(matches!(
+        jobs.poll(&id).unwrap().state,
+        JobState::Complete(42)
+    ));
+    assert!(jobs.poll("nope").is_none());
+}
+
+#[tokio::test(start_paused = true)]
+async fn failures_keep_their_status() {
+    let jobs: JobStore<u32> = JobStore::default();
+    let id =
+        jobs.spawn(|_| async { Err(UserError::Upstream("Octopus said no.".to_owned()).into()) });
+    settle(&jobs).await;
+
+    let JobState::Failed(error) = jobs.poll(&id).unwrap().state else {
+        panic!("the job should have failed");
+    };
+    assert_eq!(error.status, axum::http::StatusCode::BAD_GATEWAY);
+    assert_eq!(error.message, "Octopus said no.");
+}
+
+#[tokio::test(start_paused = true)]
+async fn finished_jobs_are_swept_after_ten_minutes() {
+    let jobs = JobStore::default();
+    let claimed = jobs.spawn(|_| async { Ok(1) });
+    let unclaimed = jobs.spawn(|_| async { Ok(2) });
+    let running = jobs.spawn(|_| async {
+        tokio::time::sleep(Duration::from_secs(3600)).await;
+        Ok(3)
+    });
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert!(jobs.poll(&claimed).is_some());
+
+    tokio::time::advance(Duration::from_secs(9 * 60)).await;
+    assert!(jobs.poll(&unclaimed).is_some());
+
+    tokio::time::advance(Duration::from_secs(60)).await;
+    jobs.sweep();
+    assert!(jobs.poll(&claimed).is_none());
+    assert!(jobs.poll(&unclaimed).is_none());
+    // Still running, however long it's been
+    assert!(jobs.poll(&running).is_some());
+}
+
+#[tokio::test(start_paused = true)]
+async fn draining_with_nothing_to_collect_is_instant() {
+    let jobs = JobStore::default();
+    let id = jobs.spawn(|_| async { Ok(1) });
+    settle(&jobs).await;
+    jobs.poll(&id);
+
+    let started = Instant::now();
+    assert_eq!(jobs.drain(Duration::from_secs(60)).await, 0);
+    assert_eq!(started.elapsed(), Duration::ZERO);
+}
+
+#[tokio::test(start_paused = true)]
+async fn draining_waits_a_while_for_results_to_be_collected() {
+    let jobs = JobStore::default();
+    jobs.spawn(|_| async { Ok(1) });
+    settle(&jobs).await;
+
+    let started = Instant::now();
+    assert_eq!(jobs.drain(Duration::from_secs(60)).await, 0);
+    let waited = started.elapsed();
+    assert!(waited >= Duration::from_secs(5), "{:?}", waited);
+    assert!(waited < Duration::from_secs(6), "{:?}", waited);
+}
+
+#[tokio::test(start_paused = true)]
+async fn draining_stops_once_results_are_collected() {
+    let jobs = JobStore::default();
+    let id = jobs.spawn(|_| async {
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        Ok(1)
+    });
+
+    let poller = jobs.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        poller.poll(&id);
+    });
+
+    let started = Instant::now();
+    assert_eq!(jobs.drain(Duration::from_secs(60)).await, 0);
+    let waited = started.elapsed();
+    assert!(waited >= Duration::from_secs(3), "{:?}", waited);
+    assert!(waited < Duration::from_secs(4), "{:?}", waited);
+}
+
+#[tokio::test(start_paused = true)]
+async fn draining_gives_up_on_slow_jobs() {
+    let jobs = JobStore::default();
+    jobs.spawn(|_| async {
+        tokio::time::sleep(Duration::from_secs(3600)).await;
+        Ok(1)
+    });
+
+    let started = Instant::now();
+    assert_eq!(jobs.drain(Duration::from_secs(30)).await, 1);
+    assert!(started.elapsed() < Duration::from_secs(31));
+}
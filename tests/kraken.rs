@@ -0,0 +1,224 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+use axum::{extract::State, http::HeaderMap, routing::post, Json, Router};
+use octocompare::{
+    api::ClientSettings, error::status_of, kraken::KrakenClient, reconcile::billed_periods,
+};
+use serde_json::{json, Value};
+
+/// A stand-in for Kraken's GraphQL endpoint that writes down what it was
+/// asked for.
+#[derive(Default)]
+struct Kraken {
+    /// Seconds the tokens it hands out last
+    token_lifetime: i64,
+    /// How many bills queries to turn away as having an expired token
+    expire: AtomicUsize,
+    tokens: AtomicUsize,
+    log: Mutex<Vec<String>>,
+}
+
+async fn graphql(
+    State(kraken): State<Arc<Kraken>>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Json<Value> {
+    let query = body["query"].as_str().unwrap();
+    let variables = &body["variables"];
+    let mut log = kraken.log.lock().unwrap();
+
+    if query.contains("obtainKrakenToken") {
+        let input = &variables["input"];
+        if input["APIKey"] == "sk_bad" {
+            log.push("rejected key".to_owned());
+            return Json(json!({
+                "data": { "obtainKrakenToken": null },
+                "errors": [{ "message": "Invalid data.", "extensions": { "errorCode": "KT-CT-1139" } }],
+            }));
+        }
+        match input["refreshToken"].as_str() {
+            Some(refresh_token) => log.push(format!("token from {}", refresh_token)),
+            None => log.push(format!("token from {}", input["APIKey"].as_str().unwrap())),
+        }
+        let n = kraken.tokens.fetch_add(1, Ordering::SeqCst) + 1;
+        let now = chrono::offset::Utc::now().timestamp();
+        return Json(json!({
+            "data": { "obtainKrakenToken": {
+                "token": format!("token-{}", n),
+                "refreshToken": format!("refresh-{}", n),
+                "refreshExpiresIn": now + 7 * 24 * 60 * 60,
+                "payload": { "exp": now + kraken.token_lifetime, "sub": "kraken|account-user:1" },
+            } },
+        }));
+    }
+
+    let token = headers["authorization"].to_str().unwrap();
+    log.push(format!("bills with {}", token));
+    let expired = kraken
+        .expire
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+        .is_ok();
+    if expired {
+        return Json(json!({
+            "data": null,
+            "errors": [{ "message": "Signature of the JWT has expired.", "extensions": { "errorCode": "KT-CT-1124" } }],
+        }));
+    }
+    assert_eq!(variables["accountNumber"], "A-1234ABCD");
+    Json(json!({
+        "data": { "account": {
+            "balance": -4250,
+            "bills": { "edges": [
+                { "node": {
+                    "id": "2",
+                    "billType": "STATEMENT",
+                    "fromDate": "2024-02-01",
+                    "toDate": "2024-02-29",
+                    "issuedDate": "2024-03-02",
+                    "totalCharges": { "netTotal": 7000, "grossTotal": 7350 },
+                    "totalCredits": { "netTotal": 0, "grossTotal": 0 },
+                } },
+                { "node": {
+                    "id": "1",
+                    "billType": "PRE_KRAKEN",
+                    "fromDate": null,
+                    "toDate": null,
+                    "issuedDate": "2024-02-01",
+                } },
+            ] },
+        } },
+    }))
+}
+
+async fn kraken(token_lifetime: i64, expire: usize) -> (Arc<Kraken>, ClientSettings) {
+    let kraken = Arc::new(Kraken {
+        token_lifetime,
+        expire: AtomicUsize::new(expire),
+        ..Default::default()
+    });
+    let app = Router::new()
+        .route("/graphql/", post(graphql))
+        .with_state(kraken.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    let settings = ClientSettings {
+        base_url,
+        ..Default::default()
+    };
+    (kraken, settings)
+}
+
+fn log(kraken: &Kraken) -> Vec<String> {
+    kraken.log.lock().unwrap().clone()
+}
+
+#[tokio::test]
+async fn a_token_is_fetched_once_and_reused() {
+    let (kraken, settings) = kraken(60 * 60, 0).await;
+    let client = KrakenClient::new(&settings, "sk_live");
+
+    let bills = client.bills("A-1234ABCD", 12).await.unwrap();
+    client.bills("A-1234ABCD", 12).await.unwrap();
+
+    assert_eq!(
+        log(&kraken),
+        [
+            "token from sk_live",
+            "bills with token-1",
+            "bills with token-1"
+        ]
+    );
+    assert_eq!(bills.balance, -4250);
+    assert_eq!(bills.bills.len(), 2);
+    let statement = &bills.bills[0];
+    assert_eq!(statement.bill_type.as_deref(), Some("STATEMENT"));
+    assert_eq!(
+        statement.total_charges.as_ref().unwrap().gross_total,
+        Some(7350)
+    );
+    assert!(bills.bills[1].total_charges.is_none());
+}
+
+#[tokio::test]
+async fn tokens_about_to_run_out_are_refreshed() {
+    // Inside the two minute margin, so never good enough to use as it is
+    let (kraken, settings) = kraken(60, 0).await;
+    let client = KrakenClient::new(&settings, "sk_live");
+
+    client.bills("A-1234ABCD", 12).await.unwrap();
+    client.bills("A-1234ABCD", 12).await.unwrap();
+
+    assert_eq!(
+        log(&kraken),
+        [
+            "token from sk_live",
+            "bills with token-1",
+            "token from refresh-1",
+            "bills with token-2",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn an_expired_token_is_replaced_and_the_query_tried_again() {
+    let (kraken, settings) = kraken(60 * 60, 1).await;
+    let client = KrakenClient::new(&settings, "sk_live");
+
+    let bills = client.bills("A-1234ABCD", 12).await.unwrap();
+
+    assert_eq!(
+        log(&kraken),
+        [
+            "token from sk_live",
+            "bills with token-1",
+            "token from sk_live",
+            "bills with token-2",
+        ]
+    );
+    assert_eq!(bills.bills.len(), 2);
+}
+
+#[tokio::test]
+async fn a_token_that_keeps_expiring_is_only_retried_once() {
+    let (kraken, settings) = kraken(60 * 60, 5).await;
+    let client = KrakenClient::new(&settings, "sk_live");
+
+    let error = client.bills("A-1234ABCD", 12).await.unwrap_err();
+
+    assert_eq!(status_of(&error), axum::http::StatusCode::BAD_GATEWAY);
+    assert!(error.to_string().contains("JWT has expired"), "{}", error);
+    assert_eq!(log(&kraken).len(), 4);
+}
+
+#[tokio::test]
+async fn a_key_kraken_wont_take_is_a_bad_request() {
+    let (kraken, settings) = kraken(60 * 60, 0).await;
+    let client = KrakenClient::new(&settings, "sk_bad");
+
+    let error = client.bills("A-1234ABCD", 12).await.unwrap_err();
+
+    assert_eq!(status_of(&error), axum::http::StatusCode::BAD_REQUEST);
+    assert_eq!(error.to_string(), "Octopus didn't accept that API key.");
+    assert_eq!(log(&kraken), ["rejected key"]);
+}
+
+#[tokio::test]
+async fn only_dated_statements_with_charges_are_billed_periods() {
+    let (_, settings) = kraken(60 * 60, 0).await;
+    let bills = KrakenClient::new(&settings, "sk_live")
+        .bills("A-1234ABCD", 12)
+        .await
+        .unwrap();
+
+    let periods = billed_periods(&bills.bills);
+
+    assert_eq!(periods.len(), 1);
+    assert_eq!(periods[0].from.to_string(), "2024-02-01");
+    assert_eq!(periods[0].to.to_string(), "2024-02-29");
+    assert_eq!(periods[0].amount, 7350.0);
+    assert_eq!(periods[0].days(), 29);
+}
@@ -0,0 +1,147 @@
+use octocompare::metrics::{self, render, MetricsLayer};
+use tracing::trace;
+use tracing_subscriber::{filter, layer::SubscriberExt, Layer};
+
+/// Runs `record` with [`MetricsLayer`] listening the way the server sets it
+/// up. The registry's shared by every test, so each one uses its own labels.
+fn recording(record: impl FnOnce()) {
+    let subscriber = tracing_subscriber::registry()
+        .with(MetricsLayer.with_filter(filter::filter_fn(|m| m.target() == metrics::TARGET)));
+    tracing::subscriber::with_default(subscriber, record);
+}
+
+fn lines_starting(exposition: &str, prefix: &str) -> Vec<String> {
+    exposition
+        .lines()
+        .filter(|l| l.starts_with(prefix))
+        .map(|l| l.to_owned())
+        .collect()
+}
+
+#[test]
+fn requests_are_counted_and_timed() {
+    recording(|| {
+        for (status, seconds) in [(200, 0.004), (200, 0.3), (500, 12.0)] {
+            trace!(
+                target: metrics::TARGET,
+                metric = "http_request",
+                method = "GET",
+                route = "/timed",
+                status,
+                seconds
+            );
+        }
+    });
+
+    let exposition = render(&[]);
+
+    assert_eq!(
+        lines_starting(
+            &exposition,
+            "octocompare_http_requests_total{method=\"GET\",route=\"/timed\""
+        ),
+        [
+            "octocompare_http_requests_total{method=\"GET\",route=\"/timed\",status=\"200\"} 2",
+            "octocompare_http_requests_total{method=\"GET\",route=\"/timed\",status=\"500\"} 1",
+        ]
+    );
+    let histogram = "octocompare_http_request_duration_seconds";
+    let labels = "method=\"GET\",route=\"/timed\"";
+    for line in [
+        format!("{}_bucket{{{},le=\"0.005\"}} 1", histogram, labels),
+        format!("{}_bucket{{{},le=\"0.25\"}} 1", histogram, labels),
+        format!("{}_bucket{{{},le=\"0.5\"}} 2", histogram, labels),
+        format!("{}_bucket{{{},le=\"10\"}} 2", histogram, labels),
+        format!("{}_bucket{{{},le=\"30\"}} 3", histogram, labels),
+        format!("{}_bucket{{{},le=\"+Inf\"}} 3", histogram, labels),
+        format!("{}_sum{{{}}} 12.304", histogram, labels),
+        format!("{}_count{{{}}} 3", histogram, labels),
+    ] {
+        assert!(exposition.lines().any(|l| l == line), "{}", line);
+    }
+}
+
+#[test]
+fn octopus_calls_and_cache_lookups_are_counted() {
+    recording(|| {
+        trace!(
+            target: metrics::TARGET,
+            metric = "octopus_request",
+            endpoint = "counted",
+            status = "error",
+            seconds = 1.5
+        );
+        for hit in [true, false, true] {
+            trace!(target: metrics::TARGET, metric = "cache_lookup", cache = "counted", hit);
+        }
+    });
+
+    let exposition = render(&[("batch", 2), ("forecast", 0)]);
+
+    for line in [
+        "octocompare_octopus_requests_total{endpoint=\"counted\",status=\"error\"} 1",
+        "octocompare_octopus_request_duration_seconds_count{endpoint=\"counted\"} 1",
+        "octocompare_cache_lookups_total{cache=\"counted\",result=\"hit\"} 2",
+        "octocompare_cache_lookups_total{cache=\"counted\",result=\"miss\"} 1",
+        "# TYPE octocompare_jobs_running gauge",
+        "octocompare_jobs_running{kind=\"batch\"} 2",
+        "octocompare_jobs_running{kind=\"forecast\"} 0",
+    ] {
+        assert!(exposition.lines().any(|l| l == line), "{}", line);
+    }
+}
+
+#[test]
+fn label_values_are_escaped() {
+    recording(|| {
+        trace!(
+            target: metrics::TARGET,
+            metric = "http_request",
+            method = "GET",
+            route = "/escaped/\"quoted\"\\back\nslash",
+            status = 404u64,
+            seconds = 0.01
+        );
+    });
+
+    let exposition = render(&[]);
+
+    assert!(exposition.lines().any(|l| l
+        == "octocompare_http_requests_total{method=\"GET\",route=\"/escaped/\\\"quoted\\\"\\\\back\\nslash\",status=\"404\"} 1"));
+    // Every sample stays on its own line
+    assert!(exposition
+        .lines()
+        .filter(|l| l.contains("/escaped/"))
+        .all(|l| l.starts_with("octocompare_http_request")));
+}
+
+#[test]
+fn other_events_are_left_alone() {
+    recording(|| {
+        trace!(
+            metric = "http_request",
+            method = "GET",
+            route = "/untargeted",
+            status = 200u64
+        );
+        trace!(target: metrics::TARGET, metric = "made_up", route = "/made-up");
+    });
+
+    let exposition = render(&[]);
+
+    assert!(!exposition.contains("/untargeted"));
+    assert!(!exposition.contains("/made-up"));
+    for metric in [
+        "octocompare_http_requests_total",
+        "octocompare_http_request_duration_seconds",
+        "octocompare_octopus_requests_total",
+        "octocompare_octopus_request_duration_seconds",
+        "octocompare_cache_lookups_total",
+    ] {
+        assert!(
+            exposition.contains(&format!("# HELP {} ", metric)),
+            "{}",
+            metric
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{Request, StatusCode},
+    middleware::{from_fn, from_fn_with_state},
+    response::Response,
+    routing::get,
+    Router,
+};
+use octocompare::{
+    config::RouteTimeouts,
+    error::{AppError, UserError},
+    middleware::{enforce_timeout, render_errors},
+};
+use tower::ServiceExt;
+
+/// Routes that go wrong in different ways, layered like the server's.
+fn app(routes: &[(&str, u64)]) -> Router {
+    let timeouts = RouteTimeouts {
+        default: Duration::from_secs(60),
+        routes: routes
+            .iter()
+            .map(|(route, secs)| (route.to_string(), Duration::from_secs(*secs)))
+            .collect::<HashMap<_, _>>(),
+    };
+    Router::new()
+        .route("/fine", get(|| async { "fine" }))
+        .route(
+            "/missing",
+            get(|| async {
+                Err::<(), AppError>(UserError::NotFound("No such report.".to_owned()).into())
+            }),
+        )
+        .route(
+            "/broken",
+            get(|| async { Err::<(), AppError>(anyhow::anyhow!("Disk full").into()) }),
+        )
+        .route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(120)).await;
+                "done"
+            }),
+        )
+        .route_layer(from_fn_with_state(Arc::new(timeouts), enforce_timeout))
+        .layer(from_fn(render_errors))
+}
+
+async fn send(app: Router, uri: &str, headers: &[(&str, &str)]) -> (Response, String) {
+    let mut request = Request::get(uri);
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let response = app
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let (parts, body) = response.into_parts();
+    let body = to_bytes(body, usize::MAX).await.unwrap();
+    (
+        Response::from_parts(parts, Body::empty()),
+        String::from_utf8(body.to_vec()).unwrap(),
+    )
+}
+
+fn header<'a>(response: &'a Response, name: &str) -> Option<&'a str> {
+    response.headers().get(name).map(|v| v.to_str().unwrap())
+}
+
+#[tokio::test]
+async fn errors_get_a_page_with_the_correlation_id() {
+    let cases = [
+        (
+            "/missing",
+            StatusCode::NOT_FOUND,
+            "We couldn't find that",
+            "No such report.",
+        ),
+        (
+            "/broken",
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Something went wrong",
+            "Disk full",
+        ),
+    ];
+    for (uri, status, title, detail) in cases {
+        let (response, body) = send(app(&[]), uri, &[]).await;
+
+        assert_eq!(response.status(), status, "{}", uri);
+        let id = header(&response, "x-request-id").unwrap();
+        assert!(uuid::Uuid::parse_str(id).is_ok(), "{}: {}", uri, id);
+        assert!(
+            body.contains(&format!("<title>OctoCompare | {}</title>", title)),
+            "{}",
+            uri
+        );
+        assert!(body.contains(detail), "{}", uri);
+        assert!(
+            body.contains(&format!("Reference: <code>{}</code>", id)),
+            "{}",
+            uri
+        );
+        assert_eq!(header(&response, "hx-retarget"), None, "{}", uri);
+    }
+}
+
+#[tokio::test]
+async fn htmx_errors_go_to_the_notifications() {
+    let (response, body) = send(app(&[]), "/missing", &[("hx-request", "true")]).await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(header(&response, "hx-retarget"), Some("#notifications"));
+    assert_eq!(header(&response, "hx-reswap"), Some("innerHTML"));
+    // Just the notice, not a whole page
+    assert!(body.starts_with("<div "), "{}", body);
+    assert!(!body.contains("<html"), "{}", body);
+    assert!(body.contains("No such report."));
+}
+
+#[tokio::test]
+async fn the_proxys_request_id_is_kept_if_its_sensible() {
+    let cases = [
+        ("abc-123_XYZ", Some("abc-123_XYZ")),
+        ("has spaces", None),
+        ("<script>", None),
+        (&*"x".repeat(65), None),
+    ];
+    for (given, kept) in cases {
+        let (response, body) = send(app(&[]), "/missing", &[("x-request-id", given)]).await;
+
+        let id = header(&response, "x-request-id").unwrap();
+        match kept {
+            Some(kept) => assert_eq!(id, kept),
+            None => assert!(uuid::Uuid::parse_str(id).is_ok(), "{}: {}", given, id),
+        }
+        assert!(body.contains(&format!("<code>{}</code>", id)), "{}", given);
+    }
+}
+
+#[tokio::test]
+async fn answers_are_left_alone_apart_from_the_request_id() {
+    let (response, body) = send(app(&[]), "/fine", &[("x-request-id", "abc")]).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body, "fine");
+    assert_eq!(header(&response, "x-request-id"), Some("abc"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn slow_requests_time_out() {
+    let (response, body) = send(app(&[]), "/slow", &[("hx-request", "true")]).await;
+
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(header(&response, "hx-retarget"), Some("#notifications"));
+    assert!(body.contains("That took too long"));
+    let id = header(&response, "x-request-id").unwrap();
+    assert!(body.contains(&format!("<code>{}</code>", id)));
+}
+
+#[tokio::test(start_paused = true)]
+async fn routes_can_be_given_longer() {
+    let (response, body) = send(app(&[("/slow", 180)]), "/slow", &[]).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body, "done");
+
+    let (response, _) = send(app(&[("/slow", 90)]), "/slow", &[]).await;
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+}
@@ -0,0 +1,52 @@
+use axum::http::StatusCode;
+use chrono::NaiveDate;
+use octocompare::{
+    error::status_of,
+    profile::{season, synthesise},
+};
+
+fn date(s: &str) -> NaiveDate {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+}
+
+#[test]
+fn seasons_follow_elexons_boundaries() {
+    // 2024: clocks forward 31 March, August bank holiday 26 August, clocks
+    // back 27 October
+    let cases = [
+        ("2024-01-15", "winter"),
+        ("2024-03-30", "winter"),
+        ("2024-03-31", "spring"),
+        ("2024-05-03", "spring"),
+        ("2024-05-04", "summer"),
+        ("2024-07-12", "summer"),
+        ("2024-07-13", "high_summer"),
+        ("2024-08-25", "high_summer"),
+        ("2024-08-26", "autumn"),
+        ("2024-10-26", "autumn"),
+        ("2024-10-27", "winter"),
+        ("2024-12-31", "winter"),
+    ];
+    for (day, expected) in cases {
+        assert_eq!(season(date(day)), expected, "{}", day);
+    }
+}
+
+#[test]
+fn a_year_adds_up_to_the_annual_consumption() {
+    for class in [1, 2] {
+        let readings = synthesise(class, 2900.0, date("2024-01-01"), date("2024-12-30")).unwrap();
+        let total: f64 = readings.iter().map(|d| d.consumption).sum();
+        assert!((total - 2900.0).abs() < 1e-6, "class {}: {}", class, total);
+        assert!(readings[0].interval_start > readings[1].interval_start);
+    }
+}
+
+#[test]
+fn classes_without_a_profile_are_turned_down() {
+    for class in [0, 3, 8] {
+        let error = synthesise(class, 2900.0, date("2024-01-01"), date("2024-01-31")).unwrap_err();
+        assert_eq!(status_of(&error), StatusCode::NOT_FOUND);
+        assert!(error.to_string().contains(&format!("class {}", class)));
+    }
+}
@@ -0,0 +1,143 @@
+use chrono::{DateTime, Duration, Utc};
+use octocompare::{
+    api::ConsumptionDatum,
+    quality::{check, fill_gaps},
+};
+
+fn utc(s: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+}
+
+/// Half-hourly readings from `from`, one per value, newest first like the API.
+/// `None` leaves that half hour out.
+fn readings(from: &str, values: &[Option<f64>]) -> Vec<ConsumptionDatum> {
+    let from = utc(from);
+    let mut results: Vec<ConsumptionDatum> = values
+        .iter()
+        .enumerate()
+        .filter_map(|(i, value)| {
+            let start = from + Duration::minutes(30 * i as i64);
+            Some(ConsumptionDatum {
+                consumption: (*value)?,
+                interval_start: start,
+                interval_end: start + Duration::minutes(30),
+            })
+        })
+        .collect();
+    results.reverse();
+    results
+}
+
+#[test]
+fn finds_gaps_between_readings() {
+    let consumption = readings(
+        "2024-01-01T00:00:00Z",
+        &[Some(1.0), None, None, Some(1.0), None, Some(1.0)],
+    );
+    let quality = check(&consumption, 0);
+
+    assert_eq!(quality.expected, 6);
+    assert_eq!(quality.readings, 3);
+    assert_eq!(quality.gaps.len(), 2);
+    assert_eq!(quality.gaps[0].from, utc("2024-01-01T00:30:00Z"));
+    assert_eq!(quality.gaps[0].to, utc("2024-01-01T01:30:00Z"));
+    assert_eq!(quality.gaps[0].slots, 2);
+    assert_eq!(quality.missing(), 3);
+    assert!(!quality.is_clean());
+}
+
+#[test]
+fn fills_short_gaps_in_a_straight_line() {
+    let mut consumption = readings("2024-01-01T00:00:00Z", &[Some(1.0), None, None, Some(2.5)]);
+    let quality = check(&consumption, 0);
+    let filled = fill_gaps(&mut consumption, &quality.gaps);
+
+    assert_eq!(filled, 2);
+    let values: Vec<f64> = consumption.iter().rev().map(|d| d.consumption).collect();
+    assert_eq!(values, vec![1.0, 1.5, 2.0, 2.5]);
+    // Still newest first, with nothing missing
+    assert!(consumption
+        .windows(2)
+        .all(|w| w[0].interval_start == w[1].interval_end));
+}
+
+#[test]
+fn leaves_gaps_longer_than_a_day() {
+    let mut values = vec![Some(1.0)];
+    values.extend(std::iter::repeat_n(None, 49));
+    values.push(Some(1.0));
+    let mut consumption = readings("2024-01-01T00:00:00Z", &values);
+    let quality = check(&consumption, 0);
+
+    assert_eq!(quality.gaps[0].slots, 49);
+    assert_eq!(fill_gaps(&mut consumption, &quality.gaps), 0);
+    assert_eq!(consumption.len(), 2);
+}
+
+#[test]
+fn fills_a_whole_day_gap() {
+    let mut values = vec![Some(1.0)];
+    values.extend(std::iter::repeat_n(None, 48));
+    values.push(Some(1.0));
+    let mut consumption = readings("2024-01-01T00:00:00Z", &values);
+    let quality = check(&consumption, 0);
+
+    assert_eq!(fill_gaps(&mut consumption, &quality.gaps), 48);
+    assert_eq!(consumption.len(), 50);
+}
+
+#[test]
+fn twelve_zeros_in_a_row_are_a_zero_run() {
+    let mut values = vec![Some(0.5)];
+    values.extend(std::iter::repeat_n(Some(0.0), 12));
+    values.push(Some(0.5));
+    let quality = check(&readings("2024-01-01T00:00:00Z", &values), 0);
+
+    assert_eq!(quality.zero_runs.len(), 1);
+    assert_eq!(quality.zero_runs[0].slots, 12);
+    assert_eq!(quality.zero_runs[0].from, utc("2024-01-01T00:30:00Z"));
+    assert_eq!(quality.zero_runs[0].to, utc("2024-01-01T06:30:00Z"));
+}
+
+#[test]
+fn fewer_than_twelve_zeros_are_fine() {
+    let mut values = vec![Some(0.5)];
+    values.extend(std::iter::repeat_n(Some(0.0), 11));
+    values.push(Some(0.5));
+    let quality = check(&readings("2024-01-01T00:00:00Z", &values), 0);
+
+    assert!(quality.zero_runs.is_empty());
+    assert!(quality.is_clean());
+}
+
+#[test]
+fn zero_run_at_the_end_is_still_found() {
+    let mut values = vec![Some(0.5)];
+    values.extend(std::iter::repeat_n(Some(0.0), 20));
+    let quality = check(&readings("2024-01-01T00:00:00Z", &values), 0);
+
+    assert_eq!(quality.zero_runs.len(), 1);
+    assert_eq!(quality.zero_runs[0].slots, 20);
+}
+
+#[test]
+fn flags_negative_and_impossible_readings() {
+    let quality = check(
+        &readings(
+            "2024-01-01T00:00:00Z",
+            &[Some(1.0), Some(-0.1), Some(12.0), Some(11.5)],
+        ),
+        2,
+    );
+
+    assert_eq!(quality.spikes.len(), 2);
+    assert_eq!(quality.duplicates, 2);
+}
+
+#[test]
+fn no_readings_no_problems() {
+    let quality = check(&[], 0);
+
+    assert_eq!(quality.expected, 0);
+    assert!(quality.is_clean());
+}
@@ -0,0 +1,245 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use octocompare::{
+    api::AccountProperty,
+    compare::{Comparison, TariffCost},
+    kraken::{Bill, BillTotal},
+    pricing::DailyCost,
+    reconcile::{billed_periods, parse_statement, reconcile, BilledPeriod, ReconciledPeriod},
+};
+use serde_json::json;
+
+fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(y, m, d).unwrap()
+}
+
+fn bill(from: Option<NaiveDate>, to: Option<NaiveDate>, gross_total: Option<i64>) -> Bill {
+    Bill {
+        id: "1".to_owned(),
+        bill_type: Some("STATEMENT".to_owned()),
+        from_date: from,
+        to_date: to,
+        issued_date: to,
+        total_charges: Some(BillTotal {
+            net_total: gross_total.map(|t| t * 20 / 21),
+            gross_total,
+        }),
+        total_credits: None,
+    }
+}
+
+#[test]
+fn billed_periods_need_dates_and_charges() {
+    let (jan, feb) = (date(2024, 1, 1), date(2024, 1, 31));
+    let cases = [
+        ("statement", bill(Some(jan), Some(feb), Some(8400)), true),
+        ("one day", bill(Some(jan), Some(jan), Some(300)), true),
+        ("no start", bill(None, Some(feb), Some(8400)), false),
+        ("no end", bill(Some(jan), None, Some(8400)), false),
+        ("no gross total", bill(Some(jan), Some(feb), None), false),
+        (
+            "no charges",
+            Bill {
+                total_charges: None,
+                ..bill(Some(jan), Some(feb), Some(8400))
+            },
+            false,
+        ),
+        ("backwards", bill(Some(feb), Some(jan), Some(8400)), false),
+    ];
+    for (name, bill, kept) in cases {
+        let periods = billed_periods(std::slice::from_ref(&bill));
+        assert_eq!(periods.len(), kept as usize, "{}", name);
+        if let Some(period) = periods.first() {
+            assert_eq!(period.from, bill.from_date.unwrap(), "{}", name);
+            assert_eq!(period.to, bill.to_date.unwrap(), "{}", name);
+            let gross = bill.total_charges.unwrap().gross_total.unwrap();
+            assert_eq!(period.amount, gross as f64, "{}", name);
+        }
+    }
+}
+
+#[test]
+fn reads_amounts_with_thousands_separators() {
+    let csv = r#"Amount,From,To
+"£1,234.56",2024-01-01,31/01/2024
+-£5.00,2024-02-01,2024-02-29
+"#;
+    let periods = parse_statement(csv).unwrap();
+    assert_eq!(periods.len(), 2);
+    assert_eq!(periods[0].from, date(2024, 1, 1));
+    assert_eq!(periods[0].to, date(2024, 1, 31));
+    assert!((periods[0].amount - 123456.0).abs() < 1e-6);
+    assert!((periods[1].amount + 500.0).abs() < 1e-6);
+}
+
+#[test]
+fn reads_statements_without_a_header() {
+    let periods = parse_statement("2024-01-01,2024-01-31,\"£80.00\"").unwrap();
+    assert_eq!(periods[0].days(), 31);
+    assert!((periods[0].amount - 8000.0).abs() < 1e-6);
+}
+
+#[test]
+fn explains_what_is_wrong_with_a_statement() {
+    let cases = [
+        (
+            "from,to\n2024-01-01,2024-01-31",
+            "missing its amount column",
+        ),
+        (
+            "2024-01-01,,12",
+            "Line 1 of the statement doesn't have a from and to date",
+        ),
+        (
+            "from,to,amount\n2024-01-01,2024-01-31,lots",
+            "Line 1 of the statement doesn't have an amount",
+        ),
+        (
+            "2024-01-01,2024-01-31,1\n2024-02-01,2024-01-31,1",
+            "Line 2 of the statement ends before it starts",
+        ),
+        ("from,to,amount\n", "There weren't any billing periods"),
+    ];
+    for (csv, expected) in cases {
+        let error = parse_statement(csv).unwrap_err().to_string();
+        assert!(error.contains(expected), "{:?}: {}", csv, error);
+    }
+}
+
+fn period(billed: f64, computed: f64, gas: Option<f64>) -> ReconciledPeriod {
+    ReconciledPeriod {
+        billed: BilledPeriod {
+            from: date(2024, 1, 1),
+            to: date(2024, 1, 31),
+            amount: billed,
+        },
+        tariff_code: Some("E-1R-AGILE-24-10-01-C".to_owned()),
+        display_name: Some("Agile Octopus".to_owned()),
+        computed: Some(computed),
+        days_missing: 0,
+        includes_gas: gas.is_some(),
+        gas,
+    }
+}
+
+#[test]
+fn discrepancy_is_against_the_bill() {
+    let d = period(10000.0, 11000.0, None).discrepancy().unwrap();
+    assert!((d - 10.0).abs() < 1e-9);
+    assert_eq!(period(0.0, 11000.0, None).discrepancy(), None);
+}
+
+#[test]
+fn bills_with_gas_are_set_against_the_combined_cost() {
+    let d = period(25000.0, 24000.0, Some(13000.0))
+        .discrepancy()
+        .unwrap();
+    assert!((d + 4.0).abs() < 1e-9);
+}
+
+fn property() -> AccountProperty {
+    let agreement = |code: &str| {
+        json!({
+            "tariff_code": code,
+            "valid_from": "2023-06-01T00:00:00Z",
+            "valid_to": "2025-06-01T00:00:00Z",
+        })
+    };
+    serde_json::from_value(json!({
+        "id": 1,
+        "moved_in_at": "2023-06-01T00:00:00Z",
+        "moved_out_at": null,
+        "address_line_1": "1 High Street",
+        "address_line_2": "",
+        "address_line_3": "",
+        "town": "Bristol",
+        "county": "",
+        "postcode": "BS1 1AA",
+        "electricity_meter_points": [{
+            "mpan": "1900000000001",
+            "profile_class": 1,
+            "consumption_standard": 2900,
+            "agreements": [agreement("E-1R-AGILE-24-10-01-C")],
+            "is_export": false,
+            "meters": [{ "serial_number": "E1" }],
+        }],
+        "gas_meter_points": [{
+            "mprn": "1000000001",
+            "consumption_standard": 12000,
+            "agreements": [agreement("G-1R-VAR-22-11-01-C")],
+            "meters": [{ "serial_number": "G1" }],
+        }],
+    }))
+    .unwrap()
+}
+
+/// Agile costing 100p a day from the 1st to the 3rd of January 2024.
+fn comparison() -> Comparison {
+    let days = (1..=3)
+        .map(|d| DailyCost {
+            slots: 48,
+            unit_cost: 60.0,
+            standing_cost: 40.0,
+            ..DailyCost::new(date(2024, 1, d))
+        })
+        .collect();
+    Comparison {
+        address: "1 High Street, BS1 1AA".to_owned(),
+        tariffs: vec![TariffCost {
+            tariff_code: "E-1R-AGILE-24-10-01-C".to_owned(),
+            display_name: "Agile Octopus".to_owned(),
+            supplier: None,
+            exit_fee: None,
+            switching_fee: 0.0,
+            is_current: true,
+            from: None,
+            to: None,
+            unit_cost: 180.0,
+            standing_cost: 120.0,
+            data_missing: false,
+            days,
+            ev_energy: 0.0,
+            dispatches: 0,
+        }],
+        meter_points: vec![],
+        estimated: false,
+        moved_out_at: None,
+        unavailable: vec![],
+    }
+}
+
+#[test]
+fn gas_is_added_in_when_the_bill_covers_it() {
+    let billed = BilledPeriod {
+        from: date(2024, 1, 1),
+        to: date(2024, 1, 3),
+        amount: 500.0,
+    };
+    let every_day: BTreeMap<NaiveDate, f64> = (1..=3).map(|d| (date(2024, 1, d), 50.0)).collect();
+    let mut missing_a_day = every_day.clone();
+    missing_a_day.remove(&date(2024, 1, 2));
+
+    let cases = [
+        (None, Some(300.0), None, 0),
+        (Some(&every_day), Some(450.0), Some(150.0), 0),
+        (Some(&missing_a_day), None, None, 1),
+    ];
+    for (gas, computed, gas_cost, days_missing) in cases {
+        let periods = reconcile(
+            &comparison(),
+            &property(),
+            std::slice::from_ref(&billed),
+            gas,
+        );
+
+        assert_eq!(periods.len(), 1);
+        let period = &periods[0];
+        assert_eq!(period.tariff_code.as_deref(), Some("E-1R-AGILE-24-10-01-C"));
+        assert_eq!(period.computed, computed, "{:?}", gas);
+        assert_eq!(period.gas, gas_cost, "{:?}", gas);
+        assert_eq!(period.days_missing, days_missing, "{:?}", gas);
+        assert_eq!(period.includes_gas, gas_cost.is_some(), "{:?}", gas);
+    }
+}
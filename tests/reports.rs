@@ -0,0 +1,136 @@
+use std::path::PathBuf;
+
+use axum::http::{header, HeaderMap, HeaderValue};
+use octocompare::{
+    compare::Comparison,
+    reports::{saved_reports, ReportStore},
+};
+
+fn dir() -> PathBuf {
+    std::env::temp_dir().join(format!("octocompare-{}", uuid::Uuid::new_v4()))
+}
+
+fn store() -> ReportStore {
+    ReportStore::new(dir())
+}
+
+fn comparison() -> Comparison {
+    Comparison {
+        address: "1 High Street, BS1 1AA".to_owned(),
+        tariffs: vec![],
+        meter_points: vec![],
+        estimated: false,
+        moved_out_at: None,
+        unavailable: vec![],
+    }
+}
+
+fn cookies(values: &[&[u8]]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for value in values {
+        headers.append(header::COOKIE, HeaderValue::from_bytes(value).unwrap());
+    }
+    headers
+}
+
+#[tokio::test]
+async fn only_the_delete_token_deletes_a_report() {
+    let store = store();
+    let report = store.save(comparison()).await.unwrap();
+
+    let cases = ["", "not-the-token", &report.id];
+    for token in cases {
+        assert!(
+            !store.delete(&report.id, token).await.unwrap(),
+            "{:?}",
+            token
+        );
+        assert!(store.load(&report.id).await.unwrap().is_some());
+    }
+
+    assert!(store
+        .delete(&report.id, &report.delete_token)
+        .await
+        .unwrap());
+    assert!(store.load(&report.id).await.unwrap().is_none());
+    // Already gone
+    assert!(!store
+        .delete(&report.id, &report.delete_token)
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+async fn reports_saved_without_a_token_cant_be_deleted() {
+    let dir = dir();
+    let store = ReportStore::new(&dir);
+    let mut report = store.save(comparison()).await.unwrap();
+    // Saved before reports had delete tokens
+    report.delete_token = String::new();
+    std::fs::write(
+        dir.join(format!("{}.json", report.id)),
+        serde_json::to_vec(&report).unwrap(),
+    )
+    .unwrap();
+
+    assert!(!store.delete(&report.id, "").await.unwrap());
+    assert!(store.load(&report.id).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn deleting_a_report_that_isnt_there_deletes_nothing() {
+    let store = store();
+    let cases = ["9b2c0d5e-7a5b-4d7f-9d6a-0c1e2f3a4b5c", "../reports", ""];
+    for id in cases {
+        assert!(!store.delete(id, "token").await.unwrap(), "{:?}", id);
+    }
+}
+
+#[test]
+fn saved_reports_are_read_from_the_cookies() {
+    let headers = cookies(&[
+        b"theme=dark; report_abc=token-1",
+        b"report_def=token-2;report_ghi=to=ken",
+    ]);
+
+    let mut saved: Vec<_> = saved_reports(&headers).into_iter().collect();
+    saved.sort();
+
+    assert_eq!(
+        saved,
+        [
+            ("abc".to_owned(), "token-1".to_owned()),
+            ("def".to_owned(), "token-2".to_owned()),
+            ("ghi".to_owned(), "to=ken".to_owned()),
+        ]
+    );
+}
+
+#[test]
+fn malformed_report_cookies_are_skipped() {
+    let cases: [&[u8]; 7] = [
+        b"",
+        b";;",
+        b"report_abc",
+        b"report_=token",
+        b"report_abc=",
+        b"reports=abc",
+        b"report_abc=\xfftoken",
+    ];
+    for case in cases {
+        assert!(
+            saved_reports(&cookies(&[case])).is_empty(),
+            "{:?}",
+            String::from_utf8_lossy(case)
+        );
+    }
+
+    // A bad header doesn't lose the good ones
+    let headers = cookies(&[b"report_abc=\xfftoken", b"report_def=token"]);
+    assert_eq!(saved_reports(&headers).len(), 1);
+}
+
+#[test]
+fn no_cookies_no_reports() {
+    assert!(saved_reports(&HeaderMap::new()).is_empty());
+}
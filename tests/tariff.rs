@@ -0,0 +1,229 @@
+use axum::http::StatusCode;
+use chrono::{DateTime, NaiveDate, Utc};
+use octocompare::{api::TariffPricing, error::status_of, tariff::TariffDefinition};
+
+fn utc(s: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+}
+
+fn date(s: &str) -> NaiveDate {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+}
+
+/// A tariff with a 53p standing charge and the given bands.
+fn tariff(bands: &str) -> String {
+    format!(
+        r#"
+code = "TEST"
+name = "Test"
+
+[[standing_charges]]
+value = 53.0
+
+{bands}
+"#
+    )
+}
+
+/// The unit rate for the half hour starting at `at`.
+fn rate_at(pricing: &TariffPricing, at: &str) -> Option<f64> {
+    let at = utc(at);
+    pricing
+        .unit_charges
+        .iter()
+        .find(|r| r.valid_from <= at && r.valid_to.is_none_or(|to| at < to))
+        .map(|r| r.value_inc_vat)
+}
+
+fn economy_7() -> TariffDefinition {
+    TariffDefinition::parse(&tariff(
+        r#"
+[[bands]]
+name = "Night"
+rate = 14.5
+times = ["23:30-06:30"]
+
+[[bands]]
+name = "Day"
+rate = 30.1
+"#,
+    ))
+    .unwrap()
+}
+
+#[test]
+fn overnight_band_wraps_past_midnight() {
+    let pricing = economy_7()
+        .compile(date("2024-01-15"), date("2024-01-15"))
+        .unwrap();
+
+    assert_eq!(rate_at(&pricing, "2024-01-15T00:00:00Z"), Some(14.5));
+    assert_eq!(rate_at(&pricing, "2024-01-15T06:00:00Z"), Some(14.5));
+    assert_eq!(rate_at(&pricing, "2024-01-15T06:30:00Z"), Some(30.1));
+    assert_eq!(rate_at(&pricing, "2024-01-15T23:00:00Z"), Some(30.1));
+    assert_eq!(rate_at(&pricing, "2024-01-15T23:30:00Z"), Some(14.5));
+}
+
+#[test]
+fn band_times_are_uk_local_time() {
+    // 23:30 BST is 22:30 UTC
+    let pricing = economy_7()
+        .compile(date("2024-07-15"), date("2024-07-15"))
+        .unwrap();
+
+    assert_eq!(rate_at(&pricing, "2024-07-14T23:00:00Z"), Some(14.5));
+    assert_eq!(rate_at(&pricing, "2024-07-15T05:00:00Z"), Some(14.5));
+    assert_eq!(rate_at(&pricing, "2024-07-15T05:30:00Z"), Some(30.1));
+    assert_eq!(rate_at(&pricing, "2024-07-15T22:30:00Z"), Some(14.5));
+}
+
+#[test]
+fn first_matching_band_wins_where_bands_overlap() {
+    let definition = TariffDefinition::parse(&tariff(
+        r#"
+[[bands]]
+name = "Peak"
+rate = 40.0
+times = ["16:00-19:00"]
+weekdays = ["Mon", "Tue", "Wed", "Thu", "Fri"]
+
+[[bands]]
+name = "Afternoon"
+rate = 20.0
+times = ["12:00-24:00"]
+
+[[bands]]
+name = "Standard"
+rate = 25.0
+"#,
+    ))
+    .unwrap();
+    // A Friday then a Saturday
+    let pricing = definition
+        .compile(date("2024-01-19"), date("2024-01-20"))
+        .unwrap();
+
+    assert_eq!(rate_at(&pricing, "2024-01-19T11:30:00Z"), Some(25.0));
+    assert_eq!(rate_at(&pricing, "2024-01-19T17:00:00Z"), Some(40.0));
+    assert_eq!(rate_at(&pricing, "2024-01-19T23:30:00Z"), Some(20.0));
+    assert_eq!(rate_at(&pricing, "2024-01-20T17:00:00Z"), Some(20.0));
+}
+
+#[test]
+fn half_hours_no_band_covers_are_left_unpriced() {
+    let definition = TariffDefinition::parse(&tariff(
+        r#"
+[[bands]]
+name = "Winter"
+rate = 30.0
+months = [1, 2, 12]
+"#,
+    ))
+    .unwrap();
+    let pricing = definition
+        .compile(date("2024-02-29"), date("2024-03-01"))
+        .unwrap();
+
+    assert_eq!(rate_at(&pricing, "2024-02-29T12:00:00Z"), Some(30.0));
+    assert_eq!(rate_at(&pricing, "2024-03-01T12:00:00Z"), None);
+}
+
+#[test]
+fn flat_rates_are_merged_and_newest_first() {
+    let pricing = economy_7()
+        .compile(date("2024-01-15"), date("2024-01-16"))
+        .unwrap();
+
+    // Night, day, night (wrapping into the next day), day, night
+    assert_eq!(pricing.unit_charges.len(), 5);
+    assert!(pricing.unit_charges[0].valid_from > pricing.unit_charges[1].valid_from);
+    assert_eq!(pricing.standing_charges.len(), 1);
+    assert_eq!(
+        pricing.standing_charges[0].valid_to,
+        Some(utc("2024-01-17T00:00:00Z"))
+    );
+}
+
+#[test]
+fn midnight_can_end_a_range() {
+    let definition = TariffDefinition::parse(&tariff(
+        r#"
+[[bands]]
+name = "Evening"
+rate = 10.0
+times = ["20:00-24:00"]
+
+[[bands]]
+name = "Day"
+rate = 30.0
+"#,
+    ))
+    .unwrap();
+    let pricing = definition
+        .compile(date("2024-01-15"), date("2024-01-15"))
+        .unwrap();
+
+    assert_eq!(rate_at(&pricing, "2024-01-15T23:30:00Z"), Some(10.0));
+    assert_eq!(rate_at(&pricing, "2024-01-15T00:00:00Z"), Some(30.0));
+}
+
+#[test]
+fn bad_bands_are_rejected() {
+    let cases = [
+        (r#"times = ["7:75-08:00"]"#, "7:75"),
+        (r#"times = ["24:30-02:00"]"#, "24:30"),
+        (r#"times = ["25:00-02:00"]"#, "25:00"),
+        (r#"times = ["07:00-nine"]"#, "nine"),
+        (r#"times = ["07:00"]"#, "HH:MM-HH:MM range"),
+        (
+            r#"times = ["07:00-07:00"]"#,
+            "starts and ends at the same time",
+        ),
+        (
+            r#"times = ["00:00-00:00"]"#,
+            "starts and ends at the same time",
+        ),
+        (r#"times = ["24:00-07:00"]"#, "can't start at 24:00"),
+        (r#"times = ["24:00-24:00"]"#, "can't start at 24:00"),
+        (r#"weekdays = ["Funday"]"#, "Funday"),
+        (r#"months = [13]"#, "13 isn't a month"),
+        (r#"valid_from = "2024-13-01""#, "2024-13-01"),
+    ];
+    for (field, expected) in cases {
+        let source = tariff(&format!(
+            "[[bands]]\nname = \"Broken\"\nrate = 20.0\n{}\n",
+            field
+        ));
+        let error = TariffDefinition::parse(&source).unwrap_err();
+        assert_eq!(status_of(&error), StatusCode::BAD_REQUEST, "{}", field);
+        let error = format!("{:#}", error);
+        assert!(
+            error.contains("Band Broken in tariff TEST") && error.contains(expected),
+            "{}: {}",
+            field,
+            error
+        );
+    }
+}
+
+#[test]
+fn a_tariff_needs_bands() {
+    let source = "code = \"TEST\"\nname = \"Test\"\nstanding_charges = []\nbands = []\n";
+    let error = TariffDefinition::parse(source).unwrap_err();
+    assert!(error.to_string().contains("doesn't have any rate bands"));
+}
+
+#[test]
+fn definitions_can_be_json() {
+    let definition = TariffDefinition::parse(
+        r#"{"code": "FLAT", "name": "Flat", "standing_charges": [{"value": 50.0}],
+            "bands": [{"name": "All day", "rate": 24.5}]}"#,
+    )
+    .unwrap();
+    let pricing = definition
+        .compile(date("2024-01-15"), date("2024-01-15"))
+        .unwrap();
+
+    assert_eq!(pricing.unit_charges.len(), 1);
+    assert_eq!(rate_at(&pricing, "2024-01-15T12:00:00Z"), Some(24.5));
+}
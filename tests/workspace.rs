@@ -0,0 +1,194 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use axum::{extract::Path, http::StatusCode, routing::get, Json, Router};
+use chrono::{DateTime, Utc};
+use octocompare::{
+    api::ClientSettings,
+    compare::{CompareRequest, Comparison, TariffCost},
+    workspace::{batch_totals, compare_batch, BatchTotals, PropertyOutcome},
+};
+use serde_json::{json, Value};
+
+fn utc(s: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+}
+
+fn property(id: u32) -> Value {
+    json!({
+        "id": id,
+        "moved_in_at": "2023-01-01T00:00:00Z",
+        "moved_out_at": null,
+        "address_line_1": format!("{} High Street", id),
+        "address_line_2": "",
+        "address_line_3": "",
+        "town": "Bristol",
+        "county": "",
+        "postcode": "BS1 1AA",
+        "electricity_meter_points": [],
+        "gas_meter_points": [],
+    })
+}
+
+/// An Octopus stand-in where account A-1 has property 1 and every other
+/// account is turned away. Counts the account lookups.
+async fn octopus() -> (String, Arc<AtomicUsize>) {
+    let fetches = Arc::new(AtomicUsize::new(0));
+    let counter = fetches.clone();
+    let app = Router::new().route(
+        "/accounts/:number",
+        get(move |Path(number): Path<String>| async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+            if number == "A-1" {
+                Ok(Json(
+                    json!({ "number": number, "properties": [property(1)] }),
+                ))
+            } else {
+                Err(StatusCode::UNAUTHORIZED)
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (base_url, fetches)
+}
+
+fn request(account_number: &str, property_id: f64) -> CompareRequest {
+    CompareRequest {
+        api_key: "sk_test".to_owned(),
+        account_number: account_number.to_owned(),
+        property_id,
+        ev: None,
+        definitions: vec![],
+        fill_gaps: false,
+        products: vec![],
+    }
+}
+
+#[tokio::test]
+async fn a_failing_property_doesnt_lose_the_rest_of_the_batch() {
+    let (base_url, fetches) = octopus().await;
+    let client = ClientSettings {
+        base_url,
+        ..Default::default()
+    }
+    .client();
+
+    let outcomes = compare_batch(
+        &client,
+        vec![
+            request("A-1", 1.0),
+            request("B-2", 3.0),
+            request("A-1", 2.0),
+        ],
+        &(),
+    )
+    .await
+    .unwrap();
+
+    let summary: Vec<_> = outcomes
+        .iter()
+        .map(|o| (o.account_number.as_str(), o.property_id, o.result.is_ok()))
+        .collect();
+    assert_eq!(
+        summary,
+        [("A-1", 1.0, true), ("B-2", 3.0, false), ("A-1", 2.0, false)]
+    );
+    assert_eq!(
+        outcomes[0].result.as_ref().unwrap().address,
+        "1 High Street, BS1 1AA"
+    );
+    assert_eq!(
+        outcomes[1].result.as_ref().unwrap_err(),
+        "Octopus didn't recognise that account number and API key."
+    );
+    // A-1 is only looked up once for its two properties
+    assert_eq!(fetches.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn an_empty_batch_compares_nothing() {
+    let outcomes = compare_batch(&ClientSettings::default().client(), vec![], &())
+        .await
+        .unwrap();
+    assert!(outcomes.is_empty());
+}
+
+fn cost(code: &str, is_current: bool, unit_cost: f64) -> TariffCost {
+    TariffCost {
+        tariff_code: code.to_owned(),
+        display_name: code.to_lowercase(),
+        supplier: None,
+        exit_fee: None,
+        switching_fee: 0.0,
+        is_current,
+        from: Some(utc("2024-01-01T00:00:00Z")),
+        to: Some(utc("2024-01-03T00:00:00Z")),
+        unit_cost,
+        standing_cost: 100.0,
+        data_missing: false,
+        days: vec![],
+        ev_energy: 0.0,
+        dispatches: 0,
+    }
+}
+
+fn outcome(result: Result<Vec<TariffCost>, &str>) -> PropertyOutcome {
+    PropertyOutcome {
+        account_number: "A-1".to_owned(),
+        property_id: 1.0,
+        result: result
+            .map(|tariffs| Comparison {
+                address: "1 High Street, BS1 1AA".to_owned(),
+                tariffs,
+                meter_points: vec![],
+                estimated: false,
+                moved_out_at: None,
+                unavailable: vec![],
+            })
+            .map_err(|e| e.to_owned()),
+        report: None,
+    }
+}
+
+#[test]
+fn totals_only_count_properties_with_a_current_and_cheapest_tariff() {
+    let outcomes = [
+        outcome(Ok(vec![
+            cost("CURRENT", true, 900.0),
+            cost("AGILE", false, 400.0),
+        ])),
+        // No current tariff, so nothing to save against
+        outcome(Ok(vec![cost("AGILE", false, 300.0)])),
+        outcome(Ok(vec![])),
+        outcome(Err(
+            "Octopus didn't recognise that account number and API key.",
+        )),
+        outcome(Ok(vec![
+            cost("CURRENT", true, 200.0),
+            cost("FLEX", false, 250.0),
+        ])),
+    ];
+
+    let totals = batch_totals(&outcomes);
+
+    assert_eq!(
+        totals,
+        BatchTotals {
+            current: 1000.0 + 300.0,
+            cheapest: 500.0 + 300.0,
+            properties: 2,
+        }
+    );
+    assert_eq!(totals.saving(), 500.0);
+}
+
+#[test]
+fn totals_for_a_batch_that_all_failed_are_zero() {
+    let totals = batch_totals(&[outcome(Err("Octopus is down."))]);
+    assert_eq!(totals, BatchTotals::default());
+    assert_eq!(totals.saving(), 0.0);
+}